@@ -34,7 +34,7 @@ fn main() {
 
     info!("Running client connected to {}", address);
 
-    let mut stream = connection.get_stream(2);
+    let mut stream = connection.get_stream(1);
 
     let mut stdin = stdin();
     let mut stdout = stdout();
@@ -46,7 +46,7 @@ fn main() {
             break;
         }
 
-        stream.write(&mut buf[..size]).unwrap();
+        stream.write_all(&buf[..size]).unwrap();
 
         let size = stream.read(&mut buf).unwrap();
 
@@ -45,7 +45,7 @@ fn main() {
         };
         info!("Got a connection");
 
-        let mut stream = connection.get_stream(2);
+        let mut stream = connection.get_stream(1);
 
         let mut buf = vec![0; 256];
         loop {
@@ -55,7 +55,7 @@ fn main() {
                 break;
             }
 
-            stream.write(&mut buf[..size]).unwrap();
+            stream.write_all(&buf[..size]).unwrap();
         }
     }
 }
@@ -35,11 +35,11 @@ fn main() {
 
     info!("Running client connected to {}", address);
 
-    let mut stream = connection.get_stream(2);
+    let mut stream = connection.open_stream().unwrap();
 
     info!("Requesting the file");
     stream.write_all(filename.as_bytes()).unwrap();
-    stream.finalize();
+    stream.finalize().unwrap();
 
     std::io::copy(&mut stream, &mut std::io::stdout()).unwrap();
 }
@@ -7,8 +7,9 @@ extern crate mig;
 use std::fs::File;
 use std::io::{Read};
 use std::str;
+use std::thread;
 
-use mig::quic::threaded::{QuicListener};
+use mig::quic::threaded::{QuicConnection, QuicListener, QuicStream};
 
 
 fn main() {
@@ -47,26 +48,38 @@ fn main() {
         };
         info!("Got a connection");
 
-        let mut stream = connection.get_stream(2);
+        let filedir = filedir.clone();
+        thread::spawn(move || handle_connection(connection, filedir));
+    }
+}
 
-        let mut buf = vec![];
-        let amt = stream.read_to_end(&mut buf).unwrap();
-        let buf = &buf[..amt];
+/// Serve a file for every stream the client opens
+fn handle_connection(connection: QuicConnection, filedir: String) {
+    while let Ok(stream) = connection.accept_stream() {
+        let filedir = filedir.clone();
+        thread::spawn(move || send_file(stream, filedir));
+    }
+    info!("Connection closed");
+}
 
-        let filename = str::from_utf8(&buf).unwrap();
-        info!("Requested filename: {}", filename);
+fn send_file(mut stream: QuicStream, filedir: String) {
+    let mut buf = vec![];
+    let amt = stream.read_to_end(&mut buf).unwrap();
+    let buf = &buf[..amt];
 
-        let filepath = format!("{}/{}", filedir, filename);
-        let mut requested_file = match File::open(filepath) {
-            Ok(file) => file,
-            Err(ref e) => {
-                error!("Error opening file: {:?}", e);
-                continue;
-            }
-        };
+    let filename = str::from_utf8(&buf).unwrap();
+    info!("Requested filename on stream {}: {}", stream.id(), filename);
 
-        std::io::copy(&mut requested_file, &mut stream).unwrap();
-        info!("File sent");
-    }
+    let filepath = format!("{}/{}", filedir, filename);
+    let mut requested_file = match File::open(filepath) {
+        Ok(file) => file,
+        Err(ref e) => {
+            error!("Error opening file: {:?}", e);
+            return;
+        }
+    };
+
+    std::io::copy(&mut requested_file, &mut stream).unwrap();
+    info!("File sent");
 }
 
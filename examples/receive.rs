@@ -41,9 +41,9 @@ fn main() {
     };
     info!("Got a connection");
 
-    let mut stream = connection.get_stream(2);
+    let mut stream = connection.get_stream(1);
 
-    info!("Got stream 2. Copying from the stream to stdout.");
+    info!("Got stream 1. Copying from the stream to stdout.");
 
     std::io::copy(&mut stream, &mut std::io::stdout()).unwrap();
 
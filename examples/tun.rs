@@ -12,7 +12,6 @@ use mig::quic::threaded::{QuicConnection,QuicListener};
 
 use tun::{Device as TunDevice};
 
-use std::sync::Arc;
 use std::os::unix::io::{FromRawFd,AsRawFd};
 
 
@@ -82,7 +81,7 @@ fn main() {
     
     let mut dev = tun::create(tunname).unwrap();
     
-    let connection = Arc::new(get_connection(mode, &*address));
+    let connection = get_connection(mode, &*address);
  
     info!("Set the IP address of {} youself", dev.name());
     match mode {
@@ -110,9 +109,6 @@ fn main() {
     
     info!("Serving");
     
-    
-    let connection_copy = connection.clone();
-    
     // Hack:
     let dev_copy = unsafe { ::std::fs::File::from_raw_fd(dev.as_raw_fd()) };
   
@@ -121,8 +117,9 @@ fn main() {
         Mode::Listen  => (3,2),
     };
     
+    // streams keep the connection open and can be moved to other threads
+    let mut stream2 = connection.get_stream(stream_n_1);
     ::std::thread::spawn(move || {
-        let mut stream2 = connection_copy.get_stream(stream_n_1);
         let mut my_dev_copy = dev_copy;
         
         loop {
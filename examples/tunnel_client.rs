@@ -4,7 +4,6 @@ extern crate env_logger;
 
 extern crate mig;
 
-use std::sync::{Arc};
 use std::io::{Read, Write};
 use std::net::TcpListener;
 use std::str;
@@ -50,29 +49,28 @@ fn main() {
         let mut connection_worker = connection.try_clone().unwrap();
         info!("Got a connection");
 
-        let quic_own = Arc::new(QuicConnection::new(target.clone()).unwrap());
-        let quic_writer = quic_own.clone();
+        let quic_own = QuicConnection::new(target.clone()).unwrap();
 
+        // the client sends on its own stream 1, the server replies on its stream 2
         let mut stream_own = quic_own.get_stream(2);
+        let mut quic_writer = quic_own.get_stream(1);
 
         let target_writer = thread::spawn(move || {
-            let mut quic_writer = quic_writer.get_stream(2);
-
             let mut buf = vec![0; 4096];
             loop {
-                let size = connection_worker.read(&mut buf).unwrap();;
+                let size = connection_worker.read(&mut buf).unwrap();
 
                 if size == 0 {
                     break;
                 }
 
-                quic_writer.write(&mut buf[..size]).unwrap();
+                quic_writer.write_all(&buf[..size]).unwrap();
             }
         });
 
         let mut buf = vec![0; 4096];
         loop {
-            let size = stream_own.read(&mut buf).unwrap();;
+            let size = stream_own.read(&mut buf).unwrap();
 
             if size == 0 {
                 break;
@@ -4,7 +4,6 @@ extern crate env_logger;
 
 extern crate mig;
 
-use std::sync::{Arc};
 use std::io::{Read, Write};
 use std::net::TcpStream;
 use std::str;
@@ -47,21 +46,19 @@ fn main() {
                 return;
             },
         };
-        let connection = Arc::new(connection);
-        let connection_worker = connection.clone();
         info!("Got a connection");
 
+        // the client sends on its own stream 1, the server replies on its stream 2
         let mut stream_own = connection.get_stream(2);
+        let mut stream_reader = connection.get_stream(1);
 
         let mut target_stream_own = TcpStream::connect(target.clone()).unwrap();
         let mut target_stream_writer = target_stream_own.try_clone().unwrap();
 
         let target_writer = thread::spawn(move || {
-            let mut stream_writer = connection_worker.get_stream(2);
-
             let mut buf = vec![0; 4096];
             loop {
-                let size = stream_writer.read(&mut buf).unwrap();;
+                let size = stream_reader.read(&mut buf).unwrap();
 
                 if size == 0 {
                     break;
@@ -73,13 +70,13 @@ fn main() {
 
         let mut buf = vec![0; 4096];
         loop {
-            let size = target_stream_own.read(&mut buf).unwrap();;
+            let size = target_stream_own.read(&mut buf).unwrap();
 
             if size == 0 {
                 break;
             }
 
-            stream_own.write(&mut buf[..size]).unwrap();
+            stream_own.write_all(&buf[..size]).unwrap();
         }
 
         target_writer.join().unwrap();
@@ -30,9 +30,9 @@ fn main() {
 
     info!("Running client connected to {}", address);
 
-    let mut stream = connection.get_stream(2);
+    let mut stream = connection.get_stream(1);
 
-    info!("Got stream 2. Copying from the stream to stdout.");
+    info!("Got stream 1. Copying from stdin to the stream.");
 
     //std::io::copy(&mut stream, &mut std::io::stdout());
     std::io::copy(&mut std::io::stdin(), &mut stream).unwrap();
@@ -2,6 +2,8 @@
 extern crate byteorder;
 extern crate cast;
 extern crate env_logger;
+#[cfg(target_os = "linux")]
+extern crate libc;
 #[macro_use]
 extern crate log;
 extern crate rand;
@@ -0,0 +1,53 @@
+/// How many spare buffers a pool keeps at most
+pub const DEFAULT_MAX_BUFFERS: usize = 256;
+
+
+/// Datagram buffers kept for reuse, so that sending and receiving don't allocate per packet
+///
+/// Buffers are handed out `buffer_size` bytes long, with whatever they held before.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BufferPool {
+    buffer_size: usize,
+    max_buffers: usize,
+    buffers: Vec<Vec<u8>>,
+}
+
+impl BufferPool {
+    pub fn new(buffer_size: usize, max_buffers: usize) -> BufferPool {
+        BufferPool {
+            buffer_size,
+            max_buffers,
+            buffers: Vec::new(),
+        }
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// How many spare buffers are kept
+    pub fn len(&self) -> usize {
+        self.buffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+
+    pub fn take(&mut self) -> Vec<u8> {
+        match self.buffers.pop() {
+            Some(buffer) => buffer,
+            None => vec![0; self.buffer_size],
+        }
+    }
+
+    /// Keep a buffer of any length for reuse, unless the pool is full or the buffer is too small
+    pub fn give_back(&mut self, mut buffer: Vec<u8>) {
+        if self.buffers.len() >= self.max_buffers || buffer.capacity() < self.buffer_size {
+            return;
+        }
+
+        buffer.resize(self.buffer_size, 0);
+        self.buffers.push(buffer);
+    }
+}
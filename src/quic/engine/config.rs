@@ -0,0 +1,66 @@
+use std::time;
+
+
+/// 1500 bytes minus the IPv6 and UDP headers
+pub const DEFAULT_MAX_PACKET_SIZE: usize = 1452;
+
+/// 9000 bytes minus the IPv4 and UDP headers, for networks with jumbo frames
+pub const JUMBO_MAX_PACKET_SIZE: usize = 8972;
+
+/// Connections without incoming packets for this long are dropped
+pub const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 30;
+
+/// Streams a peer may open at once unless configured otherwise
+pub const DEFAULT_MAX_OPEN_STREAMS: u32 = 100;
+
+/// Unacknowledged bytes a single stream may buffer for sending
+pub const DEFAULT_STREAM_SEND_BUFFER: usize = 1024 * 1024;
+
+/// Unacknowledged bytes all streams of a connection may buffer for sending
+pub const DEFAULT_CONNECTION_SEND_BUFFER: usize = 4 * 1024 * 1024;
+
+
+/// Engine-wide settings applied to every connection
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// Ask peers to omit the connection ID from the packets they send us.
+    ///
+    /// Only a client that owns its socket may set this: packets without
+    /// a connection ID are routed by their source address instead.
+    pub omit_connection_id: bool,
+
+    /// The largest UDP payload path MTU discovery probes for.
+    ///
+    /// Defaults to what fits a 1500 byte Ethernet frame, set it to
+    /// `JUMBO_MAX_PACKET_SIZE` where paths carry jumbo frames, or to
+    /// `pmtu::BASE_PACKET_SIZE` to disable discovery. Smaller values are
+    /// raised to that size, which every path is assumed to carry.
+    pub max_packet_size: usize,
+
+    /// How many streams the peer may have open at once, announced when the connection starts.
+    pub max_open_streams: u32,
+
+    /// Drop connections that haven't received anything for this long.
+    pub idle_timeout: time::Duration,
+
+    /// How many written bytes a stream holds until the peer acknowledges them.
+    ///
+    /// Writes beyond this are cut short, the threaded API blocks until there's room.
+    pub stream_send_buffer: usize,
+
+    /// Like `stream_send_buffer`, for all the streams of a connection together.
+    pub connection_send_buffer: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            omit_connection_id: false,
+            max_packet_size: DEFAULT_MAX_PACKET_SIZE,
+            max_open_streams: DEFAULT_MAX_OPEN_STREAMS,
+            idle_timeout: time::Duration::from_secs(DEFAULT_IDLE_TIMEOUT_SECS),
+            stream_send_buffer: DEFAULT_STREAM_SEND_BUFFER,
+            connection_send_buffer: DEFAULT_CONNECTION_SEND_BUFFER,
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::cmp::max;
+
+
+/// Largest packet we expect to put on the wire, used to size the window
+pub const MAX_SEGMENT_SIZE: usize = 1200;
+
+pub const INITIAL_WINDOW: usize = 10 * MAX_SEGMENT_SIZE;
+pub const MINIMUM_WINDOW: usize = 2 * MAX_SEGMENT_SIZE;
+
+
+/// NewReno-style congestion controller for a single path
+#[derive(Clone, Debug, PartialEq)]
+pub struct CongestionController {
+    congestion_window: usize,
+    bytes_in_flight: usize,
+    slow_start_threshold: usize,
+
+    // losses of packets sent before this one don't shrink the window again
+    recovery_end_packet_number: Option<u64>,
+}
+
+impl Default for CongestionController {
+    fn default() -> CongestionController {
+        CongestionController {
+            congestion_window: INITIAL_WINDOW,
+            bytes_in_flight: 0,
+            slow_start_threshold: usize::MAX,
+
+            recovery_end_packet_number: None,
+        }
+    }
+}
+
+impl CongestionController {
+    pub fn new() -> CongestionController {
+        CongestionController::default()
+    }
+
+    pub fn congestion_window(&self) -> usize {
+        self.congestion_window
+    }
+
+    pub fn bytes_in_flight(&self) -> usize {
+        self.bytes_in_flight
+    }
+
+    /// How many more bytes the window allows to be sent right now
+    pub fn available_window(&self) -> usize {
+        self.congestion_window.saturating_sub(self.bytes_in_flight)
+    }
+
+    pub fn on_packet_sent(&mut self, size: usize) {
+        self.bytes_in_flight += size;
+    }
+
+    pub fn on_packet_acked(&mut self, packet_number: u64, size: usize) {
+        self.bytes_in_flight = self.bytes_in_flight.saturating_sub(size);
+
+        match self.recovery_end_packet_number {
+            Some(recovery_end) if packet_number <= recovery_end => {
+                // still recovering, don't grow the window
+                return;
+            },
+            _ => {},
+        }
+
+        if self.congestion_window < self.slow_start_threshold {
+            self.congestion_window += size;
+        } else {
+            self.congestion_window += MAX_SEGMENT_SIZE * size / self.congestion_window;
+        }
+    }
+
+    /// Register a lost packet, `largest_sent` is the largest packet number sent so far
+    pub fn on_packet_lost(&mut self, packet_number: u64, size: usize, largest_sent: u64) {
+        self.bytes_in_flight = self.bytes_in_flight.saturating_sub(size);
+
+        match self.recovery_end_packet_number {
+            Some(recovery_end) if packet_number <= recovery_end => {
+                return;
+            },
+            _ => {},
+        }
+
+        self.recovery_end_packet_number = Some(largest_sent);
+        self.congestion_window = max(self.congestion_window / 2, MINIMUM_WINDOW);
+        self.slow_start_threshold = self.congestion_window;
+        debug!("Packet {} lost, congestion window: {}", packet_number, self.congestion_window);
+    }
+}
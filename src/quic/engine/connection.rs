@@ -1,109 +1,585 @@
 use std::cmp::min;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::net;
+use std::time;
+
+use rand;
+use rand::Rng;
 
 use quic::endpoint_role::EndpointRole;
-use quic::errors::Result;
-use quic::packets::frames::{ack, Frame, stream, window_update};
+use quic::errors::{codes, Error, Result};
+use quic::QUIC_VERSION;
+use quic::packets::frames::{ack, blocked, connection_close, Frame, FrameRef, goaway, padding, parameters, path_challenge, path_response, ping, rst_stream, stream, window_update};
 use quic::packets;
+use super::config::{Config, DEFAULT_MAX_OPEN_STREAMS};
+use super::event::Event;
+use super::path::{AddressCandidate, Migration, Path, PathInfo, SentPacket, SentStreamData, Validation, MAX_PATHS};
+use super::pmtu::MtuDiscovery;
 use super::stream::{Stream, StreamState};
+use super::timer::{ScheduledEvent, TimerToken};
+
 
+const PATH_VALIDATION_TIMEOUT_MS: u64 = 200;
+
+
+/// A packet ready to be encoded, along with the path and addresses it travels on
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutgoingPacket {
+    pub path_id: u8,
+    pub local_address: Option<net::SocketAddr>,
+    pub peer_address: net::SocketAddr,
+    pub packet: packets::Packet,
+}
+
+
+/// Where a connection is in its lifetime
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectionState {
+    /// Waiting for the peer's parameters
+    Handshaking,
+    Established,
+    /// We sent a CONNECTION_CLOSE and repeat it to packets that still arrive
+    Closing,
+    /// The peer closed the connection or went idle, nothing is sent anymore
+    Draining,
+    /// The draining period is over and the connection can be dropped
+    Closed,
+}
 
-const MAX_DATA_SIZE: usize = 1000;
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Connection {
     id: u64,
     endpoint_role: EndpointRole,
-    last_consecutive_packet_number: u64,
-    next_outgoing_packet_number: u64,
-    peer_address: net::SocketAddr,
-    pending_packets: Vec<packets::Packet>,
-    streams: Vec<Stream>,
-    pub unacked_packet_numbers: HashSet<u64>,
+    state: ConnectionState,
+    omit_incoming_connection_id: bool,
+    omit_outgoing_connection_id: bool,
+    max_packet_size: usize,
+    max_incoming_streams: u32,
+    max_outgoing_streams: u32,
+    stream_send_buffer: usize,
+    connection_send_buffer: usize,
+    largest_local_stream_id: u32,
+    largest_peer_stream_id: u32,
+    // the peer won't take new streams after a GOAWAY
+    peer_going_away: bool,
+    close_code: Option<u32>,
+    close_packet: Option<OutgoingPacket>,
+    resend_close_packet: bool,
+    idle_timeout: time::Duration,
+    last_activity: time::Instant,
+    paths: Vec<Path>,
+    next_path_id: u8,
+    address_candidate: Option<AddressCandidate>,
+    pending_packets: Vec<OutgoingPacket>,
+    pending_events: Vec<(time::Duration, ScheduledEvent)>,
+    cancelled_timers: Vec<TimerToken>,
+    // only open streams, closed ones are implied by the largest opened ids
+    streams: BTreeMap<u32, Stream>,
+    // streams opened by the peer, not accepted by the application yet
+    new_peer_streams: VecDeque<u32>,
+    // streams to report as writable once acknowledgements free their send buffer
+    write_waiters: BTreeSet<u32>,
+    // streams to report as writable once all their data is acknowledged
+    flush_waiters: BTreeSet<u32>,
+    events: Vec<Event>,
 
     incoming_packet_count: u64,
     outgoing_packet_count: u64,
 }
 
 impl Connection {
-    pub fn new(id: u64, endpoint_role: EndpointRole, peer_address: net::SocketAddr) -> Connection {
-        Connection {
-            id: id,
-            endpoint_role: endpoint_role,
-            last_consecutive_packet_number: 0,
-            next_outgoing_packet_number: 1,
-            peer_address: peer_address,
+    pub fn new(
+            id: u64,
+            endpoint_role: EndpointRole,
+            peer_address: net::SocketAddr,
+            local_address: Option<net::SocketAddr>,
+            config: &Config,
+            now: time::Instant) -> Connection {
+        // only a client can route by address, servers share their socket
+        let omit_incoming_connection_id =
+            endpoint_role == EndpointRole::Client && config.omit_connection_id;
+
+        let mut connection = Connection {
+            id,
+            endpoint_role,
+            state: ConnectionState::Handshaking,
+            omit_incoming_connection_id,
+            omit_outgoing_connection_id: false,
+            max_packet_size: config.max_packet_size,
+            max_incoming_streams: config.max_open_streams,
+            // until the peer announces its own limit
+            max_outgoing_streams: DEFAULT_MAX_OPEN_STREAMS,
+            stream_send_buffer: config.stream_send_buffer,
+            connection_send_buffer: config.connection_send_buffer,
+            largest_local_stream_id: 0,
+            largest_peer_stream_id: 0,
+            peer_going_away: false,
+            close_code: None,
+            close_packet: None,
+            resend_close_packet: false,
+            idle_timeout: config.idle_timeout,
+            last_activity: now,
+            paths: vec![Path::new(0, local_address, peer_address, Validation::validated(), config.max_packet_size)],
+            next_path_id: 1,
+            address_candidate: None,
             pending_packets: vec![],
-            streams: vec![],
-            unacked_packet_numbers: HashSet::new(),
+            pending_events: vec![],
+            cancelled_timers: vec![],
+            streams: BTreeMap::new(),
+            new_peer_streams: VecDeque::new(),
+            write_waiters: BTreeSet::new(),
+            flush_waiters: BTreeSet::new(),
+            events: vec![],
 
             incoming_packet_count: 0,
             outgoing_packet_count: 0,
+        };
+
+        connection.send_parameters();
+        connection.pending_events.push((config.idle_timeout, ScheduledEvent::IdleTimeout(id)));
+
+        connection
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Whether packets from the peer may arrive without a connection ID
+    pub fn omits_incoming_connection_id(&self) -> bool {
+        self.omit_incoming_connection_id
+    }
+
+    /// Buffer data to send on a stream, returns how much fit into the send buffers
+    pub fn write(&mut self, stream_id: u32, buf: &[u8]) -> Result<usize> {
+        self.local_stream(stream_id)?;
+        let connection_space = self.connection_send_space();
+        let stream_send_buffer = self.stream_send_buffer;
+
+        let stream = match self.streams.get_mut(&stream_id) {
+            Some(stream) => stream,
+            None => return Err(Error::InvalidStream),
+        };
+
+        if stream.state == StreamState::LocalClosed || stream.state == StreamState::Closed {
+            return Err(Error::InvalidStream);
+        }
+
+        let stream_space = stream_send_buffer.saturating_sub(stream.buffered_outgoing_len());
+        let size = min(buf.len(), min(stream_space, connection_space));
+        stream.extend_outgoing_buf(&buf[..size]);
+        if size < buf.len() {
+            self.write_waiters.insert(stream_id);
         }
+
+        Ok(size)
     }
 
-    pub fn write(&mut self, stream_id: u32, buf: &[u8]) -> Result<()> {
-        self.extend_streams(stream_id);
-        let ref mut stream = self.streams[stream_id as usize];
-        stream.extend_outgoing_buf(buf);
+    /// Open the next stream with a locally initiated id
+    pub fn open_stream(&mut self) -> Result<u32> {
+        let stream_id = if self.largest_local_stream_id == 0 {
+            if self.endpoint_role == EndpointRole::Client { 1 } else { 2 }
+        } else {
+            match self.largest_local_stream_id.checked_add(2) {
+                Some(stream_id) => stream_id,
+                None => return Err(Error::TooManyOpenStreams),
+            }
+        };
 
-        Ok(())
+        self.local_stream(stream_id)?;
+        debug!("Connection {} opened stream {}", self.id, stream_id);
+
+        Ok(stream_id)
+    }
+
+    /// Take the next stream opened by the peer
+    ///
+    /// Fails once the connection is closed and every new stream was taken.
+    pub fn accept_stream(&mut self) -> Result<Option<u32>> {
+        match self.new_peer_streams.pop_front() {
+            Some(stream_id) => Ok(Some(stream_id)),
+            None if !self.is_open() =>
+                Err(Error::ConnectionClosed(self.close_code.unwrap_or(codes::QUIC_NO_ERROR))),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether `accept_stream` would return a stream or an error
+    pub fn has_new_streams(&self) -> bool {
+        !self.new_peer_streams.is_empty() || !self.is_open()
+    }
+
+    /// The highest offset up to which the peer acknowledged all data of the stream
+    ///
+    /// Fails for streams that were closed and freed, which happens only once
+    /// everything was acknowledged.
+    pub fn acked_offset(&self, stream_id: u32) -> Result<u64> {
+        match self.streams.get(&stream_id) {
+            Some(stream) => Ok(stream.acked_offset()),
+            None => Err(Error::InvalidStream),
+        }
+    }
+
+    /// Whether everything written to the stream has been acknowledged
+    ///
+    /// Fails if the connection closed before that.
+    pub fn is_flushed(&mut self, stream_id: u32) -> Result<bool> {
+        let buffered = self.streams.get(&stream_id).map_or(0, |stream| stream.buffered_outgoing_len());
+        if buffered == 0 {
+            return Ok(true);
+        }
+        if !self.is_open() {
+            return Err(Error::ConnectionClosed(self.close_code.unwrap_or(codes::QUIC_NO_ERROR)));
+        }
+
+        self.flush_waiters.insert(stream_id);
+        Ok(false)
+    }
+
+    /// Whether a write to the stream would take any data, or fail right away
+    pub fn is_writable(&self, stream_id: u32) -> bool {
+        if !self.is_open() {
+            return true;
+        }
+
+        let stream_space = match self.streams.get(&stream_id) {
+            Some(stream) => self.stream_send_buffer.saturating_sub(stream.buffered_outgoing_len()),
+            None => self.stream_send_buffer,
+        };
+
+        stream_space > 0 && self.connection_send_space() > 0
     }
 
     pub fn read(&mut self, stream_id: u32, buf: &mut [u8]) -> Result<usize> {
-        self.extend_streams(stream_id);
-        let ref mut stream = self.streams[stream_id as usize];
-        stream.read(buf)
+        if !self.is_open() {
+            let close_code = self.close_code.unwrap_or(codes::QUIC_NO_ERROR);
+
+            // after a graceful close, data that arrived before it can still be read
+            return match self.streams.get_mut(&stream_id) {
+                Some(ref mut stream) if close_code == codes::QUIC_NO_ERROR && stream.data_available() =>
+                    stream.read(buf),
+                _ => Err(Error::ConnectionClosed(close_code)),
+            };
+        }
+
+        self.local_stream(stream_id)?;
+        let read_size = match self.streams.get_mut(&stream_id) {
+            Some(stream) => stream.read(buf)?,
+            // everything was read before the stream got closed
+            None => 0,
+        };
+
+        self.collect_closed_streams();
+
+        Ok(read_size)
     }
 
     pub fn finalize_outgoing_stream(&mut self, stream_id: u32) -> Result<()> {
-        self.extend_streams(stream_id);
-        let ref mut stream = self.streams[stream_id as usize];
-        stream.finalize_outgoing();
+        self.local_stream(stream_id)?;
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            stream.finalize_outgoing();
+        }
 
         Ok(())
     }
 
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    /// Whether the connection can still carry data
+    pub fn is_open(&self) -> bool {
+        match self.state {
+            ConnectionState::Handshaking | ConnectionState::Established => true,
+            ConnectionState::Closing | ConnectionState::Draining | ConnectionState::Closed => false,
+        }
+    }
+
+    /// The error code the connection was closed with, by either side
+    pub fn close_code(&self) -> Option<u32> {
+        self.close_code
+    }
+
+    // a closed connection reports data, so that readers wake up and get the error
     pub fn any_data_available(&self) -> bool {
-        self.streams.iter().any(|stream| stream.data_available())
+        !self.is_open() || self.streams.values().any(|stream| stream.data_available())
     }
 
     pub fn data_available(&self, stream_id: u32) -> bool {
-        match self.streams.get(stream_id as usize) {
+        if !self.is_open() {
+            return true;
+        }
+
+        match self.streams.get(&stream_id) {
             Some(stream) => stream.data_available(),
+            // reading a closed stream returns the end of the stream right away
+            None => self.is_closed_stream(stream_id),
+        }
+    }
+
+    /// Start using another local/peer address pair for this connection
+    ///
+    /// The path only carries stream data once the peer answers its PATH_CHALLENGE.
+    pub fn open_path(
+            &mut self,
+            local_address: Option<net::SocketAddr>,
+            peer_address: net::SocketAddr) -> Result<u8> {
+        if self.endpoint_role != EndpointRole::Client {
+            return Err(Error::InvalidData(String::from("Only clients can open paths")));
+        }
+        if self.paths.len() >= MAX_PATHS || self.next_path_id == u8::MAX {
+            return Err(Error::InvalidData(String::from("Too many paths")));
+        }
+
+        let path_id = self.next_path_id;
+        self.next_path_id += 1;
+
+        let mut rng = rand::thread_rng();
+        let mut validation = Validation::new(rng.gen());
+        validation.limit_amplification = false;
+
+        self.paths.push(Path::new(path_id, local_address, peer_address, validation, self.max_packet_size));
+        let index = self.paths.len() - 1;
+        self.send_path_challenge(index);
+
+        debug!("Opening path {} from {:?} to {}", path_id, local_address, peer_address);
+        Ok(path_id)
+    }
+
+    pub fn paths(&self) -> Vec<PathInfo> {
+        self.paths.iter().map(|path| path.info()).collect()
+    }
+
+    pub fn check_unacked_packet(&mut self, path_id: u8, packet_number: u64) {
+        if !self.is_open() {
+            return;
+        }
+
+        // packets of abandoned paths were handled when the path went away
+        let sent_packet = match self.path_index(path_id) {
+            Some(index) => {
+                trace!("Unacked packets on path {}: {:?}", path_id, self.paths[index].unacked_packets.keys());
+                self.paths[index].on_packet_lost(packet_number)
+            },
+            None => None,
+        };
+
+        if let Some(sent_packet) = sent_packet {
+            debug!("Resending frames of packet {} from path {}", packet_number, path_id);
+            self.retransmit(sent_packet);
+        }
+    }
+
+    /// Whether an encoded packet of `size` bytes may be sent right now
+    pub fn can_send(&self, outgoing: &OutgoingPacket, size: usize) -> bool {
+        let path = match self.path_index(outgoing.path_id) {
+            Some(index) => &self.paths[index],
+            None => return false,
+        };
+
+        if outgoing.peer_address == path.peer_address {
+            return path.validation.can_send(size);
+        }
+
+        match self.address_candidate {
+            Some(ref candidate) if candidate.path_id == path.id && candidate.address == outgoing.peer_address =>
+                candidate.validation.can_send(size),
+            _ => false,
+        }
+    }
+
+    /// Account for an encoded packet handed to the socket
+    ///
+    /// Returns the retransmission timeout of its path if the packet has to be retransmitted until acknowledged.
+    pub fn on_packet_sent(&mut self, outgoing: OutgoingPacket, size: usize, now: time::Instant) -> Option<time::Duration> {
+        let index = self.path_index(outgoing.path_id)?;
+
+        if outgoing.peer_address != self.paths[index].peer_address {
+            // probing packets to a candidate address are never retransmitted as is
+            if let Some(ref mut candidate) = self.address_candidate {
+                if candidate.path_id == outgoing.path_id && candidate.address == outgoing.peer_address {
+                    candidate.validation.on_datagram_sent(size);
+                }
+            }
+
+            return None;
+        }
+
+        let path = &mut self.paths[index];
+        path.validation.on_datagram_sent(size);
+
+        let packet_number = outgoing.packet.packet_number().unwrap();
+        let frames = match outgoing.packet {
+            packets::Packet::Regular(regular_packet) => regular_packet.payload.frames,
+            _ => vec![],
+        };
+
+        // stream data is kept by its stream, only the ranges are remembered
+        let mut sent_packet = SentPacket {
+            size,
+            time_sent: now,
+            frames: vec![],
+            stream_data: vec![],
+            retransmission_timer: None,
+        };
+        for frame in frames.into_iter().filter(Self::is_retransmittable) {
+            match frame {
+                Frame::Stream(ref stream_frame) if !stream_frame.stream_data.is_empty() => {
+                    sent_packet.stream_data.push(SentStreamData {
+                        stream_id: stream_frame.stream_id,
+                        offset: stream_frame.offset,
+                        length: stream_frame.stream_data.len(),
+                    });
+                    continue;
+                },
+                _ => {},
+            }
+            sent_packet.frames.push(frame);
+        }
+        if sent_packet.frames.is_empty() && sent_packet.stream_data.is_empty() {
+            return None;
+        }
+
+        path.on_packet_sent(packet_number, sent_packet);
+
+        Some(path.retransmission_timeout())
+    }
+
+    /// Remember the timer of a sent packet, to cancel it when the packet gets acknowledged
+    pub fn on_retransmission_scheduled(&mut self, path_id: u8, packet_number: u64, token: TimerToken) {
+        if let Some(index) = self.path_index(path_id) {
+            self.paths[index].set_retransmission_timer(packet_number, token);
+        }
+    }
+
+    pub fn drain_scheduled_events(&mut self) -> Vec<(time::Duration, ScheduledEvent)> {
+        self.pending_events.drain(..).collect()
+    }
+
+    pub fn drain_cancelled_timers(&mut self) -> Vec<TimerToken> {
+        self.cancelled_timers.drain(..).collect()
+    }
+
+    pub fn drain_events(&mut self) -> Vec<Event> {
+        self.events.drain(..).collect()
+    }
+
+    /// Resend an unanswered PATH_CHALLENGE or give up on its address or path
+    pub fn check_path_validation(&mut self, challenge_data: u64) {
+        if !self.is_open() {
+            return;
+        }
+
+        let candidate_pending = match self.address_candidate {
+            Some(ref candidate) =>
+                !candidate.validation.is_validated() &&
+                candidate.validation.challenge_data() == challenge_data,
             None => false,
+        };
+        if candidate_pending {
+            if !self.send_candidate_challenge() {
+                let candidate = self.address_candidate.take().unwrap();
+                warn!("Could not validate address {}, not migrating", candidate.address);
+            }
+            return;
+        }
+
+        let pending_path =
+            self.paths.iter()
+            .position(|path| !path.is_validated() && path.validation.challenge_data() == challenge_data);
+        if let Some(index) = pending_path {
+            if !self.send_path_challenge(index) {
+                let path = self.paths.remove(index);
+                warn!("Could not validate path {} to {}, abandoning it", path.id, path.peer_address);
+
+                // nothing will acknowledge the packets sent on it anymore
+                for (_, sent_packet) in path.unacked_packets {
+                    self.cancelled_timers.extend(sent_packet.retransmission_timer);
+                    self.retransmit(sent_packet);
+                }
+            }
+        }
+    }
+
+    /// A PMTU probe went unacknowledged for too long
+    pub fn check_mtu_probe(&mut self, path_id: u8, packet_number: u64) {
+        if !self.is_open() {
+            return;
+        }
+
+        if let Some(index) = self.path_index(path_id) {
+            self.paths[index].mtu.on_probe_lost(packet_number);
         }
     }
 
-    pub fn check_unacked_packet(&mut self, packet: packets::Packet) {
-        let packet_number = packet.packet_number().unwrap();
+    /// Close the connection if nothing arrived for the idle timeout, or check again later
+    pub fn check_idle_timeout(&mut self, now: time::Instant) {
+        if !self.is_open() {
+            return;
+        }
+
+        let idle_time = if now > self.last_activity {
+            now - self.last_activity
+        } else {
+            time::Duration::from_secs(0)
+        };
+
+        if idle_time >= self.idle_timeout {
+            info!("Connection {} timed out after {:?} without packets", self.id, idle_time);
+            self.drain(codes::QUIC_NETWORK_IDLE_TIMEOUT);
+        } else {
+            self.pending_events.push((self.idle_timeout - idle_time, ScheduledEvent::IdleTimeout(self.id)));
+        }
+    }
 
-        trace!("Unacked packets: {:?}", self.unacked_packet_numbers);
-        if self.unacked_packet_numbers.contains(&packet_number) {
-            debug!("Resending packet: {:?}", packet);
-            self.pending_packets.push(packet);
+    /// The draining period is over, the connection can be dropped
+    pub fn on_draining_timeout(&mut self) {
+        if self.state == ConnectionState::Closing || self.state == ConnectionState::Draining {
+            debug!("Connection {} closed", self.id);
+            self.state = ConnectionState::Closed;
         }
     }
 
     pub fn is_finalized(&self) -> bool {
+        if !self.is_open() {
+            return true;
+        }
+
         let mut all_streams_finalized = true;
-        for stream in &self.streams {
+        for stream in self.streams.values() {
             if !stream.is_finalized() {
                 all_streams_finalized = false;
                 break;
             }
         }
 
-        self.unacked_packet_numbers.is_empty() && all_streams_finalized
+        let all_packets_acked = self.paths.iter().all(|path| path.unacked_packets.is_empty());
+
+        all_packets_acked && all_streams_finalized
     }
 
-    pub fn drain_outgoing_packets(&mut self) -> Vec<packets::Packet> {
+    pub fn drain_outgoing_packets(&mut self) -> Vec<OutgoingPacket> {
         let mut packets = vec![];
 
-        packets.extend(self.drain_outgoing_stream_packets());
-        packets.extend(self.drain_outgoing_window_update_packets());
-        packets.extend(self.drain_pending_packets());
+        match self.state {
+            ConnectionState::Handshaking | ConnectionState::Established => {
+                packets.extend(self.drain_outgoing_stream_packets());
+                packets.extend(self.drain_outgoing_window_update_packets());
+                packets.extend(self.drain_pending_packets());
+                packets.extend(self.drain_mtu_probe_packets());
+            },
+            ConnectionState::Closing => {
+                self.pending_packets.clear();
+                if self.resend_close_packet {
+                    self.resend_close_packet = false;
+                    packets.extend(self.close_packet.clone());
+                }
+            },
+            ConnectionState::Draining | ConnectionState::Closed => {
+                self.pending_packets.clear();
+            },
+        }
 
         self.outgoing_packet_count += packets.len() as u64;
         debug!("drain_outgoing_packets len: {}", packets.len());
@@ -111,26 +587,34 @@ impl Connection {
             debug!("total outgoing packets: {}", self.outgoing_packet_count);
         }
 
-        for packet in &packets {
-            trace!("Sending packet: {:?}", packet);
+        for outgoing in &packets {
+            trace!("Sending packet on path {} to {}: {:?}", outgoing.path_id, outgoing.peer_address, outgoing.packet);
         }
 
         return packets;
     }
 
-    pub fn drain_pending_packets(&mut self) -> Vec<packets::Packet> {
+    pub fn drain_pending_packets(&mut self) -> Vec<OutgoingPacket> {
         self.pending_packets.drain(..).collect()
     }
 
-    pub fn drain_outgoing_window_update_packets(&mut self) -> Vec<packets::Packet> {
+    pub fn drain_outgoing_window_update_packets(&mut self) -> Vec<OutgoingPacket> {
         let mut packets = vec![];
+        let connection_id = self.outgoing_connection_id();
+        let index = self.primary_path_index();
+        let endpoint_role = self.endpoint_role;
+
+        for stream in self.streams.values_mut() {
+            // a window update would open the stream on the peer before we use it
+            if stream.state == StreamState::Idle && Self::is_local_stream(endpoint_role, stream.id) {
+                continue;
+            }
 
-        for stream in &mut self.streams {
             match stream.new_maximum_data() {
                 Some(maximum_data) => {
                     packets.push(Self::create_packet(
-                        &mut self.next_outgoing_packet_number,
-                        self.id,
+                        &mut self.paths[index],
+                        connection_id,
                         vec![
                             Frame::WindowUpdate(window_update::WindowUpdateFrame {
                                 stream_id: stream.id,
@@ -146,40 +630,107 @@ impl Connection {
         packets
     }
 
-    fn drain_outgoing_stream_packets(&mut self) -> Vec<packets::Packet> {
+    /// Probe larger packet sizes on validated paths, one probe per path at a time
+    ///
+    /// A probe is a PING padded to the probed size, so its acknowledgement
+    /// confirms the size made it through.
+    fn drain_mtu_probe_packets(&mut self) -> Vec<OutgoingPacket> {
+        let connection_id = self.outgoing_connection_id();
         let mut packets = vec![];
-        let mut frames = vec![];
-        let mut data_length = 0;
 
-        for stream in &mut self.streams {
-            let (mut next_outgoing_offset, stream_buffer) = stream.drain_outgoing_buffer();
-            let mut stream_buffer = &stream_buffer[..];
+        for path in &mut self.paths {
+            if !path.is_validated() {
+                continue;
+            }
 
-            while !stream_buffer.is_empty() {
-                let can_fit = MAX_DATA_SIZE - data_length;
-                let will_fit = min(can_fit, stream_buffer.len());
-                if will_fit > 0 {
+            let probe_size = match path.mtu.next_probe_size() {
+                Some(probe_size) => probe_size,
+                None => continue,
+            };
+
+            let mut probe = Self::create_packet(path, connection_id, vec![Frame::Ping(ping::PingFrame {})]);
+            let mut buffer = vec![];
+            probe.packet.encode(&mut buffer).unwrap();
+
+            // every padding frame is a single byte
+            if let packets::Packet::Regular(ref mut regular_packet) = probe.packet {
+                for _ in buffer.len()..probe_size {
+                    regular_packet.payload.frames.push(Frame::Padding(padding::PaddingFrame {}));
+                }
+            }
+
+            let packet_number = probe.packet.packet_number().unwrap();
+            path.mtu.on_probe_sent(packet_number, probe_size);
+            debug!("Probing path {} with a {} byte packet", path.id, probe_size);
+
+            self.pending_events.push((
+                MtuDiscovery::probe_timeout(path.rtt.smoothed_rtt()),
+                ScheduledEvent::MtuProbeTimeout(self.id, path.id, packet_number),
+            ));
+            packets.push(probe);
+        }
+
+        packets
+    }
+
+    /// Packetise buffered stream data over the validated paths
+    ///
+    /// Paths are filled up to their congestion window, lowest RTT first.
+    fn drain_outgoing_stream_packets(&mut self) -> Vec<OutgoingPacket> {
+        let connection_id = self.outgoing_connection_id();
+        let mut packets = vec![];
+
+        let mut path_indices: Vec<usize> =
+            (0..self.paths.len())
+            .filter(|&index| self.paths[index].is_validated())
+            .collect();
+        path_indices.sort_by_key(|&index| self.paths[index].rtt.smoothed_rtt());
+
+        // every frame but the last one of a packet is encoded with its data length
+        let frame_header_size = stream::StreamFrame::header_len(false);
+
+        for index in path_indices {
+            let path = &mut self.paths[index];
+            let header_size = Self::packet_header(path, connection_id).encoded_len(false);
+            let max_payload_size = path.mtu.max_packet_size() - header_size;
+            let mut frames = vec![];
+            let mut payload_size = 0;
+            let mut window = path.congestion.available_window();
+
+            for stream in self.streams.values_mut() {
+                loop {
+                    if max_payload_size - payload_size <= frame_header_size {
+                        packets.push(Self::create_packet(path, connection_id, frames));
+                        frames = vec![];
+                        payload_size = 0;
+                    }
+
+                    let can_fit = min(window, max_payload_size - payload_size - frame_header_size);
+                    let (offset, stream_data) = match stream.next_outgoing_data(can_fit) {
+                        Some(data) => data,
+                        None => break,
+                    };
+
+                    window -= stream_data.len();
+                    payload_size += frame_header_size + stream_data.len();
                     frames.push(Frame::Stream(
                         stream::StreamFrame {
                             stream_id: stream.id,
-                            offset: next_outgoing_offset,
-                            stream_data: Vec::from(&stream_buffer[..will_fit]),
+                            offset,
+                            stream_data,
                             fin: false,
                         }
                     ));
-
-                    stream_buffer = &stream_buffer[will_fit..];
-                    next_outgoing_offset += will_fit as u64;
                 }
+            }
 
-                if !stream_buffer.is_empty() {
-                    packets.push(Self::create_packet(
-                        &mut self.next_outgoing_packet_number,self.id, frames));
-                    frames = vec![];
-                    data_length = 0;
-                }
+            if !frames.is_empty() {
+                packets.push(Self::create_packet(path, connection_id, frames));
             }
+        }
 
+        let index = self.primary_path_index();
+        for stream in self.streams.values_mut() {
             match stream.state {
                 StreamState::LocalClosed | StreamState::Closed => {
                     if !stream.fin_sent {
@@ -195,75 +746,408 @@ impl Connection {
                                 }
                             )
                         ];
-                        packets.push(Self::create_packet(
-                            &mut self.next_outgoing_packet_number,self.id, frames));
+                        packets.push(Self::create_packet(&mut self.paths[index], connection_id, frames));
                     }
                 },
                 _ => {},
             }
         }
 
-        if !frames.is_empty() {
-            packets.push(Self::create_packet(
-                &mut self.next_outgoing_packet_number,self.id, frames));
-        }
+        self.collect_closed_streams();
 
         debug!("drain_outgoing_stream_packets len: {}", packets.len());
         packets
     }
 
-    fn update_peer_addresses(&mut self, address: net::SocketAddr) {
-        self.peer_address = address;
+    /// Notice packets arriving on a path from a new peer address and start validating it
+    ///
+    /// The path keeps sending to its current address until the new one
+    /// echoes our PATH_CHALLENGE, so a forged source address can't redirect it.
+    fn update_peer_addresses(&mut self, index: usize, address: net::SocketAddr, packet_number: u64, datagram_size: usize) {
+        let path_id = self.paths[index].id;
+
+        if address == self.paths[index].peer_address {
+            self.paths[index].validation.on_datagram_received(datagram_size);
+            return;
+        }
+
+        if let Some(ref mut candidate) = self.address_candidate {
+            if candidate.path_id == path_id && candidate.address == address {
+                candidate.validation.on_datagram_received(datagram_size);
+                return;
+            }
+        }
+
+        if packet_number <= self.paths[index].largest_received_packet_number {
+            debug!("Reordered packet from {}, not migrating", address);
+            return;
+        }
+
+        debug!("Peer address of path {} changed to {}, validating", path_id, address);
+
+        let mut rng = rand::thread_rng();
+        let mut candidate = AddressCandidate {
+            path_id,
+            address,
+            validation: Validation::new(rng.gen()),
+        };
+        candidate.validation.on_datagram_received(datagram_size);
+        self.address_candidate = Some(candidate);
+
+        self.send_candidate_challenge();
+    }
+
+    /// Start using a path opened by the peer, validating it like a new address
+    fn accept_path(
+            &mut self,
+            path_id: u8,
+            peer_address: net::SocketAddr,
+            local_address: Option<net::SocketAddr>) -> Option<usize> {
+        if self.paths.len() >= MAX_PATHS {
+            warn!("Too many paths, ignoring path {}", path_id);
+            return None;
+        }
+
+        debug!("Peer opened path {} from {}", path_id, peer_address);
+
+        let mut rng = rand::thread_rng();
+        let validation = Validation::new(rng.gen());
+        self.paths.push(Path::new(path_id, local_address, peer_address, validation, self.max_packet_size));
+        let index = self.paths.len() - 1;
+        self.send_path_challenge(index);
+
+        Some(index)
     }
 
-    pub fn handle_regular_packet(&mut self, packet: &packets::RegularPacket, source_address: net::SocketAddr) {
+    /// Send a PATH_CHALLENGE on a path that isn't validated yet, returns false once out of attempts
+    fn send_path_challenge(&mut self, index: usize) -> bool {
+        let challenge_data = {
+            let validation = &mut self.paths[index].validation;
+            if !validation.on_challenge_sent() {
+                return false;
+            }
+
+            validation.challenge_data()
+        };
+
+        let connection_id = self.outgoing_connection_id();
+        let packet = Self::create_packet(
+            &mut self.paths[index], connection_id, vec![
+            Frame::PathChallenge(path_challenge::PathChallengeFrame {
+                data: challenge_data,
+            }),
+        ]);
+        self.pending_packets.push(packet);
+        self.schedule_path_validation_timeout(challenge_data);
+
+        true
+    }
+
+    /// Send a PATH_CHALLENGE to the candidate address, returns false once out of attempts
+    fn send_candidate_challenge(&mut self) -> bool {
+        let (path_id, address, challenge_data) = match self.address_candidate {
+            Some(ref mut candidate) => {
+                if !candidate.validation.on_challenge_sent() {
+                    return false;
+                }
+
+                (candidate.path_id, candidate.address, candidate.validation.challenge_data())
+            },
+            None => return false,
+        };
+
+        let index = match self.path_index(path_id) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        let connection_id = self.outgoing_connection_id();
+        let mut packet = Self::create_packet(
+            &mut self.paths[index], connection_id, vec![
+            Frame::PathChallenge(path_challenge::PathChallengeFrame {
+                data: challenge_data,
+            }),
+        ]);
+        packet.peer_address = address;
+        self.pending_packets.push(packet);
+        self.schedule_path_validation_timeout(challenge_data);
+
+        true
+    }
+
+    fn schedule_path_validation_timeout(&mut self, challenge_data: u64) {
+        self.pending_events.push((
+            time::Duration::from_millis(PATH_VALIDATION_TIMEOUT_MS),
+            ScheduledEvent::PathValidationTimeout(self.id, challenge_data),
+        ));
+    }
+
+    pub fn handle_regular_packet(
+            &mut self,
+            packet: &packets::RegularPacketRef,
+            source_address: net::SocketAddr,
+            local_address: Option<net::SocketAddr>,
+            datagram_size: usize,
+            now: time::Instant) {
         trace!("Received packet: {:?}", packet);
 
-        self.update_peer_addresses(source_address);
+        match self.state {
+            ConnectionState::Handshaking | ConnectionState::Established => {},
+            ConnectionState::Closing => {
+                // the peer hasn't seen our CONNECTION_CLOSE yet
+                self.resend_close_packet = true;
+                return;
+            },
+            ConnectionState::Draining | ConnectionState::Closed => {
+                debug!("Connection {} is closed, dropping a packet", self.id);
+                return;
+            },
+        }
+        self.last_activity = now;
+
+        let path_id = if packet.header.multipath { packet.header.path_id } else { 0 };
+        let index = match self.path_index(path_id) {
+            Some(index) => index,
+            None => match self.accept_path(path_id, source_address, local_address) {
+                Some(index) => index,
+                None => return,
+            },
+        };
+
+        self.update_peer_addresses(index, source_address, packet.packet_number, datagram_size);
+        if packet.packet_number > self.paths[index].largest_received_packet_number {
+            self.paths[index].largest_received_packet_number = packet.packet_number;
+        }
 
         self.incoming_packet_count += 1;
         debug!("total incoming packets: {}", self.incoming_packet_count);
 
         for frame in &packet.payload.frames {
+            if !self.is_open() {
+                return;
+            }
+
             match *frame {
-                Frame::Ack(ref ack_frame) =>
-                    self.handle_ack_frame(ack_frame),
-                Frame::Blocked(..) => unimplemented!(),
-                Frame::ConnectionClose(..) => unimplemented!(),
-                Frame::GoAway(..) => unimplemented!(),
-                Frame::Padding(..) => {},
-                Frame::Ping(..) => {},
-                Frame::RstStream(..) => unimplemented!(),
-                Frame::StopWaiting(..) => unimplemented!(),
-                Frame::Stream(ref stream_frame) =>
+                FrameRef::Ack(ref ack_frame) =>
+                    self.handle_ack_frame(index, ack_frame, now),
+                FrameRef::Blocked(ref blocked_frame) =>
+                    self.handle_blocked_frame(blocked_frame),
+                FrameRef::ConnectionClose(ref connection_close_frame) =>
+                    self.handle_connection_close_frame(connection_close_frame),
+                FrameRef::GoAway(ref go_away_frame) =>
+                    self.handle_go_away_frame(go_away_frame),
+                FrameRef::Padding(..) => {},
+                FrameRef::Parameters(ref parameters_frame) =>
+                    self.handle_parameters_frame(parameters_frame),
+                FrameRef::PathChallenge(ref path_challenge_frame) =>
+                    self.handle_path_challenge_frame(index, path_challenge_frame, source_address),
+                FrameRef::PathResponse(ref path_response_frame) =>
+                    self.handle_path_response_frame(index, path_response_frame),
+                FrameRef::Ping(..) => {},
+                FrameRef::RstStream(ref rst_stream_frame) =>
+                    self.handle_rst_stream_frame(rst_stream_frame),
+                // every packet is acknowledged on its own, there are no ack ranges to trim
+                FrameRef::StopWaiting(..) => {},
+                FrameRef::Stream(ref stream_frame) =>
                     self.handle_stream_frame(stream_frame),
-                Frame::WindowUpdate(ref window_update_frame) =>
+                FrameRef::WindowUpdate(ref window_update_frame) =>
                     self.handle_window_update_frame(window_update_frame),
             }
         }
 
-        self.save_ack_frame(packet);
+        self.collect_closed_streams();
+        self.save_ack_frame(index, packet, source_address);
+    }
+
+    fn handle_path_challenge_frame(
+            &mut self,
+            index: usize,
+            path_challenge_frame: &path_challenge::PathChallengeFrame,
+            source_address: net::SocketAddr) {
+        let connection_id = self.outgoing_connection_id();
+        let mut packet = Self::create_packet(
+            &mut self.paths[index], connection_id, vec![
+            Frame::PathResponse(path_response::PathResponseFrame {
+                data: path_challenge_frame.data,
+            }),
+        ]);
+        packet.peer_address = source_address;
+        self.pending_packets.push(packet);
+    }
+
+    fn handle_path_response_frame(&mut self, index: usize, path_response_frame: &path_response::PathResponseFrame) {
+        let path_id = self.paths[index].id;
+
+        let migrated = match self.address_candidate {
+            Some(ref mut candidate) if candidate.path_id == path_id =>
+                candidate.validation.on_response_received(path_response_frame.data),
+            _ => false,
+        };
+        if migrated {
+            // the path starts over with fresh congestion state on its new address
+            let candidate = self.address_candidate.take().unwrap();
+            let old_address = self.paths[index].peer_address;
+            self.paths[index].migrate(candidate.address);
+            info!("Connection {} path {} migrated from {} to {}", self.id, path_id, old_address, candidate.address);
+
+            self.events.push(Event::PeerMigrated(self.id, Migration {
+                path_id,
+                old_address,
+                new_address: candidate.address,
+            }));
+            return;
+        }
+
+        if self.paths[index].validation.on_response_received(path_response_frame.data) {
+            info!("Connection {} path {} validated", self.id, path_id);
+            return;
+        }
+
+        debug!("Unexpected path response, ignoring");
+    }
+
+    fn handle_connection_close_frame(&mut self, connection_close_frame: &connection_close::ConnectionCloseFrame) {
+        info!(
+            "Connection {} closed by peer, error code: {:#x}, reason: {:?}",
+            self.id, connection_close_frame.error_code, connection_close_frame.reason_phrase,
+        );
+        self.drain(connection_close_frame.error_code);
+    }
+
+    fn handle_go_away_frame(&mut self, go_away_frame: &goaway::GoAwayFrame) {
+        info!(
+            "Connection {} going away, error code: {:#x}, last good stream: {}, reason: {:?}",
+            self.id, go_away_frame.error_code, go_away_frame.last_good_stream_id, go_away_frame.reason_phrase,
+        );
+        self.peer_going_away = true;
+    }
+
+    fn handle_blocked_frame(&mut self, blocked_frame: &blocked::BlockedFrame) {
+        let stream_id = blocked_frame.stream_id;
+        // the connection level window isn't enforced, only the stream ones
+        if stream_id == 0 {
+            return;
+        }
+        if !self.incoming_stream(stream_id) {
+            return;
+        }
+
+        debug!("Peer blocked on stream {}, announcing the window again", stream_id);
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            stream.on_peer_blocked();
+        }
+    }
+
+    fn handle_rst_stream_frame(&mut self, rst_stream_frame: &rst_stream::RstStreamFrame) {
+        let stream_id = rst_stream_frame.stream_id;
+        if !self.incoming_stream(stream_id) {
+            return;
+        }
+
+        let final_offset = rst_stream_frame.final_offset;
+        let (fin_offset, received_offset) = match self.streams.get(&stream_id) {
+            Some(stream) if stream.is_incoming_finalized() =>
+                (Some(stream.incoming_fin_offset()), stream.received_offset()),
+            Some(stream) => (None, stream.received_offset()),
+            None => return,
+        };
+        if fin_offset.is_some() && fin_offset != Some(final_offset) {
+            self.close(codes::QUIC_MULTIPLE_TERMINATION_OFFSETS, "Reset moved the end of the stream");
+            return;
+        }
+        if final_offset < received_offset {
+            self.close(codes::QUIC_INVALID_RST_STREAM_DATA, "Reset before data already received");
+            return;
+        }
+
+        info!(
+            "Stream {} of connection {} reset by peer, error code: {:#x}",
+            stream_id, self.id, rst_stream_frame.error_code,
+        );
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            stream.reset();
+        }
+        self.write_waiters.remove(&stream_id);
+        self.flush_waiters.remove(&stream_id);
+        self.events.push(Event::StreamReset(self.id, stream_id, rst_stream_frame.error_code));
+    }
+
+    /// The peer dropped the connection state, there's nobody to talk to anymore
+    pub fn handle_public_reset(&mut self) {
+        info!("Connection {} reset by peer", self.id);
+        self.drain(codes::QUIC_PUBLIC_RESET);
+    }
+
+    /// The server doesn't speak our version, and we have no other to fall back to
+    pub fn handle_version_negotiation(&mut self, versions: &[u32]) {
+        if self.endpoint_role != EndpointRole::Client || self.state != ConnectionState::Handshaking {
+            debug!("Unexpected version negotiation for connection {}, ignoring", self.id);
+            return;
+        }
+        if versions.contains(&QUIC_VERSION) {
+            debug!("Version negotiation lists our version, ignoring");
+            return;
+        }
+
+        warn!("Server of connection {} only supports versions {:x?}", self.id, versions);
+        self.drain(codes::QUIC_INVALID_VERSION);
+    }
+
+    fn handle_parameters_frame(&mut self, parameters_frame: &parameters::ParametersFrame) {
+        trace!("Peer parameters: {:?}", parameters_frame);
+
+        self.max_outgoing_streams = parameters_frame.max_open_streams;
+        if self.state == ConnectionState::Handshaking {
+            debug!("Connection {} established", self.id);
+            self.state = ConnectionState::Established;
+            self.events.push(Event::ConnectionEstablished(self.id));
+        }
+
+        if parameters_frame.omit_connection_id {
+            if self.endpoint_role == EndpointRole::Server {
+                self.omit_outgoing_connection_id = true;
+            } else {
+                warn!("Server asked to omit connection IDs, ignoring");
+            }
+        }
     }
 
     fn handle_window_update_frame(&mut self, wu_frame: &window_update::WindowUpdateFrame) {
         let stream_id = wu_frame.stream_id;
-        self.extend_streams(stream_id);
+        if !self.incoming_stream(stream_id) {
+            return;
+        }
 
-        let stream = &mut self.streams[stream_id as usize];
+        let stream = match self.streams.get_mut(&stream_id) {
+            Some(stream) => stream,
+            None => return,
+        };
         if wu_frame.byte_offset > stream.max_outgoing_data {
             stream.max_outgoing_data = wu_frame.byte_offset;
         }
     }
 
-    fn handle_stream_frame(&mut self, stream_frame: &stream::StreamFrame) {
+    fn handle_stream_frame(&mut self, stream_frame: &stream::StreamFrameRef) {
         let stream_id = stream_frame.stream_id;
-        self.extend_streams(stream_id);
+        if !self.incoming_stream(stream_id) {
+            return;
+        }
 
         debug!("Stream frame, data len: {}, fin: {}", stream_frame.stream_data.len(), stream_frame.fin);
 
-        let ref mut stream = self.streams[stream_id as usize];
-        match stream.extend_incoming_buf(stream_frame.offset, &stream_frame.stream_data[..]) {
+        let stream = match self.streams.get_mut(&stream_id) {
+            Some(stream) => stream,
+            None => return,
+        };
+        let was_readable = stream.data_available();
+        let was_finished = stream.state == StreamState::RemoteClosed || stream.state == StreamState::Closed;
+
+        match stream.extend_incoming_buf(stream_frame.offset, stream_frame.stream_data) {
             Ok(()) => {},
+            Err(Error::BufferOverflow) => {
+                self.close(codes::QUIC_FLOW_CONTROL_RECEIVED_TOO_MUCH_DATA, "Stream data beyond the flow control window");
+                return;
+            },
             Err(ref e) => {
                 debug!("Error: {:?}, dropping frame...", e);
             }
@@ -271,10 +1155,18 @@ impl Connection {
 
         if stream_frame.fin {
             stream.finalize_incoming(stream_frame.offset);
+            if !was_finished {
+                self.events.push(Event::StreamFinished(self.id, stream_id));
+            }
+        }
+
+        // readers are told once, when there's something to read again
+        if !was_readable && stream.data_available() {
+            self.events.push(Event::StreamReadable(self.id, stream_id));
         }
     }
 
-    fn handle_ack_frame(&mut self, ack_frame: &ack::AckFrame) {
+    fn handle_ack_frame(&mut self, index: usize, ack_frame: &ack::AckFrame, now: time::Instant) {
         // TODO: implement ACK blocks
         // self.unacked_packet_numbers =
         //     self.unacked_packet_numbers
@@ -284,39 +1176,67 @@ impl Connection {
         //     .collect();
 
         trace!("ACKed ({:?})", ack_frame);
-        self.unacked_packet_numbers.remove(&ack_frame.largest_acknowledged);
+        let sent_packet = match self.paths[index].on_packet_acked(ack_frame.largest_acknowledged, now) {
+            Some(sent_packet) => sent_packet,
+            None => return,
+        };
+
+        self.cancelled_timers.extend(sent_packet.retransmission_timer);
+        for sent_stream_data in sent_packet.stream_data {
+            if let Some(stream) = self.streams.get_mut(&sent_stream_data.stream_id) {
+                stream.on_data_acked(sent_stream_data.offset, sent_stream_data.length);
+            }
+        }
+
+        self.notify_write_waiters();
     }
 
-    fn save_ack_frame(&mut self, packet: &packets::RegularPacket) {
-        // TODO: implement ACK blocks
-        // if packet.packet_number != self.last_consecutive_packet_number + 1 {
-        //     return;
-        // }
+    /// Report the streams waiting for send buffer space that have room again,
+    /// and those waiting for acknowledgements that got them all
+    ///
+    /// A stream can be flushed while others still fill the connection's send buffer.
+    fn notify_write_waiters(&mut self) {
+        let writable: Vec<u32> =
+            self.write_waiters.iter()
+            .cloned()
+            .filter(|&stream_id| self.is_writable(stream_id))
+            .collect();
+        let flushed: Vec<u32> =
+            self.flush_waiters.iter()
+            .cloned()
+            .filter(|stream_id| self.streams.get(stream_id).map_or(0, |stream| stream.buffered_outgoing_len()) == 0)
+            .collect();
+
+        for stream_id in &writable {
+            self.write_waiters.remove(stream_id);
+        }
+        for stream_id in &flushed {
+            self.flush_waiters.remove(stream_id);
+        }
 
-        let mut ack_only_packet = true;
-        for frame in &packet.payload.frames {
-            match *frame {
-                Frame::Ack(..) => {},
-                _ => {
-                    ack_only_packet = false;
-                    break;
-                }
-            }
+        // waiters of either kind check again whether what they wait for happened
+        let ready: BTreeSet<u32> = writable.into_iter().chain(flushed).collect();
+        for stream_id in ready {
+            self.events.push(Event::StreamWritable(self.id, stream_id));
         }
+    }
+
+    /// Acknowledge a packet in the packet number space of the path it arrived on
+    fn save_ack_frame(&mut self, index: usize, packet: &packets::RegularPacketRef, source_address: net::SocketAddr) {
+        // TODO: implement ACK blocks
 
-        if ack_only_packet {
+        if Self::is_ack_only(&packet.payload) {
             return;
         }
 
         trace!("ACKing {:?}", packet);
 
-        self.last_consecutive_packet_number = packet.packet_number;
-
-        self.pending_packets.push(Self::create_packet(
-            &mut self.next_outgoing_packet_number,self.id, vec![
+        let connection_id = self.outgoing_connection_id();
+        let mut ack_packet = Self::create_packet(
+            &mut self.paths[index], connection_id, vec![
             Frame::Ack(ack::AckFrame {
                 // header
-                largest_acknowledged: self.last_consecutive_packet_number,
+                largest_acknowledged: packet.packet_number,
                 ack_delay: 0,
 
                 // ack block section
@@ -327,44 +1247,343 @@ impl Connection {
                 first_timestamp: None,
                 extra_timestamps: vec![],
             }),
-        ]));
+        ]);
+        ack_packet.peer_address = source_address;
+        self.pending_packets.push(ack_packet);
     }
 
     pub fn peer_address(&self) -> net::SocketAddr {
-        self.peer_address
+        self.paths[0].peer_address
+    }
+
+    /// Whether any path of the connection leads to `address`
+    pub fn has_peer_address(&self, address: net::SocketAddr) -> bool {
+        self.paths.iter().any(|path| path.peer_address == address)
     }
 
-    fn create_packet(next_packet_number: &mut u64, connection_id: u64, frames: Vec<Frame>) -> packets::Packet {
-        let packet_number = *next_packet_number;
-        *next_packet_number += 1;
+    fn send_parameters(&mut self) {
+        let connection_id = self.outgoing_connection_id();
+        let omit_connection_id = self.omit_incoming_connection_id;
+        let max_open_streams = self.max_incoming_streams;
+        let packet = Self::create_packet(
+            &mut self.paths[0], connection_id, vec![
+            Frame::Parameters(parameters::ParametersFrame {
+                omit_connection_id,
+                max_open_streams,
+            }),
+        ]);
+        self.pending_packets.push(packet);
+    }
 
-        let header = packets::PacketHeader {
+    /// Send what a lost packet carried again
+    ///
+    /// Stream data is queued in its stream to be sent by offset, other frames
+    /// go out in a new packet on the primary path.
+    fn retransmit(&mut self, sent_packet: SentPacket) {
+        for sent_stream_data in sent_packet.stream_data {
+            if let Some(stream) = self.streams.get_mut(&sent_stream_data.stream_id) {
+                stream.on_data_lost(sent_stream_data.offset, sent_stream_data.length);
+            }
+        }
+
+        if sent_packet.frames.is_empty() {
+            return;
+        }
+
+        let connection_id = self.outgoing_connection_id();
+        let index = self.primary_path_index();
+        let packet = Self::create_packet(&mut self.paths[index], connection_id, sent_packet.frames);
+        self.pending_packets.push(packet);
+    }
+
+    fn is_ack_only(payload: &packets::PacketPayloadRef) -> bool {
+        payload.frames.iter().all(|frame| matches!(*frame, FrameRef::Ack(..)))
+    }
+
+    /// Frames the peer has to receive eventually, whichever path they take
+    fn is_retransmittable(frame: &Frame) -> bool {
+        !matches!(
+            *frame,
+            Frame::Ack(..) |
+            Frame::ConnectionClose(..) |
+            Frame::Padding(..) |
+            Frame::PathChallenge(..) |
+            Frame::PathResponse(..) |
+            Frame::Ping(..)
+        )
+    }
+
+    fn outgoing_connection_id(&self) -> Option<u64> {
+        if self.omit_outgoing_connection_id {
+            None
+        } else {
+            Some(self.id)
+        }
+    }
+
+    fn path_index(&self, path_id: u8) -> Option<usize> {
+        self.paths.iter().position(|path| path.id == path_id)
+    }
+
+    /// The validated path with the lowest RTT, which carries control frames
+    fn primary_path_index(&self) -> usize {
+        (0..self.paths.len())
+            .filter(|&index| self.paths[index].is_validated())
+            .min_by_key(|&index| self.paths[index].rtt.smoothed_rtt())
+            .unwrap_or(0)
+    }
+
+    fn packet_header(path: &Path, connection_id: Option<u64>) -> packets::PacketHeader {
+        packets::PacketHeader {
             key_phase: false,
             packet_number_size: 4,
-            multipath: false,
+            multipath: path.id != 0,
 
-            connection_id: Some(connection_id),
-        };
+            connection_id,
+            path_id: path.id,
+        }
+    }
 
-        packets::Packet::Regular(packets::RegularPacket {
-            header: header,
+    fn create_packet(path: &mut Path, connection_id: Option<u64>, frames: Vec<Frame>) -> OutgoingPacket {
+        let packet_number = path.next_outgoing_packet_number;
+        path.next_outgoing_packet_number += 1;
+
+        let packet = packets::Packet::Regular(packets::RegularPacket {
+            header: Self::packet_header(path, connection_id),
 
             version: None,
-            packet_number: packet_number,
+            packet_number,
             payload: packets::PacketPayload {
-                frames: frames,
+                frames,
             },
-        })
+        });
+
+        OutgoingPacket {
+            path_id: path.id,
+            local_address: path.local_address,
+            peer_address: path.peer_address,
+            packet,
+        }
     }
 
-    fn extend_streams(&mut self, stream_id: u32) {
-        let next_max_id = self.streams.len() as u32;
-        if stream_id < next_max_id {
+    /// Send a CONNECTION_CLOSE and stop using the connection
+    ///
+    /// The close is repeated to packets arriving during the draining period.
+    pub fn close(&mut self, error_code: u32, reason: &str) {
+        if !self.is_open() {
             return;
         }
 
-        for stream_id in next_max_id..(stream_id + 1) {
-            self.streams.push(Stream::new(stream_id));
+        if error_code == codes::QUIC_NO_ERROR {
+            info!("Closing connection {}", self.id);
+        } else {
+            warn!("Closing connection {}, error code: {:#x}, reason: {}", self.id, error_code, reason);
+        }
+        self.state = ConnectionState::Closing;
+        self.close_code = Some(error_code);
+        self.pending_packets.clear();
+        self.write_waiters.clear();
+        self.flush_waiters.clear();
+        self.events.push(Event::ConnectionClosed(self.id, error_code));
+
+        let connection_id = self.outgoing_connection_id();
+        let index = self.primary_path_index();
+        let packet = Self::create_packet(
+            &mut self.paths[index], connection_id, vec![
+            Frame::ConnectionClose(connection_close::ConnectionCloseFrame {
+                error_code,
+                reason_phrase: Some(String::from(reason)),
+            }),
+        ]);
+        self.close_packet = Some(packet);
+        self.resend_close_packet = true;
+
+        self.schedule_draining_timeout();
+    }
+
+    /// Stop sending anything, after the peer closed the connection or went silent
+    fn drain(&mut self, error_code: u32) {
+        if !self.is_open() {
+            return;
+        }
+
+        self.state = ConnectionState::Draining;
+        self.close_code = Some(error_code);
+        self.pending_packets.clear();
+        self.write_waiters.clear();
+        self.flush_waiters.clear();
+        self.events.push(Event::ConnectionClosed(self.id, error_code));
+
+        self.schedule_draining_timeout();
+    }
+
+    /// Keep the connection around for a while to absorb packets still in flight
+    fn schedule_draining_timeout(&mut self) {
+        let index = self.primary_path_index();
+        let draining_period = self.paths[index].rtt.smoothed_rtt() * 3;
+        self.pending_events.push((draining_period, ScheduledEvent::DrainingTimeout(self.id)));
+    }
+
+    fn connection_send_space(&self) -> usize {
+        let buffered: usize = self.streams.values().map(|stream| stream.buffered_outgoing_len()).sum();
+        self.connection_send_buffer.saturating_sub(buffered)
+    }
+
+    /// Whether this endpoint initiates streams with the parity of `stream_id`
+    ///
+    /// Clients initiate odd streams and servers even ones, 0 is reserved.
+    fn is_local_stream(endpoint_role: EndpointRole, stream_id: u32) -> bool {
+        (stream_id % 2 == 1) == (endpoint_role == EndpointRole::Client)
+    }
+
+    /// Check a stream accessed by the application, opening it if it's ours
+    ///
+    /// Streams of the peer can be read from but not written to before the peer opens them.
+    fn local_stream(&mut self, stream_id: u32) -> Result<()> {
+        if !self.is_open() {
+            return Err(Error::ConnectionClosed(self.close_code.unwrap_or(codes::QUIC_NO_ERROR)));
+        }
+        if stream_id == 0 {
+            return Err(Error::InvalidStream);
+        }
+
+        if !Self::is_local_stream(self.endpoint_role, stream_id) {
+            if stream_id > self.largest_peer_stream_id {
+                return Err(Error::InvalidStream);
+            }
+            return Ok(());
+        }
+
+        if stream_id > self.largest_local_stream_id {
+            if self.peer_going_away {
+                return Err(Error::ConnectionClosed(codes::QUIC_PEER_GOING_AWAY));
+            }
+
+            let new_streams =
+                Self::streams_up_to(stream_id) - Self::streams_up_to(self.largest_local_stream_id);
+            if self.open_streams(true) as u64 + new_streams > self.max_outgoing_streams as u64 {
+                return Err(Error::TooManyOpenStreams);
+            }
+
+            let largest_local_stream_id = self.largest_local_stream_id;
+            self.insert_streams(largest_local_stream_id, stream_id);
+            self.largest_local_stream_id = stream_id;
+        }
+
+        Ok(())
+    }
+
+    /// Check a stream referenced by the peer, opening it if it's theirs
+    ///
+    /// Closes the connection and returns false if the peer broke the stream rules.
+    fn incoming_stream(&mut self, stream_id: u32) -> bool {
+        if stream_id == 0 {
+            self.close(codes::QUIC_INVALID_STREAM_ID, "Stream 0 is reserved");
+            return false;
+        }
+
+        if self.is_closed_stream(stream_id) {
+            debug!("Frame for closed stream {}, ignoring", stream_id);
+            return false;
+        }
+
+        if Self::is_local_stream(self.endpoint_role, stream_id) {
+            if stream_id > self.largest_local_stream_id {
+                self.close(codes::QUIC_INVALID_STREAM_ID, "Stream not opened yet");
+                return false;
+            }
+            return true;
+        }
+
+        if stream_id > self.largest_peer_stream_id {
+            // opening a stream implicitly opens the lower ones of the same parity
+            let new_streams =
+                Self::streams_up_to(stream_id) - Self::streams_up_to(self.largest_peer_stream_id);
+            if self.open_streams(false) as u64 + new_streams > self.max_incoming_streams as u64 {
+                self.close(codes::QUIC_TOO_MANY_OPEN_STREAMS, "Too many open streams");
+                return false;
+            }
+
+            let largest_peer_stream_id = self.largest_peer_stream_id;
+            self.insert_streams(largest_peer_stream_id, stream_id);
+            self.largest_peer_stream_id = stream_id;
+
+            let endpoint_role = self.endpoint_role;
+            let new_peer_streams: Vec<u32> =
+                self.streams.range(largest_peer_stream_id + 1..=stream_id)
+                .map(|(&new_stream_id, _)| new_stream_id)
+                .filter(|&new_stream_id| !Self::is_local_stream(endpoint_role, new_stream_id))
+                .collect();
+            for new_stream_id in new_peer_streams {
+                self.new_peer_streams.push_back(new_stream_id);
+                self.events.push(Event::StreamOpened(self.id, new_stream_id));
+            }
+        }
+
+        true
+    }
+
+    /// Streams opened by us or by the peer that aren't closed yet
+    fn open_streams(&self, local: bool) -> usize {
+        self.streams.values()
+            .filter(|stream| {
+                Self::is_local_stream(self.endpoint_role, stream.id) == local &&
+                stream.state != StreamState::Closed
+            })
+            .count()
+    }
+
+    /// Whether the stream was opened once and has been closed since
+    ///
+    /// Stream ids only grow, so every id up to the largest opened one
+    /// that is missing from the table belongs to a closed stream.
+    fn is_closed_stream(&self, stream_id: u32) -> bool {
+        let largest_stream_id = if Self::is_local_stream(self.endpoint_role, stream_id) {
+            self.largest_local_stream_id
+        } else {
+            self.largest_peer_stream_id
+        };
+
+        stream_id != 0 && stream_id <= largest_stream_id && !self.streams.contains_key(&stream_id)
+    }
+
+    /// Free the streams that were closed in both directions and fully read
+    fn collect_closed_streams(&mut self) {
+        let closed_stream_ids: Vec<u32> =
+            self.streams.values()
+            .filter(|stream| stream.state == StreamState::Closed && stream.is_finalized())
+            .map(|stream| stream.id)
+            .collect();
+
+        for stream_id in closed_stream_ids {
+            debug!("Stream {} closed, freeing it", stream_id);
+            self.streams.remove(&stream_id);
+        }
+    }
+
+    /// How many stream ids with the parity of `stream_id` lie in 1..=stream_id
+    fn streams_up_to(stream_id: u32) -> u64 {
+        if stream_id % 2 == 1 {
+            (stream_id as u64 + 1) / 2
+        } else {
+            stream_id as u64 / 2
+        }
+    }
+
+    /// Open the streams of one parity after `largest_stream_id` up to `stream_id`
+    fn insert_streams(&mut self, largest_stream_id: u32, stream_id: u32) {
+        let mut next_stream_id = if largest_stream_id == 0 {
+            2 - stream_id % 2
+        } else {
+            largest_stream_id + 2
+        };
+
+        while next_stream_id <= stream_id {
+            self.streams.insert(next_stream_id, Stream::new(next_stream_id));
+            next_stream_id = match next_stream_id.checked_add(2) {
+                Some(next_stream_id) => next_stream_id,
+                None => break,
+            };
         }
     }
 }
@@ -0,0 +1,41 @@
+use super::path::Migration;
+
+
+/// Something the application may want to act on, from `QuicEngine::poll_event`
+///
+/// Every event carries the connection id, stream events the stream id too.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// The peer's parameters arrived, on servers this is a new connection
+    ConnectionEstablished(u64),
+    /// The peer opened a stream, see `QuicEngine::accept_stream`
+    StreamOpened(u64, u32),
+    /// Data or the end of the stream arrived on a stream that had nothing to read
+    StreamReadable(u64, u32),
+    /// Acknowledgements freed send buffer space on a stream a write was cut short on,
+    /// or that was asked whether it's flushed
+    StreamWritable(u64, u32),
+    /// The peer finished sending on the stream
+    StreamFinished(u64, u32),
+    /// The peer abandoned the stream with an error code
+    StreamReset(u64, u32, u32),
+    /// The connection was closed by either side, with an error code
+    ConnectionClosed(u64, u32),
+    /// The peer moved to a new address, once it has been validated
+    PeerMigrated(u64, Migration),
+}
+
+impl Event {
+    pub fn connection_id(&self) -> u64 {
+        match *self {
+            Event::ConnectionEstablished(connection_id) |
+            Event::StreamOpened(connection_id, _) |
+            Event::StreamReadable(connection_id, _) |
+            Event::StreamWritable(connection_id, _) |
+            Event::StreamFinished(connection_id, _) |
+            Event::StreamReset(connection_id, _, _) |
+            Event::ConnectionClosed(connection_id, _) |
+            Event::PeerMigrated(connection_id, _) => connection_id,
+        }
+    }
+}
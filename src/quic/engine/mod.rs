@@ -1,4 +1,12 @@
+pub mod buffer_pool;
+pub mod config;
+pub mod congestion;
 pub mod connection;
+pub mod event;
+pub mod path;
+pub mod pmtu;
+pub mod rtt;
+mod send_buffer;
 pub mod stream;
 pub mod timer;
 pub mod udp_packet;
@@ -6,8 +14,8 @@ mod stream_buffer;
 #[cfg(test)]
 mod tests;
 
+use std::cmp::max;
 use std::collections::{VecDeque, HashMap};
-use std::io;
 use std::net;
 use std::time;
 
@@ -15,144 +23,205 @@ use rand;
 use rand::Rng;
 
 use quic::endpoint_role::EndpointRole;
-use quic::errors::{Result};
+use quic::errors::{Error, Result};
 use quic::packets;
-use quic::packets::frames::Frame;
-use self::connection::Connection;
+use self::buffer_pool::{BufferPool, DEFAULT_MAX_BUFFERS};
+use self::config::Config;
+use self::connection::{Connection, ConnectionState};
+use self::event::Event;
+use self::path::PathInfo;
 use self::udp_packet::{IncomingUdpPacket, OutgoingUdpPacket};
 
 
-#[derive(Clone, Debug, Default, PartialEq)]
+/// The protocol logic of an endpoint, without sockets or clocks
+///
+/// The caller hands in packets and the current time, and takes out packets
+/// to send, events and the time the engine wants `handle_timeout` to be called.
+/// Calls that don't take the time use the latest one handed in.
+#[derive(Clone, Debug, PartialEq)]
 pub struct QuicEngine<T: timer::Timer> {
     timer: T,
+    config: Config,
+    now: time::Instant,
 
     accept_connections: bool,
     connections: HashMap<u64, Connection>,
-    new_connection_ids: VecDeque<u64>,
+    events: VecDeque<Event>,
 
     pending_packets: Vec<OutgoingUdpPacket>,
+    // for the payloads of pending packets, and incoming ones once handled
+    buffer_pool: BufferPool,
 }
 
 impl <T: timer::Timer> QuicEngine<T> {
     pub fn new(timer: T, accept_connections: bool) -> QuicEngine<T> {
+        QuicEngine::with_config(timer, accept_connections, Config::default())
+    }
+
+    pub fn with_config(timer: T, accept_connections: bool, config: Config) -> QuicEngine<T> {
+        // paths never go below the base packet size, whatever the config says
+        let buffer_size = max(config.max_packet_size, pmtu::BASE_PACKET_SIZE);
+        let buffer_pool = BufferPool::new(buffer_size, DEFAULT_MAX_BUFFERS);
+
         QuicEngine {
-            timer: timer,
+            timer,
+            config,
+            // until the caller tells the time
+            now: time::Instant::now(),
 
-            accept_connections: accept_connections,
+            accept_connections,
             connections: HashMap::new(),
-            new_connection_ids: VecDeque::new(),
+            events: VecDeque::new(),
 
             pending_packets: Vec::new(),
+            buffer_pool,
         }
     }
 
-    pub fn initiate_connection(&mut self, addr: net::SocketAddr) -> u64 {
+    pub fn initiate_connection(&mut self, now: time::Instant, addr: net::SocketAddr) -> u64 {
+        self.now = now;
         let mut rng = rand::thread_rng();
         let connection_id = rng.gen();
 
-        let connection = Connection::new(connection_id, EndpointRole::Client, addr);
+        let connection = Connection::new(connection_id, EndpointRole::Client, addr, None, &self.config, now);
         self.connections.insert(connection_id, connection);
 
         debug!("Initiating connection (id: {})", connection_id);
+        self.flush_buffered_data();
+
         connection_id
     }
 
-    fn accept_connection(&mut self, connection_id: u64, addr: net::SocketAddr) {
-        let connection = Connection::new(connection_id, EndpointRole::Server, addr);
+    fn accept_connection(&mut self, connection_id: u64, addr: net::SocketAddr, local_address: Option<net::SocketAddr>) {
+        let connection = Connection::new(connection_id, EndpointRole::Server, addr, local_address, &self.config, self.now);
         self.connections.insert(connection_id, connection);
-        self.new_connection_ids.push_back(connection_id);
     }
 
-    pub fn have_connections(&self) -> bool {
-        !self.new_connection_ids.is_empty()
+    /// Take the next thing that happened to a connection or one of its streams
+    pub fn poll_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
     }
 
-    pub fn pop_new_connection(&mut self) -> u64 {
-        self.new_connection_ids.pop_front().unwrap()
-    }
-
-    pub fn handle_incoming_packet(&mut self, packet: IncomingUdpPacket) {
-        let endpoint_role = if self.accept_connections {
-            EndpointRole::Server
-        } else {
-            EndpointRole::Client
-        };
-
-        let source_address = packet.source_address;
-        let packet = match packets::Packet::decode(&mut io::Cursor::new(packet.payload), endpoint_role) {
-            Ok(packet) => packet,
-            Err(e) => {
-                error!("Error while decoding incoming packet: {}", e);
-                return;
-            }
-        };
-
-        match packet {
-            packets::Packet::PublicReset(..) => {
-                unimplemented!()
-            },
-            packets::Packet::Regular(ref regular_packet) => {
-                match regular_packet.header.connection_id {
-                    Some(connection_id) => {
-                        if !self.connections.contains_key(&connection_id) {
-                            if self.accept_connections {
-                                debug!("Registering connection (id: {})", connection_id);
-                                self.accept_connection(connection_id, source_address);
-                            } else {
-                                warn!("Dropping a packet with unknown connection id, can't accept");
-                                return;
-                            }
-                        }
-
-                        let connection = self.connections.get_mut(&connection_id).unwrap();
-                        connection.handle_regular_packet(regular_packet, source_address);
-                    },
-                    None => unimplemented!(),
-                }
-            },
-            packets::Packet::VersionNegotiation(..) => {
-                unimplemented!()
-            },
-        }
+    pub fn handle_incoming_packet(&mut self, now: time::Instant, packet: IncomingUdpPacket) {
+        self.now = now;
+        self.handle_datagram(&packet);
+        self.buffer_pool.give_back(packet.payload);
 
         self.flush_buffered_data();
     }
 
-    pub fn handle_due_events(&mut self) {
-        for event in self.timer.pop_due_events() {
+    /// Handle the scheduled events due at `now`
+    pub fn handle_timeout(&mut self, now: time::Instant) {
+        self.now = now;
+
+        for event in self.timer.pop_due_events(now) {
             trace!("Handling event: {:?}", event);
 
+            // events of reaped connections are dropped
             match event {
-                timer::ScheduledEvent::ResendUnackedPacket(packet) => {
-                    let connection_id = packet.connection_id().unwrap();
-                    let connection = self.connections.get_mut(&connection_id).unwrap();
-                    connection.check_unacked_packet(packet);
-                }
+                timer::ScheduledEvent::ResendUnackedPacket(connection_id, path_id, packet_number) => {
+                    if let Some(connection) = self.connections.get_mut(&connection_id) {
+                        connection.check_unacked_packet(path_id, packet_number);
+                    }
+                },
+                timer::ScheduledEvent::PathValidationTimeout(connection_id, challenge_data) => {
+                    if let Some(connection) = self.connections.get_mut(&connection_id) {
+                        connection.check_path_validation(challenge_data);
+                    }
+                },
+                timer::ScheduledEvent::MtuProbeTimeout(connection_id, path_id, packet_number) => {
+                    if let Some(connection) = self.connections.get_mut(&connection_id) {
+                        connection.check_mtu_probe(path_id, packet_number);
+                    }
+                },
+                timer::ScheduledEvent::IdleTimeout(connection_id) => {
+                    if let Some(connection) = self.connections.get_mut(&connection_id) {
+                        connection.check_idle_timeout(now);
+                    }
+                },
+                timer::ScheduledEvent::DrainingTimeout(connection_id) => {
+                    if let Some(connection) = self.connections.get_mut(&connection_id) {
+                        connection.on_draining_timeout();
+                    }
+                },
             }
         }
 
         self.flush_buffered_data();
+        self.reap_closed_connections();
     }
 
-    pub fn write(&mut self, connection_id: u64, stream_id: u32, buf: &[u8]) -> Result<()> {
-        {
-            let connection =
-                self.connections.get_mut(&connection_id)
-                .expect("Invalid connection id");
-            connection.write(stream_id, buf)?;
-        }
+    /// When `handle_timeout` should be called next, if anything is scheduled
+    pub fn next_timeout(&self) -> Option<time::Instant> {
+        self.timer.next_timeout()
+    }
+
+    /// The latest time handed in by the caller
+    pub fn now(&self) -> time::Instant {
+        self.now
+    }
 
+    /// Close a connection with `error_code`, sending a CONNECTION_CLOSE to the peer
+    pub fn close_connection(&mut self, connection_id: u64, error_code: u32) -> Result<()> {
+        self.connection_mut(connection_id)?.close(error_code, "");
         self.flush_buffered_data();
 
         Ok(())
     }
 
+    /// The state of a connection, `None` once it has been reaped
+    pub fn connection_state(&self, connection_id: u64) -> Option<ConnectionState> {
+        self.connections.get(&connection_id).map(|connection| connection.state())
+    }
+
+    /// Buffer data to send, returns how much of it fit into the send buffers
+    pub fn write(&mut self, connection_id: u64, stream_id: u32, buf: &[u8]) -> Result<usize> {
+        let written_size = self.connection_mut(connection_id)?.write(stream_id, buf)?;
+
+        self.flush_buffered_data();
+
+        Ok(written_size)
+    }
+
+    /// Open a stream with the next locally initiated id
+    pub fn open_stream(&mut self, connection_id: u64) -> Result<u32> {
+        self.connection_mut(connection_id)?.open_stream()
+    }
+
+    /// Take the next stream opened by the peer, if there is one
+    pub fn accept_stream(&mut self, connection_id: u64) -> Result<Option<u32>> {
+        self.connection_mut(connection_id)?.accept_stream()
+    }
+
+    // a reaped connection fails right away
+    pub fn has_new_streams(&self, connection_id: u64) -> bool {
+        match self.connections.get(&connection_id) {
+            Some(connection) => connection.has_new_streams(),
+            None => true,
+        }
+    }
+
+    /// The offset up to which all data written to a stream has been acknowledged
+    pub fn acked_offset(&self, connection_id: u64, stream_id: u32) -> Result<u64> {
+        self.connection(connection_id)?.acked_offset(stream_id)
+    }
+
+    /// Whether everything written to a stream was acknowledged, `StreamWritable` tells when to ask again
+    pub fn is_flushed(&mut self, connection_id: u64, stream_id: u32) -> Result<bool> {
+        self.connection_mut(connection_id)?.is_flushed(stream_id)
+    }
+
+    // writing to a reaped connection fails right away
+    pub fn is_writable(&self, connection_id: u64, stream_id: u32) -> bool {
+        match self.connections.get(&connection_id) {
+            Some(connection) => connection.is_writable(stream_id),
+            None => true,
+        }
+    }
+
     pub fn finalize_outgoing_stream(&mut self, connection_id: u64, stream_id: u32) -> Result<()> {
         {
-            let connection =
-                self.connections.get_mut(&connection_id)
-                .expect("Invalid connection id");
-            connection.finalize_outgoing_stream(stream_id)?;
+            self.connection_mut(connection_id)?.finalize_outgoing_stream(stream_id)?;
         }
 
         self.flush_buffered_data();
@@ -164,87 +233,268 @@ impl <T: timer::Timer> QuicEngine<T> {
         self.pending_packets.drain(..).collect()
     }
 
+    /// A buffer for an incoming datagram, see `BufferPool::take`
+    pub fn take_buffer(&mut self) -> Vec<u8> {
+        self.buffer_pool.take()
+    }
+
+    /// Hand back the payload of a sent packet for reuse
+    pub fn recycle_buffer(&mut self, buffer: Vec<u8>) {
+        self.buffer_pool.give_back(buffer);
+    }
+
     pub fn timer_ref(&self) -> &T {
         &self.timer
     }
 
+    pub fn timer_mut(&mut self) -> &mut T {
+        &mut self.timer
+    }
+
+    // a reaped connection has nothing left to wait for
     pub fn is_finalized(&self, connection_id: u64) -> bool {
-        let connection =
-            self.connections.get(&connection_id)
-            .expect("Invalid connection id");
+        match self.connections.get(&connection_id) {
+            Some(connection) => connection.is_finalized(),
+            None => true,
+        }
+    }
 
-        connection.is_finalized()
+    pub fn peer_address(&self, connection_id: u64) -> Result<net::SocketAddr> {
+        Ok(self.connection(connection_id)?.peer_address())
     }
 
-    pub fn data_available(&self, connection_id: u64, stream_id: u32) -> bool {
-        let connection =
-            self.connections.get(&connection_id)
-            .expect("Invalid connection id");
+    /// Open another path on a client connection, from `local_address` if given
+    pub fn open_path(
+            &mut self,
+            connection_id: u64,
+            local_address: Option<net::SocketAddr>,
+            peer_address: net::SocketAddr) -> Result<u8> {
+        let path_id = {
+            self.connection_mut(connection_id)?.open_path(local_address, peer_address)?
+        };
+
+        self.flush_buffered_data();
 
-        connection.data_available(stream_id)
+        Ok(path_id)
     }
 
-    pub fn any_data_available(&self, connection_id: u64) -> bool {
-        let connection =
-            self.connections.get(&connection_id)
-            .expect("Invalid connection id");
+    pub fn paths(&self, connection_id: u64) -> Result<Vec<PathInfo>> {
+        Ok(self.connection(connection_id)?.paths())
+    }
 
-        connection.any_data_available()
+    // reaped connections report data, so that readers wake up and get the error
+    pub fn data_available(&self, connection_id: u64, stream_id: u32) -> bool {
+        match self.connections.get(&connection_id) {
+            Some(connection) => connection.data_available(stream_id),
+            None => true,
+        }
     }
 
-    pub fn read(&mut self, connection_id: u64, stream_id: u32, buf: &mut [u8]) -> Result<usize> {
-        let read_size = {
-            let connection =
-                self.connections.get_mut(&connection_id)
-                .expect("Invalid connection id");
+    pub fn any_data_available(&self, connection_id: u64) -> bool {
+        match self.connections.get(&connection_id) {
+            Some(connection) => connection.any_data_available(),
+            None => true,
+        }
+    }
 
-            connection.read(stream_id, buf)
-        };
+    pub fn read(&mut self, connection_id: u64, stream_id: u32, buf: &mut [u8]) -> Result<usize> {
+        let read_size = self.connection_mut(connection_id)?.read(stream_id, buf);
 
         self.flush_buffered_data();
 
         read_size
     }
 
+    fn connection(&self, connection_id: u64) -> Result<&Connection> {
+        self.connections.get(&connection_id).ok_or(Error::InvalidConnection)
+    }
+
+    fn connection_mut(&mut self, connection_id: u64) -> Result<&mut Connection> {
+        self.connections.get_mut(&connection_id).ok_or(Error::InvalidConnection)
+    }
+
+    /// Forget connections whose draining period is over
+    fn reap_closed_connections(&mut self) {
+        let closed_ids: Vec<u64> =
+            self.connections.iter()
+            .filter(|&(_, connection)| connection.state() == ConnectionState::Closed)
+            .map(|(&connection_id, _)| connection_id)
+            .collect();
+
+        for connection_id in closed_ids {
+            debug!("Reaping connection {}", connection_id);
+            self.connections.remove(&connection_id);
+            self.timer.cancel_connection(connection_id);
+        }
+    }
+
+    /// Decode a datagram and hand it to its connection
+    fn handle_datagram(&mut self, packet: &IncomingUdpPacket) {
+        let now = self.now;
+        let endpoint_role = if self.accept_connections {
+            EndpointRole::Server
+        } else {
+            EndpointRole::Client
+        };
+
+        let source_address = packet.source_address;
+        let local_address = packet.destination_address;
+        let datagram_size = packet.payload.len();
+        // stream data is only copied once it's buffered in its stream
+        let packet = match packets::PacketRef::decode(&packet.payload, endpoint_role) {
+            Ok(packet) => packet,
+            Err(e) => {
+                error!("Error while decoding incoming packet: {}", e);
+                return;
+            }
+        };
+
+        match packet {
+            packets::PacketRef::PublicReset(ref public_reset_packet) => {
+                let connection_id = match self.packet_connection_id(&public_reset_packet.header, source_address) {
+                    Some(connection_id) => connection_id,
+                    None => return,
+                };
+
+                match self.connections.get_mut(&connection_id) {
+                    Some(connection) => connection.handle_public_reset(),
+                    None => debug!("Dropping a public reset for unknown connection {}", connection_id),
+                }
+            },
+            packets::PacketRef::Regular(ref regular_packet) => {
+                let connection_id = match self.packet_connection_id(&regular_packet.header, source_address) {
+                    Some(connection_id) => connection_id,
+                    None => return,
+                };
+
+                if !self.connections.contains_key(&connection_id) {
+                    // late packets of a reaped connection must not bring it back
+                    let opens_connection = regular_packet.payload.frames.iter().any(|frame| matches!(*frame, packets::frames::FrameRef::Parameters(..)));
+
+                    if !opens_connection {
+                        debug!("Dropping a packet for unknown connection {}", connection_id);
+                        return;
+                    } else if self.accept_connections {
+                        debug!("Registering connection (id: {})", connection_id);
+                        self.accept_connection(connection_id, source_address, local_address);
+                    } else {
+                        warn!("Dropping a packet with unknown connection id, can't accept");
+                        return;
+                    }
+                }
+
+                let connection = match self.connections.get_mut(&connection_id) {
+                    Some(connection) => connection,
+                    None => return,
+                };
+                connection.handle_regular_packet(regular_packet, source_address, local_address, datagram_size, now);
+            },
+            packets::PacketRef::VersionNegotiation(ref version_negotiation_packet) => {
+                let connection_id = match self.packet_connection_id(&version_negotiation_packet.header, source_address) {
+                    Some(connection_id) => connection_id,
+                    None => return,
+                };
+
+                match self.connections.get_mut(&connection_id) {
+                    Some(connection) => connection.handle_version_negotiation(&version_negotiation_packet.versions),
+                    None => debug!("Dropping a version negotiation for unknown connection {}", connection_id),
+                }
+            },
+        }
+    }
+
+    /// The connection a packet belongs to, by its connection ID or else its source address
+    fn packet_connection_id(&self, header: &packets::PacketHeader, source_address: net::SocketAddr) -> Option<u64> {
+        match header.connection_id {
+            Some(connection_id) => Some(connection_id),
+            None => {
+                let connection_id = self.find_connection_by_address(source_address);
+                if connection_id.is_none() {
+                    warn!("Dropping a packet without connection id from {}", source_address);
+                }
+                connection_id
+            },
+        }
+    }
+
+    /// Route a packet that arrived without a connection ID by its source address
+    ///
+    /// This only works for connections that asked their peer to omit the ID
+    /// and is ambiguous when several of them talk to the same peer.
+    fn find_connection_by_address(&self, address: net::SocketAddr) -> Option<u64> {
+        if self.accept_connections {
+            return None;
+        }
+
+        let mut matching_ids =
+            self.connections.iter()
+            .filter(|&(_, connection)| {
+                connection.omits_incoming_connection_id() && connection.has_peer_address(address)
+            })
+            .map(|(&connection_id, _)| connection_id);
+
+        match (matching_ids.next(), matching_ids.next()) {
+            (Some(connection_id), None) => Some(connection_id),
+            (Some(..), Some(..)) => {
+                warn!("Several connections to {}, can't route by address", address);
+                None
+            },
+            (None, _) => None,
+        }
+    }
+
     fn flush_buffered_data(&mut self) {
+        let now = self.now;
+
         for connection in self.connections.values_mut() {
-            let peer_address = connection.peer_address();
-            for packet in connection.drain_outgoing_packets() {
-                let ack_only_packet = match packet {
-                    packets::Packet::Regular(ref regular_packet) => {
-                        let mut ack_only_packet = true;
-                        for frame in &regular_packet.payload.frames {
-                            match *frame {
-                                Frame::Ack(..) => {},
-                                _ => {
-                                    ack_only_packet = false;
-                                    break;
-                                }
-                            }
-                        }
-
-                        ack_only_packet
+            for outgoing in connection.drain_outgoing_packets() {
+                let mut buffer = self.buffer_pool.take();
+                match outgoing.packet.encode_to_slice(&mut buffer) {
+                    Ok(size) => buffer.truncate(size),
+                    // connections size packets for their path, the network would drop this one
+                    Err(e) => {
+                        error!("Dropping a packet that doesn't fit {} bytes: {:?}", buffer.len(), e);
+                        debug_assert!(false, "Packet larger than the maximum packet size");
+                        self.buffer_pool.give_back(buffer);
+                        continue;
                     },
-                    _ => false,
-                };
+                }
+
+                if !connection.can_send(&outgoing, buffer.len()) {
+                    debug!("Not sending a packet to unvalidated address {}", outgoing.peer_address);
+                    self.buffer_pool.give_back(buffer);
+                    continue;
+                }
 
-                if !ack_only_packet {
-                    connection.unacked_packet_numbers.insert(packet.packet_number().unwrap());
+                let path_id = outgoing.path_id;
+                let packet_number = outgoing.packet.packet_number().unwrap();
+                let destination_address = outgoing.peer_address;
+                let source_address = outgoing.local_address;
 
-                    self.timer.schedule(
-                        time::Duration::from_millis(100),
-                        timer::ScheduledEvent::ResendUnackedPacket(packet.clone()),
+                // the connection keeps what it needs to retransmit, not the packet
+                if let Some(retransmission_timeout) = connection.on_packet_sent(outgoing, buffer.len(), now) {
+                    let token = self.timer.schedule(
+                        now + retransmission_timeout,
+                        timer::ScheduledEvent::ResendUnackedPacket(connection.id(), path_id, packet_number),
                     );
+                    connection.on_retransmission_scheduled(path_id, packet_number, token);
                 }
 
-                let mut buffer = vec![];
-                packet.encode(&mut buffer).unwrap();
-
                 self.pending_packets.push(OutgoingUdpPacket {
-                    destination_address: peer_address,
+                    source_address,
+                    destination_address,
                     payload: buffer,
                 });
             }
+
+            for (delay, event) in connection.drain_scheduled_events() {
+                self.timer.schedule(now + delay, event);
+            }
+            for token in connection.drain_cancelled_timers() {
+                self.timer.cancel(token);
+            }
+
+            self.events.extend(connection.drain_events());
         }
     }
 }
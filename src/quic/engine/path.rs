@@ -0,0 +1,301 @@
+use std::cmp::min;
+use std::collections::HashMap;
+use std::net;
+use std::time;
+
+use quic::packets::frames::Frame;
+use super::congestion::CongestionController;
+use super::pmtu::MtuDiscovery;
+use super::rtt::RttEstimator;
+use super::timer::TimerToken;
+
+
+/// How many times more data than received may be sent to an unvalidated address
+pub const AMPLIFICATION_FACTOR: u64 = 3;
+
+/// PATH_CHALLENGE attempts before a path or address is abandoned
+pub const MAX_PATH_CHALLENGES: u32 = 3;
+
+/// Paths a single connection may use at once
+pub const MAX_PATHS: usize = 8;
+
+/// Consecutive retransmission timeouts after which the timeout stops doubling
+pub const MAX_RTO_BACKOFF: u32 = 6;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathState {
+    Validating,
+    Validated,
+}
+
+
+/// Challenge/response state of a peer address
+#[derive(Clone, Debug, PartialEq)]
+pub struct Validation {
+    pub state: PathState,
+    /// Addresses we picked ourselves can't be used to amplify an attack
+    pub limit_amplification: bool,
+
+    challenge_data: u64,
+    challenge_count: u32,
+    bytes_received: u64,
+    bytes_sent: u64,
+}
+
+impl Validation {
+    /// An address that needs no validation, e.g. the one the connection started on
+    pub fn validated() -> Validation {
+        Validation {
+            state: PathState::Validated,
+            limit_amplification: true,
+
+            challenge_data: 0,
+            challenge_count: 0,
+            bytes_received: 0,
+            bytes_sent: 0,
+        }
+    }
+
+    pub fn new(challenge_data: u64) -> Validation {
+        Validation {
+            state: PathState::Validating,
+            challenge_data,
+
+            ..Validation::validated()
+        }
+    }
+
+    pub fn is_validated(&self) -> bool {
+        self.state == PathState::Validated
+    }
+
+    pub fn challenge_data(&self) -> u64 {
+        self.challenge_data
+    }
+
+    /// Count a PATH_CHALLENGE, returns false once the attempts are exhausted
+    pub fn on_challenge_sent(&mut self) -> bool {
+        if self.challenge_count >= MAX_PATH_CHALLENGES {
+            return false;
+        }
+
+        self.challenge_count += 1;
+        true
+    }
+
+    /// Check a PATH_RESPONSE, marking the address validated if it echoes our challenge
+    pub fn on_response_received(&mut self, data: u64) -> bool {
+        if self.state == PathState::Validating && data == self.challenge_data {
+            self.state = PathState::Validated;
+            return true;
+        }
+
+        false
+    }
+
+    pub fn on_datagram_received(&mut self, size: usize) {
+        self.bytes_received += size as u64;
+    }
+
+    pub fn on_datagram_sent(&mut self, size: usize) {
+        self.bytes_sent += size as u64;
+    }
+
+    /// Whether a datagram of `size` bytes can be sent without breaking the amplification limit
+    pub fn can_send(&self, size: usize) -> bool {
+        self.is_validated() || !self.limit_amplification ||
+            self.bytes_sent + size as u64 <= AMPLIFICATION_FACTOR * self.bytes_received
+    }
+}
+
+
+/// Stream data carried by a sent packet, the bytes themselves stay in the stream
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SentStreamData {
+    pub stream_id: u32,
+    pub offset: u64,
+    pub length: usize,
+}
+
+
+/// What an unacknowledged packet carried, to retransmit it if it gets lost
+#[derive(Clone, Debug, PartialEq)]
+pub struct SentPacket {
+    pub size: usize,
+    pub time_sent: time::Instant,
+    pub frames: Vec<Frame>,
+    pub stream_data: Vec<SentStreamData>,
+    // cancelled once the packet is acknowledged
+    pub retransmission_timer: Option<TimerToken>,
+}
+
+
+/// A local/peer address pair with its own packet number space,
+/// congestion controller and RTT estimate
+#[derive(Clone, Debug, PartialEq)]
+pub struct Path {
+    pub id: u8,
+    pub local_address: Option<net::SocketAddr>,
+    pub peer_address: net::SocketAddr,
+    pub validation: Validation,
+    pub congestion: CongestionController,
+    pub rtt: RttEstimator,
+    pub mtu: MtuDiscovery,
+
+    pub next_outgoing_packet_number: u64,
+    pub largest_received_packet_number: u64,
+    pub unacked_packets: HashMap<u64, SentPacket>,
+    // retransmission timeouts since the last acknowledgement,
+    // losses of packets sent before the last one that counted don't count again
+    pub rto_backoff: u32,
+    pub rto_backoff_end: Option<u64>,
+    pub lost_packets: u64,
+}
+
+impl Path {
+    pub fn new(
+            id: u8,
+            local_address: Option<net::SocketAddr>,
+            peer_address: net::SocketAddr,
+            validation: Validation,
+            max_packet_size: usize) -> Path {
+        Path {
+            id,
+            local_address,
+            peer_address,
+            validation,
+            congestion: CongestionController::new(),
+            rtt: RttEstimator::new(),
+            mtu: MtuDiscovery::new(max_packet_size),
+
+            next_outgoing_packet_number: 1,
+            largest_received_packet_number: 0,
+            unacked_packets: HashMap::new(),
+            rto_backoff: 0,
+            rto_backoff_end: None,
+            lost_packets: 0,
+        }
+    }
+
+    pub fn is_validated(&self) -> bool {
+        self.validation.is_validated()
+    }
+
+    pub fn largest_sent_packet_number(&self) -> u64 {
+        self.next_outgoing_packet_number - 1
+    }
+
+    /// The retransmission timeout for packets sent now, doubled for every timeout in a row
+    pub fn retransmission_timeout(&self) -> time::Duration {
+        self.rtt.retransmission_timeout() * (1 << min(self.rto_backoff, MAX_RTO_BACKOFF))
+    }
+
+    pub fn on_packet_sent(&mut self, packet_number: u64, sent_packet: SentPacket) {
+        self.congestion.on_packet_sent(sent_packet.size);
+        self.unacked_packets.insert(packet_number, sent_packet);
+    }
+
+    pub fn set_retransmission_timer(&mut self, packet_number: u64, token: TimerToken) {
+        if let Some(sent_packet) = self.unacked_packets.get_mut(&packet_number) {
+            sent_packet.retransmission_timer = Some(token);
+        }
+    }
+
+    /// Forget an acknowledged packet, returns what it carried unless it was lost already
+    pub fn on_packet_acked(&mut self, packet_number: u64, now: time::Instant) -> Option<SentPacket> {
+        match self.unacked_packets.remove(&packet_number) {
+            Some(sent_packet) => {
+                if now >= sent_packet.time_sent {
+                    self.rtt.update(now - sent_packet.time_sent);
+                }
+                self.rto_backoff = 0;
+                self.congestion.on_packet_acked(packet_number, sent_packet.size);
+                self.mtu.on_packet_acked(packet_number, sent_packet.size);
+                Some(sent_packet)
+            },
+            // PMTU probes aren't tracked as unacked packets
+            None => {
+                self.mtu.on_packet_acked(packet_number, 0);
+                None
+            },
+        }
+    }
+
+    /// Forget a packet whose retransmission timer fired, returns what it carried unless it was acknowledged already
+    pub fn on_packet_lost(&mut self, packet_number: u64) -> Option<SentPacket> {
+        let largest_sent = self.largest_sent_packet_number();
+        let sent_packet = self.unacked_packets.remove(&packet_number);
+        if let Some(ref sent_packet) = sent_packet {
+            let backs_off = match self.rto_backoff_end {
+                Some(backoff_end) => packet_number > backoff_end,
+                None => true,
+            };
+            if backs_off {
+                self.rto_backoff = self.rto_backoff.saturating_add(1);
+                self.rto_backoff_end = Some(largest_sent);
+            }
+            self.lost_packets += 1;
+            self.congestion.on_packet_lost(packet_number, sent_packet.size, largest_sent);
+            self.mtu.on_packet_lost(sent_packet.size);
+        }
+
+        sent_packet
+    }
+
+    /// Switch to a new peer address, starting over with congestion, RTT and MTU state
+    pub fn migrate(&mut self, peer_address: net::SocketAddr) {
+        self.peer_address = peer_address;
+        self.validation = Validation::validated();
+        self.congestion = CongestionController::new();
+        self.rtt = RttEstimator::new();
+        self.rto_backoff = 0;
+        self.mtu = MtuDiscovery::new(self.mtu.upper_bound());
+    }
+
+    pub fn info(&self) -> PathInfo {
+        PathInfo {
+            id: self.id,
+            local_address: self.local_address,
+            peer_address: self.peer_address,
+            validated: self.is_validated(),
+            smoothed_rtt: self.rtt.smoothed_rtt(),
+            congestion_window: self.congestion.congestion_window(),
+            max_packet_size: self.mtu.max_packet_size(),
+            lost_packets: self.lost_packets,
+        }
+    }
+}
+
+
+/// A new peer address seen on an existing path, not validated yet
+#[derive(Clone, Debug, PartialEq)]
+pub struct AddressCandidate {
+    pub path_id: u8,
+    pub address: net::SocketAddr,
+    pub validation: Validation,
+}
+
+
+/// A snapshot of a path for the application
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathInfo {
+    pub id: u8,
+    pub local_address: Option<net::SocketAddr>,
+    pub peer_address: net::SocketAddr,
+    pub validated: bool,
+    pub smoothed_rtt: time::Duration,
+    pub congestion_window: usize,
+    pub max_packet_size: usize,
+    /// Packets declared lost after their retransmission timeout
+    pub lost_packets: u64,
+}
+
+
+/// The peer of a connection moved to a new, validated address
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Migration {
+    pub path_id: u8,
+    pub old_address: net::SocketAddr,
+    pub new_address: net::SocketAddr,
+}
@@ -0,0 +1,133 @@
+use std::cmp::max;
+use std::time;
+
+
+/// Packet size every path is assumed to support
+pub const BASE_PACKET_SIZE: usize = 1200;
+
+/// Consecutive lost probes of one size before it is considered too large
+pub const MAX_PROBE_ATTEMPTS: u32 = 2;
+
+/// Consecutive lost packets of the discovered size that indicate a black hole
+pub const BLACK_HOLE_THRESHOLD: u32 = 3;
+
+/// The search stops once the bounds are this close
+const SEARCH_PRECISION: usize = 16;
+
+const MIN_PROBE_TIMEOUT_MS: u64 = 100;
+
+
+/// Path MTU discovery state of a single path
+///
+/// Binary searches between the confirmed packet size and the configured maximum
+/// by sending padded probes, one at a time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MtuDiscovery {
+    upper_bound: usize,
+    max_packet_size: usize,
+    search_high: usize,
+    probe: Option<(u64, usize)>,
+    probe_attempts: u32,
+    lost_large_packets: u32,
+}
+
+impl MtuDiscovery {
+    /// Start from the base size, probing up to `upper_bound` bytes
+    ///
+    /// An upper bound below the base size leaves nothing to probe.
+    pub fn new(upper_bound: usize) -> MtuDiscovery {
+        MtuDiscovery {
+            upper_bound,
+            max_packet_size: BASE_PACKET_SIZE,
+            search_high: max(upper_bound, BASE_PACKET_SIZE),
+            probe: None,
+            probe_attempts: 0,
+            lost_large_packets: 0,
+        }
+    }
+
+    pub fn upper_bound(&self) -> usize {
+        self.upper_bound
+    }
+
+    /// Largest packet known to get through
+    pub fn max_packet_size(&self) -> usize {
+        self.max_packet_size
+    }
+
+    /// Size of the next probe, if one should be sent now
+    pub fn next_probe_size(&self) -> Option<usize> {
+        if self.probe.is_some() || self.search_high - self.max_packet_size < SEARCH_PRECISION {
+            return None;
+        }
+
+        Some(self.max_packet_size + (self.search_high - self.max_packet_size + 1) / 2)
+    }
+
+    pub fn on_probe_sent(&mut self, packet_number: u64, size: usize) {
+        self.probe = Some((packet_number, size));
+    }
+
+    pub fn probe_timeout(rtt: time::Duration) -> time::Duration {
+        max(rtt * 3, time::Duration::from_millis(MIN_PROBE_TIMEOUT_MS))
+    }
+
+    pub fn on_packet_acked(&mut self, packet_number: u64, size: usize) {
+        if self.is_probe_sized(size) {
+            self.lost_large_packets = 0;
+        }
+
+        match self.probe {
+            Some((probe_packet_number, probe_size)) if probe_packet_number == packet_number => {
+                debug!("PMTU probe of {} bytes acknowledged", probe_size);
+                self.max_packet_size = max(self.max_packet_size, probe_size);
+                self.probe = None;
+                self.probe_attempts = 0;
+            },
+            _ => {},
+        }
+    }
+
+    /// A probe went unacknowledged, returns whether the search bound was lowered
+    pub fn on_probe_lost(&mut self, packet_number: u64) -> bool {
+        let probe_size = match self.probe {
+            Some((probe_packet_number, probe_size)) if probe_packet_number == packet_number => probe_size,
+            _ => return false,
+        };
+        self.probe = None;
+
+        self.probe_attempts += 1;
+        if self.probe_attempts < MAX_PROBE_ATTEMPTS {
+            return false;
+        }
+
+        debug!("PMTU probe of {} bytes lost, lowering the search bound", probe_size);
+        self.probe_attempts = 0;
+        self.search_high = probe_size - 1;
+
+        true
+    }
+
+    /// A regular packet was lost, falls back to the base size if packets of the discovered size keep vanishing
+    ///
+    /// Smaller packets would fit a smaller path MTU as well, their losses say nothing about it.
+    pub fn on_packet_lost(&mut self, size: usize) {
+        if !self.is_probe_sized(size) {
+            return;
+        }
+
+        self.lost_large_packets += 1;
+        if self.lost_large_packets >= BLACK_HOLE_THRESHOLD {
+            warn!("Packets of {} bytes are black-holed, falling back to {}", size, BASE_PACKET_SIZE);
+            self.search_high = max(self.max_packet_size - 1, BASE_PACKET_SIZE);
+            self.max_packet_size = BASE_PACKET_SIZE;
+            self.probe = None;
+            self.probe_attempts = 0;
+            self.lost_large_packets = 0;
+        }
+    }
+    /// Whether a packet only fits because of a probe, within the precision of the search
+    fn is_probe_sized(&self, size: usize) -> bool {
+        size > BASE_PACKET_SIZE && size + SEARCH_PRECISION > self.max_packet_size
+    }
+}
@@ -0,0 +1,60 @@
+use std::cmp::max;
+use std::time;
+
+
+/// RTT assumed until the first sample arrives
+pub const INITIAL_RTT_MS: u64 = 100;
+
+/// Retransmission timeout until the first sample arrives (RFC 6298)
+pub const INITIAL_RTO_MS: u64 = 1000;
+
+/// The least a retransmission timeout adds to the smoothed RTT, for queues building up between samples
+pub const MIN_RTO_MS: u64 = 200;
+
+
+/// Smoothed round-trip time estimate for a single path (RFC 6298)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RttEstimator {
+    latest: Option<time::Duration>,
+    smoothed: Option<time::Duration>,
+    variance: time::Duration,
+}
+
+impl RttEstimator {
+    pub fn new() -> RttEstimator {
+        RttEstimator::default()
+    }
+
+    pub fn latest_rtt(&self) -> Option<time::Duration> {
+        self.latest
+    }
+
+    pub fn smoothed_rtt(&self) -> time::Duration {
+        self.smoothed.unwrap_or_else(|| time::Duration::from_millis(INITIAL_RTT_MS))
+    }
+
+    /// How long an unacknowledged packet waits before it's considered lost, without backoff
+    pub fn retransmission_timeout(&self) -> time::Duration {
+        match self.smoothed {
+            Some(smoothed) => smoothed + max(self.variance * 4, time::Duration::from_millis(MIN_RTO_MS)),
+            None => time::Duration::from_millis(INITIAL_RTO_MS),
+        }
+    }
+
+    pub fn update(&mut self, sample: time::Duration) {
+        self.latest = Some(sample);
+
+        match self.smoothed {
+            None => {
+                self.smoothed = Some(sample);
+                self.variance = sample / 2;
+            },
+            Some(smoothed) => {
+                let deviation = if smoothed > sample { smoothed - sample } else { sample - smoothed };
+
+                self.variance = (self.variance * 3 + deviation) / 4;
+                self.smoothed = Some((smoothed * 7 + sample) / 8);
+            },
+        }
+    }
+}
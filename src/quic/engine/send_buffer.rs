@@ -0,0 +1,226 @@
+use std::cmp::{max, min};
+use std::collections::{BTreeMap, VecDeque};
+
+
+/// Writes are gathered into chunks of up to this many bytes
+pub const CHUNK_SIZE: usize = 16 * 1024;
+
+
+/// A set of non-overlapping, non-adjacent `start..end` ranges
+#[derive(Clone, Debug, Default, PartialEq)]
+struct RangeSet {
+    ranges: BTreeMap<u64, u64>,
+}
+
+impl RangeSet {
+    fn first(&self) -> Option<(u64, u64)> {
+        self.ranges.iter().next().map(|(&start, &end)| (start, end))
+    }
+
+    fn insert(&mut self, mut start: u64, mut end: u64) {
+        if start >= end {
+            return;
+        }
+
+        // absorb every range touching the new one
+        let touching: Vec<(u64, u64)> = {
+            let preceding =
+                self.ranges.range(..start).next_back()
+                .filter(|&(_, &range_end)| range_end >= start);
+
+            preceding.into_iter().chain(self.ranges.range(start..end + 1))
+                .map(|(&range_start, &range_end)| (range_start, range_end))
+                .collect()
+        };
+        for (range_start, range_end) in touching {
+            self.ranges.remove(&range_start);
+            start = min(start, range_start);
+            end = max(end, range_end);
+        }
+
+        self.ranges.insert(start, end);
+    }
+
+    fn remove(&mut self, start: u64, end: u64) {
+        if start >= end {
+            return;
+        }
+
+        let overlapping: Vec<(u64, u64)> = {
+            let preceding =
+                self.ranges.range(..start).next_back()
+                .filter(|&(_, &range_end)| range_end > start);
+
+            preceding.into_iter().chain(self.ranges.range(start..end))
+                .map(|(&range_start, &range_end)| (range_start, range_end))
+                .collect()
+        };
+        for (range_start, range_end) in overlapping {
+            self.ranges.remove(&range_start);
+            if range_start < start {
+                self.ranges.insert(range_start, start);
+            }
+            if range_end > end {
+                self.ranges.insert(end, range_end);
+            }
+        }
+    }
+}
+
+
+/// Outgoing data of a stream, kept until the peer acknowledges it
+///
+/// Bytes below `acked_offset` are released a chunk at a time, lost ranges
+/// are sent again before any new data.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SendBuffer {
+    chunks: VecDeque<Vec<u8>>,
+    // stream offset of the first byte in `chunks`
+    chunks_offset: u64,
+    end_offset: u64,
+    next_offset: u64,
+    acked_offset: u64,
+    acked: RangeSet,
+    lost: RangeSet,
+}
+
+impl SendBuffer {
+    pub fn new() -> SendBuffer {
+        SendBuffer::default()
+    }
+
+    pub fn write(&mut self, buf: &[u8]) {
+        let mut buf = buf;
+        self.end_offset += buf.len() as u64;
+
+        if let Some(chunk) = self.chunks.back_mut() {
+            let size = min(CHUNK_SIZE.saturating_sub(chunk.len()), buf.len());
+            chunk.extend_from_slice(&buf[..size]);
+            buf = &buf[size..];
+        }
+
+        for data in buf.chunks(CHUNK_SIZE) {
+            self.chunks.push_back(data.to_vec());
+        }
+    }
+
+    /// Offset right after the last written byte
+    pub fn end_offset(&self) -> u64 {
+        self.end_offset
+    }
+
+    /// All bytes below this offset have been acknowledged
+    pub fn acked_offset(&self) -> u64 {
+        self.acked_offset
+    }
+
+    /// Bytes held by the buffer, sent or not
+    pub fn len(&self) -> usize {
+        (self.end_offset - self.acked_offset) as usize
+    }
+
+    /// Whether every written byte has been acknowledged
+    pub fn is_empty(&self) -> bool {
+        self.acked_offset == self.end_offset
+    }
+
+    /// Give up on the unacknowledged data, as if the peer had acknowledged it
+    pub fn discard(&mut self) {
+        self.chunks.clear();
+        self.chunks_offset = self.end_offset;
+        self.next_offset = self.end_offset;
+        self.acked_offset = self.end_offset;
+        self.acked = RangeSet::default();
+        self.lost = RangeSet::default();
+    }
+
+    /// Take up to `max_size` bytes to send, lost ones first
+    ///
+    /// New data is limited to offsets below `max_offset`, the peer's flow control limit.
+    pub fn next_data(&mut self, max_size: usize, max_offset: u64) -> Option<(u64, Vec<u8>)> {
+        if max_size == 0 {
+            return None;
+        }
+
+        if let Some((start, end)) = self.lost.first() {
+            let end = min(end, start + max_size as u64);
+            self.lost.remove(start, end);
+            return Some((start, self.copy_range(start, end)));
+        }
+
+        let end = min(min(self.end_offset, max_offset), self.next_offset + max_size as u64);
+        if end <= self.next_offset {
+            return None;
+        }
+
+        let start = self.next_offset;
+        self.next_offset = end;
+
+        Some((start, self.copy_range(start, end)))
+    }
+
+    pub fn on_acked(&mut self, offset: u64, length: usize) {
+        let end = offset + length as u64;
+        self.lost.remove(offset, end);
+        if end <= self.acked_offset {
+            return;
+        }
+
+        self.acked.insert(max(offset, self.acked_offset), end);
+        match self.acked.first() {
+            Some((start, end)) if start == self.acked_offset => {
+                self.acked.remove(start, end);
+                self.acked_offset = end;
+            },
+            _ => return,
+        }
+
+        // release the chunks that were acknowledged completely
+        while let Some(size) = self.chunks.front().map(|chunk| chunk.len() as u64) {
+            if self.chunks_offset + size > self.acked_offset {
+                break;
+            }
+
+            self.chunks.pop_front();
+            self.chunks_offset += size;
+        }
+    }
+
+    pub fn on_lost(&mut self, offset: u64, length: usize) {
+        let start = max(offset, self.acked_offset);
+        let end = offset + length as u64;
+        if start >= end {
+            return;
+        }
+
+        self.lost.insert(start, end);
+        // some of the range may have been acknowledged through a retransmission
+        let acked: Vec<(u64, u64)> =
+            self.acked.ranges.range(..end)
+            .map(|(&acked_start, &acked_end)| (acked_start, acked_end))
+            .collect();
+        for (acked_start, acked_end) in acked {
+            self.lost.remove(acked_start, acked_end);
+        }
+    }
+
+    fn copy_range(&self, start: u64, end: u64) -> Vec<u8> {
+        let mut data = Vec::with_capacity((end - start) as usize);
+        let mut chunk_offset = self.chunks_offset;
+
+        for chunk in &self.chunks {
+            let chunk_end = chunk_offset + chunk.len() as u64;
+            if chunk_end > start {
+                let from = (max(start, chunk_offset) - chunk_offset) as usize;
+                let to = (min(end, chunk_end) - chunk_offset) as usize;
+                data.extend_from_slice(&chunk[from..to]);
+            }
+            if chunk_end >= end {
+                break;
+            }
+            chunk_offset = chunk_end;
+        }
+
+        data
+    }
+}
@@ -1,6 +1,5 @@
-use std::cmp::min;
-
 use quic::errors::Result;
+use super::send_buffer::SendBuffer;
 use super::stream_buffer::StreamBuffer;
 
 
@@ -27,15 +26,14 @@ pub struct Stream {
     prev_maximum_data: u64,
     fin_offset: u64,
 
-    outgoing_buffer: Vec<u8>,
+    outgoing_buffer: SendBuffer,
     pub max_outgoing_data: u64,
-    next_outgoing_offset: u64,
 }
 
 impl Stream {
     pub fn new(id: u32) -> Stream {
         Stream {
-            id: id,
+            id,
             state: StreamState::Idle,
             fin_sent: false,
 
@@ -43,9 +41,8 @@ impl Stream {
             prev_maximum_data: 0,
             fin_offset: 0,
 
-            outgoing_buffer: vec![],
+            outgoing_buffer: SendBuffer::new(),
             max_outgoing_data: INCOMING_BUFFER_SIZE as u64,
-            next_outgoing_offset: 0,
         }
     }
 
@@ -96,8 +93,18 @@ impl Stream {
         };
     }
 
+    /// Written bytes the peer hasn't acknowledged yet
+    pub fn buffered_outgoing_len(&self) -> usize {
+        self.outgoing_buffer.len()
+    }
+
+    /// Everything written below this offset has been acknowledged by the peer
+    pub fn acked_offset(&self) -> u64 {
+        self.outgoing_buffer.acked_offset()
+    }
+
     pub fn outgoing_fin_offset(&self) -> u64 {
-        self.next_outgoing_offset + self.outgoing_buffer.len() as u64
+        self.outgoing_buffer.end_offset()
     }
 
     pub fn finalize_incoming(&mut self, offset: u64) {
@@ -112,8 +119,36 @@ impl Stream {
         };
     }
 
+    /// Whether the peer sent the end of the stream
+    pub fn is_incoming_finalized(&self) -> bool {
+        self.state == StreamState::RemoteClosed || self.state == StreamState::Closed
+    }
+
+    pub fn incoming_fin_offset(&self) -> u64 {
+        self.fin_offset
+    }
+
+    /// Offset right after the last byte received from the peer
+    pub fn received_offset(&self) -> u64 {
+        self.incoming_buffer.end_offset()
+    }
+
+    /// Abandon both directions of the stream after the peer reset it
+    pub fn reset(&mut self) {
+        self.incoming_buffer.clear();
+        self.fin_offset = self.incoming_buffer.next_index;
+        self.outgoing_buffer.discard();
+        self.fin_sent = true;
+        self.state = StreamState::Closed;
+    }
+
+    /// The peer may have missed our last window update, announce it again
+    pub fn on_peer_blocked(&mut self) {
+        self.prev_maximum_data = 0;
+    }
+
     pub fn extend_outgoing_buf(&mut self, buf: &[u8]) {
-        self.outgoing_buffer.extend(buf);
+        self.outgoing_buffer.write(buf);
         self.state = match self.state {
             StreamState::Idle | StreamState::Open => StreamState::Open,
             StreamState::RemoteClosed => StreamState::RemoteClosed,
@@ -148,13 +183,16 @@ impl Stream {
         }
     }
 
-    pub fn drain_outgoing_buffer(&mut self) -> (u64, Vec<u8>) {
-        let can_send = (self.max_outgoing_data - self.next_outgoing_offset) as usize;
-        let will_send = min(can_send, self.outgoing_buffer.len());
+    /// Up to `max_size` bytes to send next and their offset, lost data first
+    pub fn next_outgoing_data(&mut self, max_size: usize) -> Option<(u64, Vec<u8>)> {
+        self.outgoing_buffer.next_data(max_size, self.max_outgoing_data)
+    }
 
-        let next_outgoing_offset = self.next_outgoing_offset;
-        self.next_outgoing_offset += will_send as u64;
+    pub fn on_data_acked(&mut self, offset: u64, length: usize) {
+        self.outgoing_buffer.on_acked(offset, length);
+    }
 
-        (next_outgoing_offset, self.outgoing_buffer.drain(..will_send).collect())
+    pub fn on_data_lost(&mut self, offset: u64, length: usize) {
+        self.outgoing_buffer.on_lost(offset, length);
     }
 }
@@ -1,125 +1,191 @@
-use std::cmp::min;
-use std::collections::VecDeque;
+use std::cmp::{max, min};
+use std::collections::BTreeMap;
 
 use quic::errors::{Error, Result};
 
 
+/// Adjacent chunks are joined up to this size, so that small frames don't each take a chunk
+pub const MAX_CHUNK_SIZE: usize = 16 * 1024;
+
+
+/// Reassembles incoming stream data that may arrive out of order
+///
+/// Received data is kept as non-overlapping chunks keyed by their stream offset.
 #[derive(Clone, Debug, PartialEq)]
 pub struct StreamBuffer {
     capacity: usize,
     pub next_index: u64,
-    buffer: VecDeque<Option<u8>>,
+    chunks: BTreeMap<u64, Vec<u8>>,
 }
 
 impl StreamBuffer {
     pub fn new(capacity: usize) -> StreamBuffer {
         StreamBuffer {
-            capacity: capacity,
+            capacity,
             next_index: 0,
-            buffer: VecDeque::with_capacity(capacity),
+            chunks: BTreeMap::new(),
         }
     }
 
+    /// Store data received at `offset`
+    ///
+    /// A retransmission may repeat bytes that were read already. Those aren't kept,
+    /// so they are dropped without comparing them and only the rest is stored.
+    /// Bytes still in the buffer have to match what arrives again.
     pub fn add_data(&mut self, offset: u64, data: &[u8]) -> Result<()> {
-        // check that the buffer will not overflow
-        let max_available_index = self.maximum_accepted_offset();
-        let max_arriving_index = offset + (data.len() as u64) - 1;
-        if max_arriving_index > max_available_index {
-            error!(
-                "Overflow, next_index: {:?}, capacity: {:?}, offset: {:?}, data_len: {:?}",
-                self.next_index,
-                self.capacity,
-                offset,
-                data.len(),
-            );
-            return Err(Error::BufferOverflow);
+        let (offset, data) = if offset < self.next_index {
+            let delivered_size = min(self.next_index - offset, data.len() as u64) as usize;
+            (self.next_index, &data[delivered_size..])
+        } else {
+            (offset, data)
+        };
+
+        if data.is_empty() {
+            return Ok(());
         }
 
-        // check that we are not writing data that's already been read
-        if offset < self.next_index {
-            return Err(Error::InvalidData(String::from("Stream data already delivered")));
-        }
+        // check that the buffer will not overflow, nor the stream's offsets
+        let end = match offset.checked_add(data.len() as u64) {
+            Some(end) if end - 1 <= self.maximum_accepted_offset() => end,
+            _ => {
+                error!(
+                    "Overflow, next_index: {:?}, capacity: {:?}, offset: {:?}, data_len: {:?}",
+                    self.next_index,
+                    self.capacity,
+                    offset,
+                    data.len(),
+                );
+                return Err(Error::BufferOverflow);
+            },
+        };
+
+        // check that there's no mismatch with the existing data, and find the gaps to fill
+        let mut gaps = vec![];
+        let mut gap_start = offset;
+        for (&chunk_offset, chunk) in self.overlapping_chunks(offset, end) {
+            let chunk_end = chunk_offset + chunk.len() as u64;
+            let overlap_start = max(offset, chunk_offset);
+            let overlap_end = min(end, chunk_end);
+
+            let existing = &chunk[(overlap_start - chunk_offset) as usize..(overlap_end - chunk_offset) as usize];
+            let arriving = &data[(overlap_start - offset) as usize..(overlap_end - offset) as usize];
+            if existing != arriving {
+                warn!("Incorrect bytes at index {}..{}", overlap_start, overlap_end);
+                return Err(
+                    Error::InvalidData(String::from("Mismatch with bytes already in buffer"))
+                );
+            }
 
-        // check that there's no mismatch with the existing data
-        let starting_buffer_index = (offset - self.next_index) as usize;
-        self.extend_buffer(starting_buffer_index);
-        let overlapping_count = min(
-            data.len(),
-            (self.buffer.len() - starting_buffer_index),
-        );
-        for i in 0..overlapping_count {
-            let buffer_element = &mut self.buffer[starting_buffer_index + i];
-
-            match *buffer_element {
-                Some(existing_byte) => {
-                    if existing_byte != data[i] {
-                        warn!(
-                            "Incorrect byte at index {} + {} (got {}, expected {})",
-                            offset, i, data[i], existing_byte,
-                        );
-                        return Err(
-                            Error::InvalidData(String::from("Mismatch with bytes already in buffer"))
-                        );
-                    }
-                },
-                None => {
-                    *buffer_element = Some(data[i]);
-                }
+            if gap_start < overlap_start {
+                gaps.push((gap_start, overlap_start));
             }
+            gap_start = max(gap_start, overlap_end);
+        }
+        if gap_start < end {
+            gaps.push((gap_start, end));
         }
 
-        // append the rest of new data to the buffer
-        self.buffer.extend(
-            data[overlapping_count..]
-            .iter()
-            .map(|&b| Some(b))
-        );
+        // only store the bytes that weren't there yet
+        for (gap_start, gap_end) in gaps {
+            let gap_data = &data[(gap_start - offset) as usize..(gap_end - offset) as usize];
+            self.chunks.insert(gap_start, gap_data.to_vec());
+        }
+        self.merge_chunks(offset, end);
 
         Ok(())
     }
 
     pub fn pull_data(&mut self, buf: &mut [u8]) -> usize {
-        let possible_size = min(buf.len(), self.buffer.len());
         let mut actual_size = 0;
-        for i in 0..possible_size {
-            match self.buffer[i] {
-                Some(b) => {
-                    buf[i] = b;
-                    actual_size += 1;
-                },
-                None => { break; },
+
+        while actual_size < buf.len() {
+            let chunk_offset = match self.chunks.keys().next() {
+                Some(&chunk_offset) if chunk_offset == self.next_index => chunk_offset,
+                _ => break,
+            };
+            let mut chunk = self.chunks.remove(&chunk_offset).unwrap();
+
+            let size = min(chunk.len(), buf.len() - actual_size);
+            buf[actual_size..actual_size + size].copy_from_slice(&chunk[..size]);
+            if size < chunk.len() {
+                // put the unread rest back
+                self.chunks.insert(chunk_offset + size as u64, chunk.split_off(size));
             }
-        }
 
-        self.buffer.drain(..actual_size);
-        self.next_index += actual_size as u64;
+            actual_size += size;
+            self.next_index += size as u64;
+        }
 
         actual_size
     }
 
     pub fn is_readable(&self) -> bool {
-        self.buffer.len() > 0 && self.buffer[0].is_some()
+        self.chunks.keys().next() == Some(&self.next_index)
     }
 
     pub fn is_empty(&self) -> bool {
-        let mut empty = true;
-        for item in &self.buffer {
-            if item.is_some() {
-                empty = false;
-                break;
-            }
+        self.chunks.is_empty()
+    }
+
+    /// How many pieces the data that wasn't read yet is kept in
+    #[cfg(test)]
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Offset right after the last byte received, read or not
+    pub fn end_offset(&self) -> u64 {
+        match self.chunks.iter().next_back() {
+            Some((&chunk_offset, chunk)) => chunk_offset + chunk.len() as u64,
+            None => self.next_index,
         }
+    }
 
-        empty
+    /// Drop the data that wasn't read yet
+    pub fn clear(&mut self) {
+        self.chunks.clear();
     }
 
     pub fn maximum_accepted_offset(&self) -> u64 {
         self.next_index + (self.capacity as u64) - 1
     }
 
-    fn extend_buffer(&mut self, starting_buffer_index: usize) {
-        for _ in self.buffer.len()..starting_buffer_index {
-            self.buffer.push_back(None);
+    /// Join the chunks covering `start..end` with each other and with the adjacent ones
+    fn merge_chunks(&mut self, start: u64, end: u64) {
+        let mut first = match self.chunks.range(..=start).next_back() {
+            Some((&chunk_offset, _)) => chunk_offset,
+            None => return,
+        };
+        if let Some((&chunk_offset, chunk)) = self.chunks.range(..first).next_back() {
+            if chunk_offset + chunk.len() as u64 == first {
+                first = chunk_offset;
+            }
         }
+
+        // everything from the first chunk up to one starting right at `end` is contiguous
+        let offsets: Vec<u64> = self.chunks.range(first..=end).map(|(&chunk_offset, _)| chunk_offset).collect();
+        let mut merged_offset = first;
+        let mut merged = self.chunks.remove(&first).unwrap();
+        for &chunk_offset in &offsets[1..] {
+            let chunk = self.chunks.remove(&chunk_offset).unwrap();
+            if merged.len() + chunk.len() > MAX_CHUNK_SIZE {
+                self.chunks.insert(merged_offset, merged);
+                merged_offset = chunk_offset;
+                merged = chunk;
+            } else {
+                merged.extend_from_slice(&chunk);
+            }
+        }
+        self.chunks.insert(merged_offset, merged);
+    }
+
+    /// Chunks with data in `start..end`, in offset order
+    fn overlapping_chunks<'a>(&'a self, start: u64, end: u64) -> Box<dyn Iterator<Item=(&'a u64, &'a Vec<u8>)> + 'a> {
+        // the last chunk starting before `start` may reach into the range
+        let preceding =
+            self.chunks.range(..start).next_back()
+            .filter(|&(&chunk_offset, chunk)| chunk_offset + chunk.len() as u64 > start);
+
+        Box::new(preceding.into_iter().chain(self.chunks.range(start..end)))
     }
 }
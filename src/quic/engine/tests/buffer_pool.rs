@@ -0,0 +1,38 @@
+use quic::engine::buffer_pool::BufferPool;
+
+
+#[test]
+fn test_reuse() {
+    let mut pool = BufferPool::new(100, 2);
+    assert!(pool.is_empty());
+
+    let buffer = pool.take();
+    assert_eq!(buffer.len(), 100);
+    let pointer = buffer.as_ptr();
+
+    // shortened buffers come back full size, in the same allocation
+    let mut buffer = buffer;
+    buffer.truncate(10);
+    pool.give_back(buffer);
+    assert_eq!(pool.len(), 1);
+
+    let buffer = pool.take();
+    assert_eq!(buffer.len(), 100);
+    assert_eq!(buffer.as_ptr(), pointer);
+    assert!(pool.is_empty());
+}
+
+#[test]
+fn test_limits() {
+    let mut pool = BufferPool::new(100, 2);
+
+    for _ in 0..3 {
+        pool.give_back(vec![0; 100]);
+    }
+    assert_eq!(pool.len(), 2);
+
+    // too small to hold a datagram
+    let mut pool = BufferPool::new(100, 2);
+    pool.give_back(Vec::with_capacity(50));
+    assert!(pool.is_empty());
+}
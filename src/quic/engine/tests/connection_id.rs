@@ -0,0 +1,67 @@
+use std::io;
+
+use quic::endpoint_role::EndpointRole;
+use quic::engine::config::Config;
+use quic::engine::event::Event;
+use quic::engine::udp_packet::OutgoingUdpPacket;
+use quic::packets::Packet;
+use super::{address, connect, deliver, IdleTimer};
+
+
+fn decode_connection_ids(packets: &[OutgoingUdpPacket]) -> Vec<Option<u64>> {
+    packets.iter()
+        .map(|packet| {
+            let mut read = io::Cursor::new(&packet.payload[..]);
+            Packet::decode(&mut read, EndpointRole::Client).unwrap().connection_id()
+        })
+        .collect()
+}
+
+fn omitting_config() -> Config {
+    Config { omit_connection_id: true, ..Config::default() }
+}
+
+
+#[test]
+fn test_omitted_connection_id() {
+    let (mut client, mut server, connection_id) = connect(IdleTimer::default(), omitting_config(), Config::default());
+    assert_eq!(server.poll_event(), Some(Event::ConnectionEstablished(connection_id)));
+
+    server.write(connection_id, 2, b"hello").unwrap();
+    let packets = server.pop_pending_packets();
+    assert!(!packets.is_empty());
+    assert!(decode_connection_ids(&packets).iter().all(|id| id.is_none()));
+
+    deliver(&mut client, packets, address("127.0.0.1:2000"));
+    assert!(client.data_available(connection_id, 2));
+
+    let mut buf = [0; 16];
+    let read_size = client.read(connection_id, 2, &mut buf).unwrap();
+    assert_eq!(&buf[..read_size], b"hello");
+
+    // the client keeps sending connection ids so the server can route its packets
+    let packets = client.pop_pending_packets();
+    assert!(!packets.is_empty());
+    assert!(decode_connection_ids(&packets).iter().all(|&id| id == Some(connection_id)));
+}
+
+#[test]
+fn test_omitted_connection_id_unknown_address() {
+    let (mut client, mut server, connection_id) = connect(IdleTimer::default(), omitting_config(), Config::default());
+
+    server.write(connection_id, 2, b"hello").unwrap();
+    let packets = server.pop_pending_packets();
+
+    deliver(&mut client, packets, address("127.0.0.1:3000"));
+    assert!(!client.data_available(connection_id, 2));
+}
+
+#[test]
+fn test_connection_id_kept_by_default() {
+    let (_, mut server, connection_id) = connect(IdleTimer::default(), Config::default(), Config::default());
+
+    server.write(connection_id, 2, b"hello").unwrap();
+    let packets = server.pop_pending_packets();
+    assert!(!packets.is_empty());
+    assert!(decode_connection_ids(&packets).iter().all(|&id| id == Some(connection_id)));
+}
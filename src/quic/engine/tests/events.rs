@@ -0,0 +1,53 @@
+use quic::engine::QuicEngine;
+use quic::engine::config::Config;
+use quic::engine::event::Event;
+use quic::errors::codes;
+use super::{address, drain_events, exchange_packets, IdleTimer};
+
+
+#[test]
+fn test_connection_and_stream_events() {
+    let mut client = QuicEngine::new(IdleTimer::default(), false);
+    let mut server = QuicEngine::new(IdleTimer::default(), true);
+
+    let connection_id = client.initiate_connection(client.now(), address("127.0.0.1:2000"));
+    client.write(connection_id, 3, b"hello").unwrap();
+    client.finalize_outgoing_stream(connection_id, 3).unwrap();
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+
+    assert_eq!(drain_events(&mut client), vec![Event::ConnectionEstablished(connection_id)]);
+    assert_eq!(drain_events(&mut server), vec![
+        Event::ConnectionEstablished(connection_id),
+        Event::StreamOpened(connection_id, 1),
+        Event::StreamOpened(connection_id, 3),
+        Event::StreamReadable(connection_id, 3),
+        Event::StreamFinished(connection_id, 3),
+    ]);
+
+    // readable is reported again only after everything was read
+    let mut buf = [0; 16];
+    assert_eq!(server.read(connection_id, 3, &mut buf).unwrap(), 5);
+    assert_eq!(drain_events(&mut server), vec![]);
+
+    client.close_connection(connection_id, codes::QUIC_NO_ERROR).unwrap();
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+    assert_eq!(drain_events(&mut client), vec![Event::ConnectionClosed(connection_id, codes::QUIC_NO_ERROR)]);
+    assert_eq!(drain_events(&mut server), vec![Event::ConnectionClosed(connection_id, codes::QUIC_NO_ERROR)]);
+}
+
+#[test]
+fn test_writable_event() {
+    let config = Config { stream_send_buffer: 3000, ..Config::default() };
+    let mut client = QuicEngine::with_config(IdleTimer::default(), false, config);
+    let mut server = QuicEngine::new(IdleTimer::default(), true);
+
+    let connection_id = client.initiate_connection(client.now(), address("127.0.0.1:2000"));
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+    drain_events(&mut client);
+
+    // only a write that was cut short waits for room
+    assert_eq!(client.write(connection_id, 1, &[7; 2000]).unwrap(), 2000);
+    assert_eq!(client.write(connection_id, 3, &[7; 4000]).unwrap(), 3000);
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+    assert_eq!(drain_events(&mut client), vec![Event::StreamWritable(connection_id, 3)]);
+}
@@ -0,0 +1,92 @@
+use std::time;
+
+use quic::engine::QuicEngine;
+use quic::engine::config::Config;
+use quic::engine::connection::ConnectionState;
+use quic::engine::event::Event;
+use quic::errors::{codes, Error};
+use quic::engine::timer::ManualTimer;
+use super::{address, connect, exchange_packets};
+
+
+fn advance(engine: &mut QuicEngine<ManualTimer>, duration: time::Duration) {
+    let now = engine.timer_mut().advance(duration);
+    engine.handle_timeout(now);
+}
+
+
+#[test]
+fn test_close_and_reap() {
+    let (mut client, mut server, connection_id) = connect(ManualTimer::new(time::Instant::now()), Config::default(), Config::default());
+    assert_eq!(client.connection_state(connection_id), Some(ConnectionState::Established));
+    assert_eq!(server.connection_state(connection_id), Some(ConnectionState::Established));
+
+    client.close_connection(connection_id, codes::QUIC_NO_ERROR).unwrap();
+    assert_eq!(client.connection_state(connection_id), Some(ConnectionState::Closing));
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+    assert_eq!(server.connection_state(connection_id), Some(ConnectionState::Draining));
+
+    let mut buf = [0; 16];
+    match server.read(connection_id, 1, &mut buf) {
+        Err(Error::ConnectionClosed(code)) => assert_eq!(code, codes::QUIC_NO_ERROR),
+        _ => assert!(false, "Closed connection expected"),
+    }
+
+    // both sides forget the connection once the draining period is over
+    advance(&mut client, time::Duration::from_secs(1));
+    advance(&mut server, time::Duration::from_secs(1));
+    assert_eq!(client.connection_state(connection_id), None);
+    assert_eq!(server.connection_state(connection_id), None);
+
+    // a reaped connection is an error, not a panic
+    match client.write(connection_id, 1, b"hello") {
+        Err(Error::InvalidConnection) => {},
+        _ => assert!(false, "Invalid connection expected"),
+    }
+    match client.read(connection_id, 1, &mut buf) {
+        Err(Error::InvalidConnection) => {},
+        _ => assert!(false, "Invalid connection expected"),
+    }
+    assert!(client.peer_address(connection_id).is_err());
+    assert!(client.is_finalized(connection_id));
+    assert!(client.data_available(connection_id, 1));
+}
+
+#[test]
+fn test_idle_timeout() {
+    let config = Config { idle_timeout: time::Duration::from_secs(1), ..Config::default() };
+    let (mut client, mut server, connection_id) = connect(ManualTimer::new(time::Instant::now()), Config::default(), config);
+
+    // incoming packets keep the connection alive
+    advance(&mut server, time::Duration::from_millis(600));
+    client.write(connection_id, 1, b"hello").unwrap();
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+    advance(&mut server, time::Duration::from_millis(600));
+    assert_eq!(server.connection_state(connection_id), Some(ConnectionState::Established));
+
+    // silence closes it without telling the peer
+    advance(&mut server, time::Duration::from_millis(600));
+    assert_eq!(server.connection_state(connection_id), Some(ConnectionState::Draining));
+    assert!(server.pop_pending_packets().is_empty());
+
+    advance(&mut server, time::Duration::from_secs(1));
+    assert_eq!(server.connection_state(connection_id), None);
+}
+
+#[test]
+fn test_late_packets_of_reaped_connection() {
+    let config = Config { idle_timeout: time::Duration::from_secs(1), ..Config::default() };
+    let (mut client, mut server, connection_id) = connect(ManualTimer::new(time::Instant::now()), Config::default(), config);
+
+    advance(&mut server, time::Duration::from_secs(2));
+    advance(&mut server, time::Duration::from_secs(1));
+    assert_eq!(server.connection_state(connection_id), None);
+    assert_eq!(server.poll_event(), Some(Event::ConnectionEstablished(connection_id)));
+    assert_eq!(server.poll_event(), Some(Event::ConnectionClosed(connection_id, codes::QUIC_NETWORK_IDLE_TIMEOUT)));
+
+    // stream data without the connection parameters doesn't open a new connection
+    client.write(connection_id, 1, b"hello").unwrap();
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+    assert_eq!(server.poll_event(), None);
+    assert_eq!(server.connection_state(connection_id), None);
+}
@@ -0,0 +1,146 @@
+use std::io;
+
+use quic::endpoint_role::EndpointRole;
+use quic::engine::config::Config;
+use quic::engine::event::Event;
+use quic::engine::path::Migration;
+use quic::engine::udp_packet::{IncomingUdpPacket, OutgoingUdpPacket};
+use quic::packets;
+use quic::packets::frames::{Frame, path_response};
+use super::{address, connect, deliver, drain_events, has_migrated, IdleTimer};
+
+
+fn decode_frames(packet: &OutgoingUdpPacket) -> Vec<Frame> {
+    let mut read = io::Cursor::new(&packet.payload[..]);
+    match packets::Packet::decode(&mut read, EndpointRole::Client).unwrap() {
+        packets::Packet::Regular(regular_packet) => regular_packet.payload.frames,
+        _ => panic!("Regular packet expected"),
+    }
+}
+
+fn has_path_challenge(packet: &OutgoingUdpPacket) -> bool {
+    decode_frames(packet).iter().any(|frame| matches!(*frame, Frame::PathChallenge(..)))
+}
+
+fn sent_to(packets: &[OutgoingUdpPacket], destination: &str) -> Vec<OutgoingUdpPacket> {
+    packets.iter()
+        .filter(|packet| packet.destination_address == address(destination))
+        .cloned()
+        .collect()
+}
+
+
+#[test]
+fn test_migration() {
+    let (mut client, mut server, connection_id) = connect(IdleTimer::default(), Config::default(), Config::default());
+
+    // the client moves to another address
+    client.write(connection_id, 1, b"hello").unwrap();
+    deliver(&mut server, client.pop_pending_packets(), address("127.0.0.2:1000"));
+    assert!(server.data_available(connection_id, 1));
+
+    // stream data keeps going to the old address until the new one is validated
+    server.write(connection_id, 1, b"world").unwrap();
+    let packets = server.pop_pending_packets();
+    let challenges: Vec<_> = packets.iter().filter(|packet| has_path_challenge(packet)).collect();
+    assert_eq!(challenges.len(), 1);
+    assert_eq!(challenges[0].destination_address, address("127.0.0.2:1000"));
+    assert!(!has_migrated(&mut server));
+    assert_eq!(server.peer_address(connection_id).unwrap(), address("127.0.0.1:1000"));
+    assert!(!sent_to(&packets, "127.0.0.1:1000").is_empty());
+
+    // the client echoes the challenge from its new address
+    deliver(&mut client, packets, address("127.0.0.1:2000"));
+    deliver(&mut server, client.pop_pending_packets(), address("127.0.0.2:1000"));
+
+    assert_eq!(drain_events(&mut server), vec![
+        Event::PeerMigrated(connection_id, Migration {
+            path_id: 0,
+            old_address: address("127.0.0.1:1000"),
+            new_address: address("127.0.0.2:1000"),
+        }),
+    ]);
+    assert_eq!(server.peer_address(connection_id).unwrap(), address("127.0.0.2:1000"));
+
+    server.write(connection_id, 1, b"again").unwrap();
+    let packets = server.pop_pending_packets();
+    assert!(!packets.is_empty());
+    assert_eq!(sent_to(&packets, "127.0.0.2:1000").len(), packets.len());
+}
+
+#[test]
+fn test_wrong_path_response() {
+    let (mut client, mut server, connection_id) = connect(IdleTimer::default(), Config::default(), Config::default());
+
+    client.write(connection_id, 1, b"hello").unwrap();
+    deliver(&mut server, client.pop_pending_packets(), address("127.0.0.2:1000"));
+    server.pop_pending_packets();
+
+    // a forged response that doesn't echo the challenge
+    let packet = packets::Packet::Regular(packets::RegularPacket {
+        header: packets::PacketHeader {
+            key_phase: false,
+            packet_number_size: 4,
+            multipath: false,
+
+            connection_id: Some(connection_id),
+            path_id: 0,
+        },
+
+        version: None,
+        packet_number: 1000,
+        payload: packets::PacketPayload {
+            frames: vec![
+                Frame::PathResponse(path_response::PathResponseFrame { data: 42 }),
+            ],
+        },
+    });
+    let mut payload = vec![];
+    packet.encode(&mut payload).unwrap();
+    let now = server.now();
+    server.handle_incoming_packet(now, IncomingUdpPacket {
+        source_address: address("127.0.0.2:1000"),
+        destination_address: None,
+        payload,
+    });
+
+    assert!(!has_migrated(&mut server));
+    assert_eq!(server.peer_address(connection_id).unwrap(), address("127.0.0.1:1000"));
+}
+
+#[test]
+fn test_amplification_limit() {
+    let (mut client, mut server, connection_id) = connect(IdleTimer::default(), Config::default(), Config::default());
+
+    client.write(connection_id, 1, b"hello").unwrap();
+    let packets = client.pop_pending_packets();
+    let received_size: usize = packets.iter().map(|packet| packet.payload.len()).sum();
+    deliver(&mut server, packets, address("127.0.0.2:1000"));
+
+    server.write(connection_id, 1, &vec![42; 10000]).unwrap();
+    let packets = server.pop_pending_packets();
+    let sent_size: usize =
+        sent_to(&packets, "127.0.0.2:1000").iter()
+        .map(|packet| packet.payload.len())
+        .sum();
+
+    assert!(sent_size > 0);
+    assert!(sent_size <= 3 * received_size);
+}
+
+#[test]
+fn test_reordered_packet_from_old_address() {
+    let (mut client, mut server, connection_id) = connect(IdleTimer::default(), Config::default(), Config::default());
+
+    client.write(connection_id, 1, b"hello").unwrap();
+    let first_packets = client.pop_pending_packets();
+    client.write(connection_id, 1, b"world").unwrap();
+    let second_packets = client.pop_pending_packets();
+
+    deliver(&mut server, second_packets, address("127.0.0.1:1000"));
+    deliver(&mut server, first_packets, address("127.0.0.2:1000"));
+
+    let packets = server.pop_pending_packets();
+    assert!(!packets.iter().any(has_path_challenge));
+    assert_eq!(server.peer_address(connection_id).unwrap(), address("127.0.0.1:1000"));
+}
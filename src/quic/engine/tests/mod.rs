@@ -1 +1,116 @@
+mod buffer_pool;
+mod connection_id;
+mod events;
+mod lifecycle;
+mod migration;
+mod multipath;
+mod peer_input;
+mod pmtu;
+mod retransmission;
+mod send_buffer;
+mod simulator;
 mod stream_buffer;
+mod streams;
+mod timer;
+mod transfer;
+
+use std::net;
+use std::time;
+
+use quic::engine::QuicEngine;
+use quic::engine::config::Config;
+use quic::engine::event::Event;
+use quic::engine::timer::{ScheduledEvent, Timer, TimerToken};
+use quic::engine::udp_packet::{IncomingUdpPacket, OutgoingUdpPacket};
+
+
+/// A timer that never fires, for tests that drive engines by hand
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IdleTimer {}
+
+impl Timer for IdleTimer {
+    fn schedule(&mut self, _deadline: time::Instant, _event: ScheduledEvent) -> TimerToken {
+        TimerToken(0)
+    }
+
+    fn cancel(&mut self, _token: TimerToken) {}
+
+    fn cancel_connection(&mut self, _connection_id: u64) {}
+
+    fn next_timeout(&self) -> Option<time::Instant> {
+        None
+    }
+
+    fn pop_due_events(&mut self, _now: time::Instant) -> Vec<ScheduledEvent> {
+        vec![]
+    }
+}
+
+
+pub fn address(address: &str) -> net::SocketAddr {
+    address.parse().unwrap()
+}
+
+/// Deliver packets to an engine as if they came from `source_address`,
+/// unless they were sent from a specific local address
+///
+/// They arrive at the engine's current time.
+pub fn deliver<T: Timer>(
+        engine: &mut QuicEngine<T>,
+        packets: Vec<OutgoingUdpPacket>,
+        source_address: net::SocketAddr) {
+    for packet in packets {
+        let now = engine.now();
+        engine.handle_incoming_packet(now, IncomingUdpPacket {
+            source_address: packet.source_address.unwrap_or(source_address),
+            destination_address: None,
+            payload: packet.payload,
+        });
+    }
+}
+
+pub fn drain_events<T: Timer>(engine: &mut QuicEngine<T>) -> Vec<Event> {
+    let mut events = vec![];
+    while let Some(event) = engine.poll_event() {
+        events.push(event);
+    }
+
+    events
+}
+
+/// Whether the peer of any connection of `engine` migrated since events were last drained
+pub fn has_migrated<T: Timer>(engine: &mut QuicEngine<T>) -> bool {
+    drain_events(engine).iter().any(|event| matches!(*event, Event::PeerMigrated(..)))
+}
+
+/// Shuttle packets between two engines until both go quiet
+pub fn exchange_packets<T: Timer>(
+        client: &mut QuicEngine<T>,
+        client_address: net::SocketAddr,
+        server: &mut QuicEngine<T>,
+        server_address: net::SocketAddr) {
+    loop {
+        let client_packets = client.pop_pending_packets();
+        let server_packets = server.pop_pending_packets();
+        if client_packets.is_empty() && server_packets.is_empty() {
+            break;
+        }
+
+        deliver(server, client_packets, client_address);
+        deliver(client, server_packets, server_address);
+    }
+}
+
+/// A client and a server engine that went through the handshake, and their connection's id
+pub fn connect<T: Timer + Clone>(
+        timer: T,
+        client_config: Config,
+        server_config: Config) -> (QuicEngine<T>, QuicEngine<T>, u64) {
+    let mut client = QuicEngine::with_config(timer.clone(), false, client_config);
+    let mut server = QuicEngine::with_config(timer, true, server_config);
+
+    let connection_id = client.initiate_connection(client.now(), address("127.0.0.1:2000"));
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+
+    (client, server, connection_id)
+}
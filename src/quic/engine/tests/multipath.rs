@@ -0,0 +1,103 @@
+use std::io;
+
+use quic::endpoint_role::EndpointRole;
+use quic::engine::config::Config;
+use quic::engine::udp_packet::OutgoingUdpPacket;
+use quic::packets;
+use super::{address, connect, deliver, exchange_packets, has_migrated, IdleTimer};
+
+
+fn decode(packet: &OutgoingUdpPacket) -> packets::RegularPacket {
+    let mut read = io::Cursor::new(&packet.payload[..]);
+    match packets::Packet::decode(&mut read, EndpointRole::Client).unwrap() {
+        packets::Packet::Regular(regular_packet) => regular_packet,
+        _ => panic!("Regular packet expected"),
+    }
+}
+
+
+#[test]
+fn test_open_path() {
+    let (mut client, mut server, connection_id) = connect(IdleTimer::default(), Config::default(), Config::default());
+
+    let path_id = client.open_path(connection_id, Some(address("127.0.0.2:1000")), address("127.0.0.1:2000")).unwrap();
+    assert_eq!(path_id, 1);
+
+    // the new path has its own packet number space
+    let packets = client.pop_pending_packets();
+    assert_eq!(packets.len(), 1);
+    assert_eq!(packets[0].source_address, Some(address("127.0.0.2:1000")));
+    let packet = decode(&packets[0]);
+    assert!(packet.header.multipath);
+    assert_eq!(packet.header.path_id, 1);
+    assert_eq!(packet.packet_number, 1);
+
+    let client_paths = client.paths(connection_id).unwrap();
+    assert_eq!(client_paths.len(), 2);
+    assert!(!client_paths[1].validated);
+
+    deliver(&mut server, packets, address("127.0.0.1:1000"));
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+
+    let client_paths = client.paths(connection_id).unwrap();
+    assert!(client_paths.iter().all(|path| path.validated));
+
+    let server_paths = server.paths(connection_id).unwrap();
+    assert_eq!(server_paths.len(), 2);
+    assert!(server_paths.iter().all(|path| path.validated));
+    assert_eq!(server_paths[1].id, 1);
+    assert_eq!(server_paths[1].peer_address, address("127.0.0.2:1000"));
+
+    // the original path and address are unaffected
+    assert!(!has_migrated(&mut server));
+    assert_eq!(server.peer_address(connection_id).unwrap(), address("127.0.0.1:1000"));
+}
+
+#[test]
+fn test_open_path_on_server() {
+    let (_client, mut server, connection_id) = connect(IdleTimer::default(), Config::default(), Config::default());
+
+    assert!(server.open_path(connection_id, None, address("127.0.0.3:1000")).is_err());
+    assert_eq!(server.paths(connection_id).unwrap().len(), 1);
+}
+
+#[test]
+fn test_transfer_over_two_paths() {
+    let (mut client, mut server, connection_id) = connect(IdleTimer::default(), Config::default(), Config::default());
+
+    client.open_path(connection_id, Some(address("127.0.0.2:1000")), address("127.0.0.1:2000")).unwrap();
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+
+    let data: Vec<u8> = (0..50000).map(|i| (i % 251) as u8).collect();
+    client.write(connection_id, 1, &data).unwrap();
+    client.finalize_outgoing_stream(connection_id, 1).unwrap();
+
+    // stream data is spread over both validated paths
+    let packets = client.pop_pending_packets();
+    assert!(packets.iter().any(|packet| packet.source_address.is_none()));
+    assert!(packets.iter().any(|packet| packet.source_address == Some(address("127.0.0.2:1000"))));
+    deliver(&mut server, packets, address("127.0.0.1:1000"));
+
+    let mut received = vec![];
+    let mut buf = [0; 4096];
+    loop {
+        exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+        if !server.data_available(connection_id, 1) {
+            break;
+        }
+
+        let read_size = server.read(connection_id, 1, &mut buf).unwrap();
+        if read_size == 0 {
+            break;
+        }
+        received.extend_from_slice(&buf[..read_size]);
+    }
+
+    assert_eq!(received, data);
+
+    // every packet got acknowledged on the path it was sent on
+    server.finalize_outgoing_stream(connection_id, 1).unwrap();
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+    assert!(client.is_finalized(connection_id));
+    assert!(server.is_finalized(connection_id));
+}
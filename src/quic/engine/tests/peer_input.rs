@@ -0,0 +1,290 @@
+use std::io;
+use std::net;
+
+use quic::QUIC_VERSION;
+use quic::endpoint_role::EndpointRole;
+use quic::engine::QuicEngine;
+use quic::engine::config::Config;
+use quic::engine::connection::ConnectionState;
+use quic::engine::event::Event;
+use quic::engine::udp_packet::{IncomingUdpPacket, OutgoingUdpPacket};
+use quic::errors::{codes, Error};
+use quic::packets;
+use quic::packets::frames::{blocked, Frame, goaway, rst_stream, stop_waiting, stream};
+use super::{address, connect, exchange_packets, IdleTimer};
+
+
+/// Connected engines, with the handshake's events consumed
+fn connect_quietly() -> (QuicEngine<IdleTimer>, QuicEngine<IdleTimer>, u64) {
+    let (mut client, mut server, connection_id) = connect(IdleTimer::default(), Config::default(), Config::default());
+    drain_events(&mut client);
+    drain_events(&mut server);
+
+    (client, server, connection_id)
+}
+
+fn drain_events(engine: &mut QuicEngine<IdleTimer>) -> Vec<Event> {
+    let mut events = vec![];
+    while let Some(event) = engine.poll_event() {
+        events.push(event);
+    }
+
+    events
+}
+
+fn header(connection_id: u64) -> packets::PacketHeader {
+    packets::PacketHeader {
+        key_phase: false,
+        packet_number_size: 4,
+        multipath: false,
+
+        connection_id: Some(connection_id),
+        path_id: 0,
+    }
+}
+
+fn send_packet(engine: &mut QuicEngine<IdleTimer>, packet: packets::Packet, source_address: net::SocketAddr) {
+    let mut payload = vec![];
+    packet.encode(&mut payload).unwrap();
+
+    let now = engine.now();
+    engine.handle_incoming_packet(now, IncomingUdpPacket {
+        source_address,
+        destination_address: None,
+        payload,
+    });
+}
+
+fn send_frames(engine: &mut QuicEngine<IdleTimer>, connection_id: u64, frames: Vec<Frame>, source_address: net::SocketAddr) {
+    send_packet(engine, packets::Packet::Regular(packets::RegularPacket {
+        header: header(connection_id),
+
+        version: None,
+        packet_number: 1000,
+        payload: packets::PacketPayload { frames },
+    }), source_address);
+}
+
+fn decode_frames(packet: &OutgoingUdpPacket) -> Vec<Frame> {
+    let mut read = io::Cursor::new(&packet.payload[..]);
+    match packets::Packet::decode(&mut read, EndpointRole::Client).unwrap() {
+        packets::Packet::Regular(regular_packet) => regular_packet.payload.frames,
+        _ => panic!("Regular packet expected"),
+    }
+}
+
+
+#[test]
+fn test_rst_stream() {
+    let (mut client, mut server, connection_id) = connect_quietly();
+
+    client.write(connection_id, 1, b"hello").unwrap();
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+    server.write(connection_id, 1, b"unsent").unwrap();
+    drain_events(&mut server);
+
+    send_frames(&mut server, connection_id, vec![
+        Frame::RstStream(rst_stream::RstStreamFrame {
+            error_code: 42,
+            stream_id: 1,
+            final_offset: 5,
+        }),
+    ], address("127.0.0.1:1000"));
+
+    assert_eq!(drain_events(&mut server), vec![Event::StreamReset(connection_id, 1, 42)]);
+    assert_eq!(server.connection_state(connection_id), Some(ConnectionState::Established));
+
+    // the stream is gone in both directions
+    let mut buf = [0; 16];
+    assert_eq!(server.read(connection_id, 1, &mut buf).unwrap(), 0);
+    match server.write(connection_id, 1, b"more") {
+        Err(Error::InvalidStream) => {},
+        other => assert!(false, "Expected an invalid stream error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_rst_stream_before_received_data() {
+    let (mut client, mut server, connection_id) = connect_quietly();
+
+    client.write(connection_id, 1, b"hello").unwrap();
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+    drain_events(&mut server);
+
+    send_frames(&mut server, connection_id, vec![
+        Frame::RstStream(rst_stream::RstStreamFrame {
+            error_code: 42,
+            stream_id: 1,
+            final_offset: 2,
+        }),
+    ], address("127.0.0.1:1000"));
+
+    assert_eq!(
+        drain_events(&mut server),
+        vec![Event::ConnectionClosed(connection_id, codes::QUIC_INVALID_RST_STREAM_DATA)]
+    );
+}
+
+#[test]
+fn test_rst_stream_moving_the_fin() {
+    let (mut client, mut server, connection_id) = connect_quietly();
+
+    client.write(connection_id, 1, b"hello").unwrap();
+    client.finalize_outgoing_stream(connection_id, 1).unwrap();
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+    drain_events(&mut server);
+
+    send_frames(&mut server, connection_id, vec![
+        Frame::RstStream(rst_stream::RstStreamFrame {
+            error_code: 42,
+            stream_id: 1,
+            final_offset: 10,
+        }),
+    ], address("127.0.0.1:1000"));
+
+    assert_eq!(
+        drain_events(&mut server),
+        vec![Event::ConnectionClosed(connection_id, codes::QUIC_MULTIPLE_TERMINATION_OFFSETS)]
+    );
+}
+
+#[test]
+fn test_stream_data_at_the_largest_offset() {
+    let (_client, mut server, connection_id) = connect_quietly();
+
+    // the end of the data doesn't fit in a stream offset
+    send_frames(&mut server, connection_id, vec![
+        Frame::Stream(stream::StreamFrame {
+            stream_id: 1,
+            offset: u64::MAX - 2,
+            stream_data: b"hello".to_vec(),
+            fin: false,
+        }),
+    ], address("127.0.0.1:1000"));
+
+    assert_eq!(
+        drain_events(&mut server),
+        vec![
+            Event::StreamOpened(connection_id, 1),
+            Event::ConnectionClosed(connection_id, codes::QUIC_FLOW_CONTROL_RECEIVED_TOO_MUCH_DATA),
+        ]
+    );
+    assert_eq!(server.connection_state(connection_id), Some(ConnectionState::Closing));
+}
+
+#[test]
+fn test_go_away() {
+    let (mut client, _server, connection_id) = connect_quietly();
+
+    client.write(connection_id, 1, b"hello").unwrap();
+    send_frames(&mut client, connection_id, vec![
+        Frame::GoAway(goaway::GoAwayFrame {
+            error_code: codes::QUIC_PEER_GOING_AWAY,
+            last_good_stream_id: 1,
+            reason_phrase: Some(String::from("shutting down")),
+        }),
+    ], address("127.0.0.1:2000"));
+
+    // streams already open keep working, new ones are refused
+    assert_eq!(client.write(connection_id, 1, b"world").unwrap(), 5);
+    match client.open_stream(connection_id) {
+        Err(Error::ConnectionClosed(codes::QUIC_PEER_GOING_AWAY)) => {},
+        other => assert!(false, "Expected the stream to be refused, got {:?}", other),
+    }
+    assert_eq!(client.connection_state(connection_id), Some(ConnectionState::Established));
+}
+
+#[test]
+fn test_blocked() {
+    let (mut client, mut server, connection_id) = connect_quietly();
+
+    client.write(connection_id, 1, b"hello").unwrap();
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+
+    send_frames(&mut server, connection_id, vec![
+        Frame::Blocked(blocked::BlockedFrame { stream_id: 1 }),
+        Frame::Blocked(blocked::BlockedFrame { stream_id: 0 }),
+    ], address("127.0.0.1:1000"));
+
+    // the window is announced again in case the last update got lost
+    let window_updates: Vec<Frame> =
+        server.pop_pending_packets().iter()
+        .flat_map(decode_frames)
+        .filter(|frame| match *frame {
+            Frame::WindowUpdate(ref window_update_frame) => window_update_frame.stream_id == 1,
+            _ => false,
+        })
+        .collect();
+    assert_eq!(window_updates.len(), 1);
+    assert_eq!(server.connection_state(connection_id), Some(ConnectionState::Established));
+}
+
+#[test]
+fn test_stop_waiting() {
+    let (_client, mut server, connection_id) = connect_quietly();
+
+    send_frames(&mut server, connection_id, vec![
+        Frame::StopWaiting(stop_waiting::StopWaitingFrame { least_acked_delta: 1 }),
+    ], address("127.0.0.1:1000"));
+
+    assert_eq!(drain_events(&mut server), vec![]);
+    assert_eq!(server.connection_state(connection_id), Some(ConnectionState::Established));
+}
+
+#[test]
+fn test_frames_for_invalid_streams() {
+    let (_client, mut server, connection_id) = connect_quietly();
+
+    // the server never opened stream 2
+    send_frames(&mut server, connection_id, vec![
+        Frame::RstStream(rst_stream::RstStreamFrame {
+            error_code: 42,
+            stream_id: 2,
+            final_offset: 0,
+        }),
+    ], address("127.0.0.1:1000"));
+
+    assert_eq!(
+        drain_events(&mut server),
+        vec![Event::ConnectionClosed(connection_id, codes::QUIC_INVALID_STREAM_ID)]
+    );
+}
+
+#[test]
+fn test_public_reset() {
+    let (mut client, _server, connection_id) = connect_quietly();
+
+    send_packet(&mut client, packets::Packet::PublicReset(packets::PublicResetPacket {
+        header: header(connection_id),
+    }), address("127.0.0.1:2000"));
+
+    assert_eq!(drain_events(&mut client), vec![Event::ConnectionClosed(connection_id, codes::QUIC_PUBLIC_RESET)]);
+    assert_eq!(client.connection_state(connection_id), Some(ConnectionState::Draining));
+
+    // resets for connections we don't know are dropped
+    send_packet(&mut client, packets::Packet::PublicReset(packets::PublicResetPacket {
+        header: header(connection_id + 1),
+    }), address("127.0.0.1:2000"));
+    assert_eq!(drain_events(&mut client), vec![]);
+}
+
+#[test]
+fn test_version_negotiation() {
+    let mut client = QuicEngine::new(IdleTimer::default(), false);
+    let connection_id = client.initiate_connection(client.now(), address("127.0.0.1:2000"));
+    client.pop_pending_packets();
+
+    // a list with our version doesn't make us give up
+    send_packet(&mut client, packets::Packet::VersionNegotiation(packets::VersionNegotiationPacket {
+        header: header(connection_id),
+        versions: vec![0x1234_5678, QUIC_VERSION],
+    }), address("127.0.0.1:2000"));
+    assert_eq!(client.connection_state(connection_id), Some(ConnectionState::Handshaking));
+
+    send_packet(&mut client, packets::Packet::VersionNegotiation(packets::VersionNegotiationPacket {
+        header: header(connection_id),
+        versions: vec![0x1234_5678],
+    }), address("127.0.0.1:2000"));
+    assert_eq!(drain_events(&mut client), vec![Event::ConnectionClosed(connection_id, codes::QUIC_INVALID_VERSION)]);
+    assert_eq!(client.connection_state(connection_id), Some(ConnectionState::Draining));
+}
@@ -0,0 +1,131 @@
+use quic::engine::config::{Config, DEFAULT_MAX_PACKET_SIZE};
+use quic::engine::pmtu::{MtuDiscovery, BASE_PACKET_SIZE, BLACK_HOLE_THRESHOLD, MAX_PROBE_ATTEMPTS};
+use quic::packets::frames::stream;
+use super::{connect, IdleTimer};
+
+
+#[test]
+fn test_probe_acked() {
+    let mut mtu = MtuDiscovery::new(1500);
+    assert_eq!(mtu.max_packet_size(), BASE_PACKET_SIZE);
+
+    let probe_size = mtu.next_probe_size().unwrap();
+    assert!(probe_size > BASE_PACKET_SIZE && probe_size <= 1500);
+
+    // one probe at a time
+    mtu.on_probe_sent(10, probe_size);
+    assert_eq!(mtu.next_probe_size(), None);
+
+    mtu.on_packet_acked(10, 0);
+    assert_eq!(mtu.max_packet_size(), probe_size);
+    assert!(mtu.next_probe_size().unwrap() > probe_size);
+}
+
+#[test]
+fn test_probe_lost() {
+    let mut mtu = MtuDiscovery::new(1500);
+    let probe_size = mtu.next_probe_size().unwrap();
+
+    for packet_number in 0..MAX_PROBE_ATTEMPTS as u64 {
+        assert_eq!(mtu.next_probe_size(), Some(probe_size));
+        mtu.on_probe_sent(packet_number, probe_size);
+        mtu.on_probe_lost(packet_number);
+    }
+
+    assert_eq!(mtu.max_packet_size(), BASE_PACKET_SIZE);
+    assert!(mtu.next_probe_size().unwrap() < probe_size);
+}
+
+#[test]
+fn test_search_converges() {
+    let path_mtu = 1400;
+    let mut mtu = MtuDiscovery::new(DEFAULT_MAX_PACKET_SIZE);
+    let mut packet_number = 0;
+
+    while let Some(probe_size) = mtu.next_probe_size() {
+        packet_number += 1;
+        mtu.on_probe_sent(packet_number, probe_size);
+        if probe_size <= path_mtu {
+            mtu.on_packet_acked(packet_number, 0);
+        } else {
+            mtu.on_probe_lost(packet_number);
+        }
+    }
+
+    assert!(mtu.max_packet_size() <= path_mtu);
+    assert!(mtu.max_packet_size() > path_mtu - 16);
+}
+
+#[test]
+fn test_black_hole() {
+    let mut mtu = MtuDiscovery::new(1500);
+    let probe_size = mtu.next_probe_size().unwrap();
+    mtu.on_probe_sent(1, probe_size);
+    mtu.on_packet_acked(1, 0);
+
+    // packets that would fit a smaller path MTU getting lost don't count
+    for _ in 0..BLACK_HOLE_THRESHOLD {
+        mtu.on_packet_lost(BASE_PACKET_SIZE);
+        mtu.on_packet_lost(BASE_PACKET_SIZE + 1);
+    }
+    assert_eq!(mtu.max_packet_size(), probe_size);
+
+    for _ in 0..BLACK_HOLE_THRESHOLD {
+        mtu.on_packet_lost(probe_size);
+    }
+    assert_eq!(mtu.max_packet_size(), BASE_PACKET_SIZE);
+    assert!(mtu.next_probe_size().unwrap() < probe_size);
+}
+
+#[test]
+fn test_default_fits_ethernet_frames() {
+    let (mut client, _server, connection_id) = connect(IdleTimer::default(), Config::default(), Config::default());
+    let max_packet_size = client.paths(connection_id).unwrap()[0].max_packet_size;
+    assert!(max_packet_size > DEFAULT_MAX_PACKET_SIZE - 16 && max_packet_size <= DEFAULT_MAX_PACKET_SIZE);
+
+    // jumbo frames need to be asked for
+    client.write(connection_id, 1, &[7; 20000]).unwrap();
+    let packets = client.pop_pending_packets();
+    assert!(packets.iter().all(|packet| packet.payload.len() <= 1500 - 48));
+}
+
+#[test]
+fn test_discovery_raises_packet_size() {
+    let config = Config { max_packet_size: 4000, ..Config::default() };
+    let (mut client, _server, connection_id) = connect(IdleTimer::default(), config.clone(), config);
+
+    let max_packet_size = client.paths(connection_id).unwrap()[0].max_packet_size;
+    assert!(max_packet_size > 4000 - 16 && max_packet_size <= 4000);
+
+    // stream data now goes out in larger packets
+    client.write(connection_id, 1, &[7; 10000]).unwrap();
+    let packets = client.pop_pending_packets();
+    assert!(packets.iter().any(|packet| packet.payload.len() > BASE_PACKET_SIZE));
+    assert!(packets.iter().all(|packet| packet.payload.len() <= max_packet_size));
+}
+
+#[test]
+fn test_packets_with_several_streams_fit_the_path() {
+    let (mut client, _server, connection_id) = connect(IdleTimer::default(), Config::default(), Config::default());
+    let max_packet_size = client.paths(connection_id).unwrap()[0].max_packet_size;
+
+    // frames of different streams share packets, each with a header of its own
+    client.write(connection_id, 1, &[1; 700]).unwrap();
+    client.write(connection_id, 3, &[3; 700]).unwrap();
+    client.write(connection_id, 5, &vec![5; 3 * max_packet_size]).unwrap();
+    let packets = client.pop_pending_packets();
+    assert!(packets.iter().all(|packet| packet.payload.len() <= max_packet_size));
+    assert!(packets.iter().any(|packet| packet.payload.len() > max_packet_size - stream::StreamFrame::header_len(false)));
+}
+
+#[test]
+fn test_max_packet_size_below_base() {
+    let config = Config { max_packet_size: 1000, ..Config::default() };
+    let (mut client, _server, connection_id) = connect(IdleTimer::default(), config.clone(), config);
+    assert_eq!(client.paths(connection_id).unwrap()[0].max_packet_size, BASE_PACKET_SIZE);
+
+    client.write(connection_id, 1, &[7; 3000]).unwrap();
+    let packets = client.pop_pending_packets();
+    assert!(packets.iter().any(|packet| packet.payload.len() > 1000));
+    assert!(packets.iter().all(|packet| packet.payload.len() <= BASE_PACKET_SIZE));
+}
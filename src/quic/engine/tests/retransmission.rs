@@ -0,0 +1,68 @@
+use std::time;
+
+use quic::engine::config::Config;
+use quic::engine::rtt::MIN_RTO_MS;
+use quic::engine::timer::ManualTimer;
+use super::{address, connect, deliver, exchange_packets};
+
+
+#[test]
+fn test_lost_stream_data() {
+    let start = time::Instant::now();
+    let (mut client, mut server, connection_id) = connect(ManualTimer::new(start), Config::default(), Config::default());
+
+    let data: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+    client.write(connection_id, 1, &data).unwrap();
+    client.finalize_outgoing_stream(connection_id, 1).unwrap();
+
+    // every packet but the first one gets lost
+    let mut packets = client.pop_pending_packets();
+    assert!(packets.len() > 2);
+    packets.truncate(1);
+    deliver(&mut server, packets, address("127.0.0.1:1000"));
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+
+    // the data is sent again from the stream once the packets time out
+    client.handle_timeout(start + time::Duration::from_secs(1));
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+
+    let mut received = vec![];
+    let mut buf = [0; 4096];
+    loop {
+        let read_size = server.read(connection_id, 1, &mut buf).unwrap();
+        if read_size == 0 {
+            break;
+        }
+        received.extend_from_slice(&buf[..read_size]);
+    }
+    assert_eq!(received, data);
+
+    server.finalize_outgoing_stream(connection_id, 1).unwrap();
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+    assert!(client.is_finalized(connection_id));
+}
+
+#[test]
+fn test_retransmission_timeout() {
+    let start = time::Instant::now();
+    let (mut client, _server, connection_id) = connect(ManualTimer::new(start), Config::default(), Config::default());
+
+    let now = start + time::Duration::from_millis(500);
+    client.handle_timeout(now);
+    client.pop_pending_packets();
+    client.write(connection_id, 1, b"hello").unwrap();
+    assert!(!client.pop_pending_packets().is_empty());
+
+    // the handshake took no time, which leaves the smallest timeout
+    let timeout = time::Duration::from_millis(MIN_RTO_MS);
+    assert_eq!(client.next_timeout(), Some(now + timeout));
+
+    // the retransmission timer runs from the time the packet was sent
+    client.handle_timeout(now + timeout - time::Duration::from_millis(1));
+    assert!(client.pop_pending_packets().is_empty());
+    client.handle_timeout(now + timeout);
+    assert!(!client.pop_pending_packets().is_empty());
+
+    // and backs off until something gets acknowledged
+    assert_eq!(client.next_timeout(), Some(now + timeout * 3));
+}
@@ -0,0 +1,62 @@
+use quic::engine::send_buffer::{SendBuffer, CHUNK_SIZE};
+
+
+#[test]
+fn test_next_data() {
+    let mut buffer = SendBuffer::new();
+    buffer.write(&[1, 2, 3]);
+    buffer.write(&[4, 5]);
+
+    // limited by the flow control offset
+    assert_eq!(buffer.next_data(10, 4), Some((0, vec![1, 2, 3, 4])));
+    assert_eq!(buffer.next_data(10, 4), None);
+    assert_eq!(buffer.next_data(10, 100), Some((4, vec![5])));
+    assert_eq!(buffer.next_data(10, 100), None);
+    assert!(!buffer.is_empty());
+}
+
+#[test]
+fn test_retransmission() {
+    let mut buffer = SendBuffer::new();
+    let data: Vec<u8> = (0..10).collect();
+    buffer.write(&data);
+
+    assert_eq!(buffer.next_data(4, 100), Some((0, vec![0, 1, 2, 3])));
+    assert_eq!(buffer.next_data(4, 100), Some((4, vec![4, 5, 6, 7])));
+    assert_eq!(buffer.next_data(4, 100), Some((8, vec![8, 9])));
+
+    // lost data is sent again by offset before anything new
+    buffer.write(&[10]);
+    buffer.on_lost(0, 4);
+    buffer.on_lost(8, 2);
+    buffer.on_acked(1, 2);
+    assert_eq!(buffer.next_data(10, 100), Some((0, vec![0])));
+    assert_eq!(buffer.next_data(10, 100), Some((3, vec![3])));
+    assert_eq!(buffer.next_data(1, 100), Some((8, vec![8])));
+    assert_eq!(buffer.next_data(10, 100), Some((9, vec![9])));
+    assert_eq!(buffer.next_data(10, 100), Some((10, vec![10])));
+    assert_eq!(buffer.next_data(10, 100), None);
+
+    // acknowledged data is never resent
+    buffer.on_acked(0, 11);
+    buffer.on_lost(0, 11);
+    assert_eq!(buffer.next_data(10, 100), None);
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn test_acked_offset() {
+    let mut buffer = SendBuffer::new();
+    let data = vec![7; 3 * CHUNK_SIZE];
+    buffer.write(&data);
+    while buffer.next_data(1000, u64::MAX).is_some() {}
+
+    buffer.on_acked(CHUNK_SIZE as u64, CHUNK_SIZE);
+    assert_eq!(buffer.acked_offset(), 0);
+    assert_eq!(buffer.len(), 3 * CHUNK_SIZE);
+
+    // the gap closes and the first two chunks are released
+    buffer.on_acked(0, CHUNK_SIZE);
+    assert_eq!(buffer.acked_offset(), 2 * CHUNK_SIZE as u64);
+    assert_eq!(buffer.len(), CHUNK_SIZE);
+}
@@ -0,0 +1,229 @@
+use std::cmp::max;
+use std::collections::BTreeMap;
+use std::net;
+use std::time;
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use quic::engine::QuicEngine;
+use quic::engine::config::Config;
+use quic::engine::timer::ManualTimer;
+use quic::engine::udp_packet::IncomingUdpPacket;
+use super::address;
+
+
+/// How a simulated link treats the datagrams sent over it
+///
+/// Probabilities apply to every datagram independently.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinkConfig {
+    pub latency: time::Duration,
+    /// Bytes per second, unlimited if `None`
+    pub bandwidth: Option<u64>,
+    /// Larger datagrams are dropped
+    pub mtu: usize,
+    pub loss: f64,
+    pub duplication: f64,
+    /// Chance of a datagram being held back by up to `reorder_delay`
+    pub reordering: f64,
+    pub reorder_delay: time::Duration,
+    /// Chance of a random byte of the datagram being flipped
+    pub corruption: f64,
+}
+
+impl Default for LinkConfig {
+    fn default() -> LinkConfig {
+        LinkConfig {
+            latency: time::Duration::from_millis(10),
+            bandwidth: None,
+            mtu: 1472,
+            loss: 0.0,
+            duplication: 0.0,
+            reordering: 0.0,
+            reorder_delay: time::Duration::from_millis(20),
+            corruption: 0.0,
+        }
+    }
+}
+
+
+#[derive(Clone, Debug)]
+struct Link {
+    config: LinkConfig,
+    // when the last datagram finishes going out, for the bandwidth limit
+    busy_until: time::Instant,
+}
+
+
+#[derive(Clone, Debug)]
+struct Datagram {
+    to_server: bool,
+    source_address: net::SocketAddr,
+    payload: Vec<u8>,
+}
+
+
+/// A client and a server engine talking over simulated links, on a virtual clock
+///
+/// Everything random comes from the seed, so a run can be reproduced exactly.
+pub struct Simulator {
+    pub client: QuicEngine<ManualTimer>,
+    pub server: QuicEngine<ManualTimer>,
+    pub client_address: net::SocketAddr,
+    pub server_address: net::SocketAddr,
+
+    now: time::Instant,
+    rng: XorShiftRng,
+    upstream: Link,
+    downstream: Link,
+    // by arrival time, then by the order they were sent in
+    in_flight: BTreeMap<(time::Instant, u64), Datagram>,
+    sent_count: u64,
+}
+
+impl Simulator {
+    pub fn new(seed: u32, upstream: LinkConfig, downstream: LinkConfig) -> Simulator {
+        Simulator::with_config(seed, upstream, downstream, Config::default())
+    }
+
+    pub fn with_config(seed: u32, upstream: LinkConfig, downstream: LinkConfig, config: Config) -> Simulator {
+        let start = time::Instant::now();
+
+        Simulator {
+            client: QuicEngine::with_config(ManualTimer::new(start), false, config.clone()),
+            server: QuicEngine::with_config(ManualTimer::new(start), true, config),
+            client_address: address("127.0.0.1:1000"),
+            server_address: address("127.0.0.1:2000"),
+
+            now: start,
+            rng: XorShiftRng::from_seed([seed, 0x193a_6754, 0xa8a7_d469, 0x9783_0e05]),
+            upstream: Link { config: upstream, busy_until: start },
+            downstream: Link { config: downstream, busy_until: start },
+            in_flight: BTreeMap::new(),
+            sent_count: 0,
+        }
+    }
+
+    pub fn now(&self) -> time::Instant {
+        self.now
+    }
+
+    pub fn connect(&mut self) -> u64 {
+        let now = self.now;
+        let server_address = self.server_address;
+        self.client.initiate_connection(now, server_address)
+    }
+
+    /// Move the clock to the next arrival or timeout and handle it
+    ///
+    /// Returns false if nothing is left to happen.
+    pub fn step(&mut self) -> bool {
+        self.transmit_pending();
+
+        let next_arrival = self.in_flight.keys().next().map(|&(arrival, _)| arrival);
+        let next = [next_arrival, self.client.next_timeout(), self.server.next_timeout()]
+            .iter()
+            .filter_map(|&deadline| deadline)
+            .min();
+        let next = match next {
+            Some(next) => max(next, self.now),
+            None => return false,
+        };
+        self.now = next;
+
+        while let Some(&(arrival, sequence)) = self.in_flight.keys().next() {
+            if arrival > next {
+                break;
+            }
+            let datagram = self.in_flight.remove(&(arrival, sequence)).unwrap();
+            let engine = if datagram.to_server { &mut self.server } else { &mut self.client };
+            engine.handle_incoming_packet(next, IncomingUdpPacket {
+                source_address: datagram.source_address,
+                destination_address: None,
+                payload: datagram.payload,
+            });
+        }
+
+        if let Some(deadline) = self.client.next_timeout() {
+            if deadline <= next {
+                self.client.handle_timeout(next);
+            }
+        }
+        if let Some(deadline) = self.server.next_timeout() {
+            if deadline <= next {
+                self.server.handle_timeout(next);
+            }
+        }
+
+        self.transmit_pending();
+        true
+    }
+
+    /// Step until `condition` holds, fails the test if it doesn't within `limit` of virtual time
+    pub fn run_until<F>(&mut self, limit: time::Duration, mut condition: F)
+            where F: FnMut(&mut Simulator) -> bool {
+        let deadline = self.now + limit;
+
+        while !condition(self) {
+            assert!(self.now <= deadline, "Condition not reached within {:?}", limit);
+            assert!(self.step(), "Nothing left to happen");
+        }
+    }
+
+    fn transmit_pending(&mut self) {
+        for packet in self.client.pop_pending_packets() {
+            let source_address = self.client_address;
+            self.transmit(true, source_address, packet.payload);
+        }
+        for packet in self.server.pop_pending_packets() {
+            let source_address = self.server_address;
+            self.transmit(false, source_address, packet.payload);
+        }
+    }
+
+    fn transmit(&mut self, to_server: bool, source_address: net::SocketAddr, mut payload: Vec<u8>) {
+        let now = self.now;
+        let link = if to_server { &mut self.upstream } else { &mut self.downstream };
+        let config = link.config.clone();
+
+        if payload.len() > config.mtu || self.rng.gen::<f64>() < config.loss {
+            return;
+        }
+
+        let mut departure = max(now, link.busy_until);
+        if let Some(bandwidth) = config.bandwidth {
+            let nanos = payload.len() as u64 * 1_000_000_000 / bandwidth;
+            departure += time::Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32);
+        }
+        link.busy_until = departure;
+
+        let mut arrival = departure + config.latency;
+        if self.rng.gen::<f64>() < config.reordering {
+            let max_delay_ms = config.reorder_delay.as_secs() * 1000 + u64::from(config.reorder_delay.subsec_millis());
+            arrival += time::Duration::from_millis(self.rng.gen_range(0, max_delay_ms + 1));
+        }
+
+        if !payload.is_empty() && self.rng.gen::<f64>() < config.corruption {
+            let index = self.rng.gen_range(0, payload.len());
+            payload[index] ^= self.rng.gen_range(1, 256) as u8;
+        }
+
+        if self.rng.gen::<f64>() < config.duplication {
+            self.enqueue(arrival, Datagram {
+                to_server,
+                source_address,
+                payload: payload.clone(),
+            });
+        }
+        self.enqueue(arrival, Datagram {
+            to_server,
+            source_address,
+            payload,
+        });
+    }
+
+    fn enqueue(&mut self, arrival: time::Instant, datagram: Datagram) {
+        self.sent_count += 1;
+        self.in_flight.insert((arrival, self.sent_count), datagram);
+    }
+}
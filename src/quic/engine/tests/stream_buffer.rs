@@ -1,4 +1,4 @@
-use quic::engine::stream_buffer::StreamBuffer;
+use quic::engine::stream_buffer::{StreamBuffer, MAX_CHUNK_SIZE};
 use quic::errors::Error;
 
 
@@ -65,3 +65,74 @@ fn test_mismatch() {
         _ => assert!(false, "Buffer mismatch expected"),
     };
 }
+
+
+#[test]
+fn test_overlapping_data() {
+    let mut buffer = StreamBuffer::new(100);
+    let mut buf = [0; 10];
+
+    buffer.add_data(2, &[3, 4]).unwrap();
+    buffer.add_data(6, &[7, 8]).unwrap();
+    assert!(!buffer.is_readable());
+
+    // only the gaps of a range spanning several chunks are taken
+    buffer.add_data(1, &[2, 3, 4, 5, 6, 7]).unwrap();
+    buffer.add_data(0, &[1, 2]).unwrap();
+    assert!(buffer.is_readable());
+
+    match buffer.add_data(5, &[6, 0]) {
+        Err(Error::InvalidData(..)) => {},
+        _ => assert!(false, "Buffer mismatch expected"),
+    };
+
+    assert_eq!(buffer.pull_data(&mut buf[..3]), 3);
+    assert_eq!(buffer.pull_data(&mut buf[3..]), 5);
+    assert_eq!(buf[..8], [1, 2, 3, 4, 5, 6, 7, 8]);
+    assert!(buffer.is_empty());
+}
+
+
+#[test]
+fn test_retransmitted_read_data() {
+    let mut buffer = StreamBuffer::new(100);
+    let mut buf = [0; 10];
+
+    buffer.add_data(0, &[1, 2, 3]).unwrap();
+    assert_eq!(buffer.pull_data(&mut buf), 3);
+
+    // a retransmission spanning read and new data, and one of read data only
+    buffer.add_data(1, &[2, 3, 4, 5]).unwrap();
+    buffer.add_data(0, &[1]).unwrap();
+    assert_eq!(buffer.pull_data(&mut buf), 2);
+    assert_eq!(buf[..2], [4, 5]);
+
+    // read bytes are gone and can't be compared, buffered ones still can
+    buffer.add_data(6, &[7, 8]).unwrap();
+    buffer.add_data(3, &[0, 0, 6, 7]).unwrap();
+    match buffer.add_data(4, &[0, 6, 0]) {
+        Err(Error::InvalidData(..)) => {},
+        _ => assert!(false, "Buffer mismatch expected"),
+    };
+    assert_eq!(buffer.pull_data(&mut buf), 3);
+    assert_eq!(buf[..3], [6, 7, 8]);
+}
+
+
+#[test]
+fn test_fragmented_data() {
+    const SIZE: usize = 2 * MAX_CHUNK_SIZE;
+    let data: Vec<u8> = (0..SIZE).map(|i| (i % 251) as u8).collect();
+    let mut buffer = StreamBuffer::new(SIZE);
+
+    // a byte per frame, the odd offsets first
+    for offset in (1..SIZE).step_by(2).chain((0..SIZE).step_by(2)) {
+        buffer.add_data(offset as u64, &data[offset..offset + 1]).unwrap();
+    }
+    assert!(buffer.chunk_count() <= 2 * SIZE / MAX_CHUNK_SIZE);
+
+    let mut received = vec![0; SIZE];
+    assert_eq!(buffer.pull_data(&mut received), SIZE);
+    assert!(received == data);
+    assert!(buffer.is_empty());
+}
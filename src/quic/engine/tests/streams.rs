@@ -0,0 +1,238 @@
+use quic::engine::QuicEngine;
+use quic::engine::config::Config;
+use quic::engine::event::Event;
+use quic::engine::udp_packet::IncomingUdpPacket;
+use quic::errors::{codes, Error};
+use quic::packets;
+use quic::packets::frames::{Frame, stream};
+use super::{address, connect, deliver, exchange_packets, IdleTimer};
+
+
+/// Send the server a STREAM frame the client engine would never produce
+fn send_stream_frame(server: &mut QuicEngine<IdleTimer>, connection_id: u64, stream_id: u32) {
+    let packet = packets::Packet::Regular(packets::RegularPacket {
+        header: packets::PacketHeader {
+            key_phase: false,
+            packet_number_size: 4,
+            multipath: false,
+
+            connection_id: Some(connection_id),
+            path_id: 0,
+        },
+
+        version: None,
+        packet_number: 1000,
+        payload: packets::PacketPayload {
+            frames: vec![
+                Frame::Stream(stream::StreamFrame {
+                    stream_id,
+                    offset: 0,
+                    stream_data: vec![1, 2, 3],
+                    fin: false,
+                }),
+            ],
+        },
+    });
+    let mut payload = vec![];
+    packet.encode(&mut payload).unwrap();
+    let now = server.now();
+    server.handle_incoming_packet(now, IncomingUdpPacket {
+        source_address: address("127.0.0.1:1000"),
+        destination_address: None,
+        payload,
+    });
+}
+
+fn assert_closed(engine: &mut QuicEngine<IdleTimer>, connection_id: u64, error_code: u32) {
+    let mut buf = [0; 16];
+    match engine.read(connection_id, 1, &mut buf) {
+        Err(Error::ConnectionClosed(code)) => assert_eq!(code, error_code),
+        _ => assert!(false, "Closed connection expected"),
+    }
+    assert!(engine.is_finalized(connection_id));
+}
+
+
+#[test]
+fn test_stream_parity() {
+    let (mut client, mut server, connection_id) = connect(IdleTimer::default(), Config::default(), Config::default());
+
+    // stream 0 is reserved and even streams belong to the server
+    match client.write(connection_id, 0, b"hello") {
+        Err(Error::InvalidStream) => {},
+        _ => assert!(false, "Error expected"),
+    }
+    match client.write(connection_id, 2, b"hello") {
+        Err(Error::InvalidStream) => {},
+        _ => assert!(false, "Error expected"),
+    }
+
+    client.write(connection_id, 1, b"hello").unwrap();
+    server.write(connection_id, 2, b"world").unwrap();
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+
+    // once the peer opened a stream, both sides can use it
+    assert!(server.data_available(connection_id, 1));
+    assert!(client.data_available(connection_id, 2));
+    server.write(connection_id, 1, b"back").unwrap();
+    client.write(connection_id, 2, b"back").unwrap();
+}
+
+#[test]
+fn test_invalid_stream_id() {
+    let (mut client, mut server, connection_id) = connect(IdleTimer::default(), Config::default(), Config::default());
+
+    // the client can't open a server stream
+    send_stream_frame(&mut server, connection_id, 4);
+    assert_closed(&mut server, connection_id, codes::QUIC_INVALID_STREAM_ID);
+
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+    assert_closed(&mut client, connection_id, codes::QUIC_INVALID_STREAM_ID);
+}
+
+#[test]
+fn test_too_many_open_streams() {
+    let config = Config { max_open_streams: 2, ..Config::default() };
+    let (mut client, mut server, connection_id) = connect(IdleTimer::default(), Config::default(), config);
+
+    // the limit is announced to the client
+    client.write(connection_id, 1, b"hello").unwrap();
+    client.write(connection_id, 3, b"hello").unwrap();
+    match client.write(connection_id, 5, b"hello") {
+        Err(Error::TooManyOpenStreams) => {},
+        _ => assert!(false, "Error expected"),
+    }
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+    assert!(server.data_available(connection_id, 3));
+
+    // a misbehaving client gets disconnected instead of allocating every stream up to the id
+    send_stream_frame(&mut server, connection_id, 0xFFFFFFFF);
+    assert_closed(&mut server, connection_id, codes::QUIC_TOO_MANY_OPEN_STREAMS);
+}
+
+#[test]
+fn test_closed_streams_are_freed() {
+    let config = Config { max_open_streams: 2, ..Config::default() };
+    let (mut client, mut server, connection_id) = connect(IdleTimer::default(), Config::default(), config);
+    let mut buf = [0; 16];
+
+    // far more short-lived streams than may be open at once
+    for stream_id in (0..20).map(|i| 2 * i + 1) {
+        client.write(connection_id, stream_id, b"ping").unwrap();
+        client.finalize_outgoing_stream(connection_id, stream_id).unwrap();
+        exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+
+        assert_eq!(server.read(connection_id, stream_id, &mut buf).unwrap(), 4);
+        assert_eq!(server.read(connection_id, stream_id, &mut buf).unwrap(), 0);
+        server.write(connection_id, stream_id, b"pong").unwrap();
+        server.finalize_outgoing_stream(connection_id, stream_id).unwrap();
+        exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+
+        assert_eq!(client.read(connection_id, stream_id, &mut buf).unwrap(), 4);
+        assert_eq!(client.read(connection_id, stream_id, &mut buf).unwrap(), 0);
+        exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+    }
+
+    // a late retransmission doesn't reopen a closed stream
+    send_stream_frame(&mut server, connection_id, 1);
+    assert!(server.data_available(connection_id, 1));
+    assert_eq!(server.read(connection_id, 1, &mut buf).unwrap(), 0);
+    match server.write(connection_id, 1, b"again") {
+        Err(Error::InvalidStream) => {},
+        _ => assert!(false, "Error expected"),
+    }
+
+    assert!(client.is_finalized(connection_id));
+    assert!(server.is_finalized(connection_id));
+}
+
+#[test]
+fn test_send_buffer_limit() {
+    let config = Config { stream_send_buffer: 3000, connection_send_buffer: 5000, ..Config::default() };
+    let (mut client, mut server, connection_id) = connect(IdleTimer::default(), config, Config::default());
+
+    // writes are cut short by the stream limit, then by the connection limit
+    let data = [7; 4000];
+    assert_eq!(client.write(connection_id, 1, &data).unwrap(), 3000);
+    assert_eq!(client.write(connection_id, 1, &data).unwrap(), 0);
+    assert!(!client.is_writable(connection_id, 1));
+    assert_eq!(client.write(connection_id, 3, &data).unwrap(), 2000);
+    assert!(!client.is_writable(connection_id, 5));
+
+    // acknowledgements free the space again
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+    assert!(client.is_writable(connection_id, 1));
+    assert_eq!(client.write(connection_id, 1, &data).unwrap(), 3000);
+}
+
+#[test]
+fn test_acked_offset() {
+    let (mut client, mut server, connection_id) = connect(IdleTimer::default(), Config::default(), Config::default());
+
+    client.write(connection_id, 1, &[7; 2000]).unwrap();
+    assert_eq!(client.acked_offset(connection_id, 1).unwrap(), 0);
+    assert!(!client.is_flushed(connection_id, 1).unwrap());
+
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+    assert_eq!(client.acked_offset(connection_id, 1).unwrap(), 2000);
+    assert!(client.is_flushed(connection_id, 1).unwrap());
+
+    // unacknowledged data can't be flushed anymore once the connection is gone
+    client.write(connection_id, 1, &[7; 10]).unwrap();
+    client.close_connection(connection_id, codes::QUIC_NO_ERROR).unwrap();
+    match client.is_flushed(connection_id, 1) {
+        Err(Error::ConnectionClosed(..)) => {},
+        _ => assert!(false, "Closed connection expected"),
+    }
+}
+
+#[test]
+fn test_flushed_while_connection_buffer_full() {
+    let config = Config { connection_send_buffer: 5000, ..Config::default() };
+    let (mut client, mut server, connection_id) = connect(IdleTimer::default(), config, Config::default());
+    while client.poll_event().is_some() {}
+
+    client.write(connection_id, 1, &[1; 1000]).unwrap();
+    let packets = client.pop_pending_packets();
+    assert_eq!(client.write(connection_id, 3, &[3; 5000]).unwrap(), 4000);
+    let other_packets = client.pop_pending_packets();
+    assert!(!client.is_writable(connection_id, 1));
+    assert!(!client.is_flushed(connection_id, 1).unwrap());
+
+    // room in the connection's buffer wakes the partly written stream, not the flush
+    deliver(&mut server, other_packets, address("127.0.0.1:1000"));
+    deliver(&mut client, server.pop_pending_packets(), address("127.0.0.1:2000"));
+    assert!(client.is_writable(connection_id, 1));
+    assert_eq!(client.poll_event(), Some(Event::StreamWritable(connection_id, 3)));
+    assert_eq!(client.poll_event(), None);
+
+    deliver(&mut server, packets, address("127.0.0.1:1000"));
+    deliver(&mut client, server.pop_pending_packets(), address("127.0.0.1:2000"));
+    assert!(client.is_flushed(connection_id, 1).unwrap());
+    assert_eq!(client.poll_event(), Some(Event::StreamWritable(connection_id, 1)));
+    assert_eq!(client.poll_event(), None);
+}
+
+#[test]
+fn test_open_and_accept_streams() {
+    let (mut client, mut server, connection_id) = connect(IdleTimer::default(), Config::default(), Config::default());
+
+    assert_eq!(client.open_stream(connection_id).unwrap(), 1);
+    assert_eq!(client.open_stream(connection_id).unwrap(), 3);
+    assert_eq!(server.open_stream(connection_id).unwrap(), 2);
+
+    // the peer learns about a stream when data arrives, lower ids are opened implicitly
+    client.write(connection_id, 3, b"hello").unwrap();
+    exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+    assert!(server.has_new_streams(connection_id));
+    assert_eq!(server.accept_stream(connection_id).unwrap(), Some(1));
+    assert_eq!(server.accept_stream(connection_id).unwrap(), Some(3));
+    assert_eq!(server.accept_stream(connection_id).unwrap(), None);
+    assert!(!client.has_new_streams(connection_id));
+
+    server.close_connection(connection_id, codes::QUIC_NO_ERROR).unwrap();
+    match server.accept_stream(connection_id) {
+        Err(Error::ConnectionClosed(..)) => {},
+        _ => assert!(false, "Closed connection expected"),
+    }
+}
@@ -0,0 +1,64 @@
+use std::time;
+
+use quic::engine::config::Config;
+use quic::engine::timer::{HeapTimer, ManualTimer, ScheduledEvent, Timer};
+use super::{address, connect, exchange_packets};
+
+
+#[test]
+fn test_events_in_deadline_order() {
+    let start = time::Instant::now();
+    let mut timer = HeapTimer::new();
+    assert_eq!(timer.next_timeout(), None);
+
+    timer.schedule(start + time::Duration::from_millis(30), ScheduledEvent::IdleTimeout(3));
+    timer.schedule(start + time::Duration::from_millis(10), ScheduledEvent::IdleTimeout(1));
+    timer.schedule(start + time::Duration::from_millis(20), ScheduledEvent::IdleTimeout(2));
+    timer.schedule(start + time::Duration::from_millis(10), ScheduledEvent::DrainingTimeout(1));
+    assert_eq!(timer.next_timeout(), Some(start + time::Duration::from_millis(10)));
+
+    assert_eq!(timer.pop_due_events(start + time::Duration::from_millis(5)), vec![]);
+    assert_eq!(timer.pop_due_events(start + time::Duration::from_millis(20)), vec![
+        ScheduledEvent::IdleTimeout(1),
+        ScheduledEvent::DrainingTimeout(1),
+        ScheduledEvent::IdleTimeout(2),
+    ]);
+    assert_eq!(timer.next_timeout(), Some(start + time::Duration::from_millis(30)));
+}
+
+#[test]
+fn test_cancel() {
+    let start = time::Instant::now();
+    let mut timer = HeapTimer::new();
+
+    let first = timer.schedule(start + time::Duration::from_millis(10), ScheduledEvent::IdleTimeout(1));
+    let second = timer.schedule(start + time::Duration::from_millis(20), ScheduledEvent::IdleTimeout(2));
+    timer.schedule(start + time::Duration::from_millis(30), ScheduledEvent::DrainingTimeout(2));
+    timer.schedule(start + time::Duration::from_millis(40), ScheduledEvent::IdleTimeout(3));
+
+    // the next timeout is always one that will fire
+    timer.cancel(first);
+    assert_eq!(timer.next_timeout(), Some(start + time::Duration::from_millis(20)));
+    timer.cancel_connection(2);
+    assert_eq!(timer.next_timeout(), Some(start + time::Duration::from_millis(40)));
+    assert_eq!(timer.len(), 1);
+
+    // cancelling twice or after firing does nothing
+    timer.cancel(second);
+    assert_eq!(timer.pop_due_events(start + time::Duration::from_secs(1)), vec![ScheduledEvent::IdleTimeout(3)]);
+    assert!(timer.is_empty());
+    assert_eq!(timer.next_timeout(), None);
+}
+
+#[test]
+fn test_acknowledged_packets_cancel_their_timers() {
+    let start = time::Instant::now();
+    let (mut client, mut server, connection_id) = connect(ManualTimer::new(start), Config::default(), Config::default());
+    let scheduled = client.timer_ref().len();
+
+    for _ in 0..10 {
+        client.write(connection_id, 1, b"hello").unwrap();
+        exchange_packets(&mut client, address("127.0.0.1:1000"), &mut server, address("127.0.0.1:2000"));
+    }
+    assert_eq!(client.timer_ref().len(), scheduled);
+}
@@ -0,0 +1,112 @@
+use std::time;
+
+use quic::engine::connection::ConnectionState;
+use super::simulator::{LinkConfig, Simulator};
+
+
+/// Send `size` bytes from the client to the server on stream 1 and check what arrives
+///
+/// Returns the id of the connection.
+fn transfer(simulator: &mut Simulator, size: usize, limit: time::Duration) -> u64 {
+    let connection_id = simulator.connect();
+    let data: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+
+    let mut written = 0;
+    let mut received = vec![];
+    let mut finished = false;
+    simulator.run_until(limit, |simulator| {
+        if written < data.len() {
+            if let Ok(written_size) = simulator.client.write(connection_id, 1, &data[written..]) {
+                written += written_size;
+                if written == data.len() {
+                    simulator.client.finalize_outgoing_stream(connection_id, 1).unwrap();
+                }
+            }
+        }
+
+        // the server only knows the connection once the first packet arrived
+        let mut buf = [0; 4096];
+        let accepted = simulator.server.connection_state(connection_id).is_some();
+        while accepted && !finished && simulator.server.data_available(connection_id, 1) {
+            match simulator.server.read(connection_id, 1, &mut buf).unwrap() {
+                0 => finished = true,
+                read_size => received.extend_from_slice(&buf[..read_size]),
+            }
+        }
+
+        finished && simulator.client.is_flushed(connection_id, 1).unwrap()
+    });
+
+    assert_eq!(received.len(), data.len());
+    assert!(received == data, "Received data differs");
+    assert_eq!(simulator.client.connection_state(connection_id), Some(ConnectionState::Established));
+
+    connection_id
+}
+
+
+#[test]
+fn test_transfer_over_clean_link() {
+    let link = LinkConfig {
+        bandwidth: Some(10 * 1024 * 1024),
+        ..LinkConfig::default()
+    };
+    let mut simulator = Simulator::new(1, link.clone(), link);
+
+    transfer(&mut simulator, 200 * 1024, time::Duration::from_secs(10));
+}
+
+#[test]
+fn test_transfer_over_high_rtt_link() {
+    // a 600ms round trip, well beyond the smallest retransmission timeout
+    let link = LinkConfig {
+        latency: time::Duration::from_millis(300),
+        bandwidth: Some(10 * 1024 * 1024),
+        ..LinkConfig::default()
+    };
+    let mut simulator = Simulator::new(1, link.clone(), link);
+
+    let connection_id = transfer(&mut simulator, 200 * 1024, time::Duration::from_secs(30));
+    for path in simulator.client.paths(connection_id).unwrap() {
+        assert_eq!(path.lost_packets, 0);
+    }
+    for path in simulator.server.paths(connection_id).unwrap() {
+        assert_eq!(path.lost_packets, 0);
+    }
+}
+
+#[test]
+fn test_transfer_over_lossy_link() {
+    let link = LinkConfig {
+        latency: time::Duration::from_millis(30),
+        bandwidth: Some(1024 * 1024),
+        loss: 0.1,
+        duplication: 0.05,
+        reordering: 0.1,
+        ..LinkConfig::default()
+    };
+
+    for seed in 1..6 {
+        let mut simulator = Simulator::new(seed, link.clone(), link.clone());
+        transfer(&mut simulator, 100 * 1024, time::Duration::from_secs(60));
+    }
+}
+
+#[test]
+fn test_simulation_is_reproducible() {
+    let link = LinkConfig {
+        loss: 0.2,
+        reordering: 0.2,
+        ..LinkConfig::default()
+    };
+
+    let mut durations = vec![];
+    for _ in 0..2 {
+        let mut simulator = Simulator::new(7, link.clone(), link.clone());
+        let start = simulator.now();
+        transfer(&mut simulator, 50 * 1024, time::Duration::from_secs(60));
+        durations.push(simulator.now() - start);
+    }
+
+    assert_eq!(durations[0], durations[1]);
+}
@@ -1,17 +1,211 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::time;
 
-use quic::packets;
-
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum ScheduledEvent {
-    ResendUnackedPacket(packets::Packet),
+    ResendUnackedPacket(u64, u8, u64),
+    PathValidationTimeout(u64, u64),
+    MtuProbeTimeout(u64, u8, u64),
+    IdleTimeout(u64),
+    DrainingTimeout(u64),
+}
+
+impl ScheduledEvent {
+    pub fn connection_id(&self) -> u64 {
+        match *self {
+            ScheduledEvent::ResendUnackedPacket(connection_id, _, _) |
+            ScheduledEvent::PathValidationTimeout(connection_id, _) |
+            ScheduledEvent::MtuProbeTimeout(connection_id, _, _) |
+            ScheduledEvent::IdleTimeout(connection_id) |
+            ScheduledEvent::DrainingTimeout(connection_id) => connection_id,
+        }
+    }
 }
 
+
+/// Identifies a scheduled event, to cancel it
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct TimerToken(pub u64);
+
+
+/// Keeps the engine's scheduled events, the engine tells it what time it is
 pub trait Timer {
-    fn now(&self) -> time::Instant;
+    fn schedule(&mut self, deadline: time::Instant, event: ScheduledEvent) -> TimerToken;
+
+    /// Drop an event that didn't fire yet, unknown tokens are ignored
+    fn cancel(&mut self, token: TimerToken);
+
+    /// Drop every event of a connection
+    fn cancel_connection(&mut self, connection_id: u64);
+
+    /// The deadline of the earliest event
+    fn next_timeout(&self) -> Option<time::Instant>;
+
+    /// Events due at `now`, earliest first
+    fn pop_due_events(&mut self, now: time::Instant) -> Vec<ScheduledEvent>;
+}
+
+
+/// Events ordered by deadline in a binary heap
+///
+/// Cancelled events stay in the heap until they reach the top, so both
+/// scheduling and cancelling are O(log n).
+#[derive(Clone, Debug, Default)]
+pub struct HeapTimer {
+    next_token: u64,
+    // events scheduled for the same time fire in the order they were scheduled in
+    deadlines: BinaryHeap<Reverse<(time::Instant, u64)>>,
+    events: HashMap<u64, ScheduledEvent>,
+    connection_tokens: HashMap<u64, HashSet<u64>>,
+}
+
+impl HeapTimer {
+    pub fn new() -> HeapTimer {
+        HeapTimer::default()
+    }
+
+    /// How many events are waiting to fire
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    fn remove_event(&mut self, token: u64) -> Option<ScheduledEvent> {
+        let event = self.events.remove(&token)?;
+
+        let connection_id = event.connection_id();
+        let connection_done = match self.connection_tokens.get_mut(&connection_id) {
+            Some(tokens) => {
+                tokens.remove(&token);
+                tokens.is_empty()
+            },
+            None => false,
+        };
+        if connection_done {
+            self.connection_tokens.remove(&connection_id);
+        }
+
+        Some(event)
+    }
+
+    // keeps the earliest deadline a live one, for `next_timeout`
+    fn discard_cancelled(&mut self) {
+        while let Some(&Reverse((_, token))) = self.deadlines.peek() {
+            if self.events.contains_key(&token) {
+                break;
+            }
+            self.deadlines.pop();
+        }
+    }
+}
+
+impl Timer for HeapTimer {
+    fn schedule(&mut self, deadline: time::Instant, event: ScheduledEvent) -> TimerToken {
+        let token = self.next_token;
+        self.next_token += 1;
+
+        self.connection_tokens.entry(event.connection_id()).or_default().insert(token);
+        self.events.insert(token, event);
+        self.deadlines.push(Reverse((deadline, token)));
+
+        TimerToken(token)
+    }
+
+    fn cancel(&mut self, token: TimerToken) {
+        self.remove_event(token.0);
+        self.discard_cancelled();
+    }
+
+    fn cancel_connection(&mut self, connection_id: u64) {
+        if let Some(tokens) = self.connection_tokens.remove(&connection_id) {
+            for token in tokens {
+                self.events.remove(&token);
+            }
+        }
+        self.discard_cancelled();
+    }
+
+    fn next_timeout(&self) -> Option<time::Instant> {
+        self.deadlines.peek().map(|&Reverse((deadline, _))| deadline)
+    }
+
+    fn pop_due_events(&mut self, now: time::Instant) -> Vec<ScheduledEvent> {
+        let mut due = vec![];
+        while let Some(&Reverse((deadline, token))) = self.deadlines.peek() {
+            if deadline > now {
+                break;
+            }
+
+            self.deadlines.pop();
+            if let Some(event) = self.remove_event(token) {
+                due.push(event);
+            }
+        }
+        self.discard_cancelled();
+
+        due
+    }
+}
+
+
+/// A timer with a clock that only moves on `advance`, for driving engines deterministically
+#[derive(Clone, Debug)]
+pub struct ManualTimer {
+    now: time::Instant,
+    timer: HeapTimer,
+}
+
+impl ManualTimer {
+    pub fn new(start: time::Instant) -> ManualTimer {
+        ManualTimer {
+            now: start,
+            timer: HeapTimer::new(),
+        }
+    }
+
+    pub fn now(&self) -> time::Instant {
+        self.now
+    }
+
+    /// Move the clock forward, returns the new time to hand to the engine
+    pub fn advance(&mut self, duration: time::Duration) -> time::Instant {
+        self.now += duration;
+        self.now
+    }
+
+    /// How many events are waiting to fire
+    pub fn len(&self) -> usize {
+        self.timer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timer.is_empty()
+    }
+}
+
+impl Timer for ManualTimer {
+    fn schedule(&mut self, deadline: time::Instant, event: ScheduledEvent) -> TimerToken {
+        self.timer.schedule(deadline, event)
+    }
+
+    fn cancel(&mut self, token: TimerToken) {
+        self.timer.cancel(token)
+    }
+
+    fn cancel_connection(&mut self, connection_id: u64) {
+        self.timer.cancel_connection(connection_id)
+    }
 
-    fn schedule(&mut self, when: time::Duration, event: ScheduledEvent);
+    fn next_timeout(&self) -> Option<time::Instant> {
+        self.timer.next_timeout()
+    }
 
-    fn pop_due_events(&mut self) -> Vec<ScheduledEvent>;
+    fn pop_due_events(&mut self, now: time::Instant) -> Vec<ScheduledEvent> {
+        self.timer.pop_due_events(now)
+    }
 }
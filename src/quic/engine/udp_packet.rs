@@ -4,11 +4,15 @@ use std::net;
 #[derive(Clone, Debug, PartialEq)]
 pub struct IncomingUdpPacket {
     pub source_address: net::SocketAddr,
+    // the local address the packet arrived on, if the caller knows it
+    pub destination_address: Option<net::SocketAddr>,
     pub payload: Vec<u8>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct OutgoingUdpPacket {
+    // the local address to send from, None for the default socket
+    pub source_address: Option<net::SocketAddr>,
     pub destination_address: net::SocketAddr,
     pub payload: Vec<u8>,
 }
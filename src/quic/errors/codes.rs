@@ -1,3 +1,4 @@
+pub const QUIC_NO_ERROR: u32 = 0x80000000;
 pub const QUIC_INTERNAL_ERROR: u32 = 0x80000001;
 pub const QUIC_STREAM_DATA_AFTER_TERMINATION: u32 = 0x80000002;
 pub const QUIC_INVALID_PACKET_HEADER: u32 = 0x80000003;
@@ -8,11 +8,14 @@ use std::io;
 #[derive(Debug)]
 pub enum Error {
     BufferOverflow,
+    ConnectionClosed(u32),
     Decoding(String),
+    InvalidConnection,
     InvalidData(String),
     InvalidHandle,
     InvalidStream,
     Io(io::Error),
+    TooManyOpenStreams,
     UnsupportedVersion(u32),
 }
 
@@ -25,10 +28,18 @@ impl std::convert::Into<io::Error> for Error {
                 io::ErrorKind::InvalidData,
                 self,
             ),
+            Error::ConnectionClosed(..) => io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                self,
+            ),
             Error::Decoding(..) => io::Error::new(
                 io::ErrorKind::InvalidData,
                 self,
             ),
+            Error::InvalidConnection => io::Error::new(
+                io::ErrorKind::NotConnected,
+                self,
+            ),
             Error::InvalidData(..) => io::Error::new(
                 io::ErrorKind::InvalidData,
                 self,
@@ -42,6 +53,10 @@ impl std::convert::Into<io::Error> for Error {
                 self,
             ),
             Error::Io(io_error) => io_error,
+            Error::TooManyOpenStreams => io::Error::new(
+                io::ErrorKind::Other,
+                self,
+            ),
             Error::UnsupportedVersion(..) => io::Error::new(
                 io::ErrorKind::InvalidData,
                 self,
@@ -54,11 +69,14 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::BufferOverflow => write!(f, "Incoming buffer overflow"),
+            Error::ConnectionClosed(error_code) => write!(f, "Connection closed, error code: {:#x}", error_code),
             Error::Decoding(ref message) => message.fmt(f),
+            Error::InvalidConnection => write!(f, "Invalid connection"),
             Error::InvalidData(ref message) => message.fmt(f),
             Error::InvalidHandle => write!(f, "Invalid handle"),
             Error::InvalidStream => write!(f, "Invalid stream"),
             Error::Io(ref io_error) => io_error.fmt(f),
+            Error::TooManyOpenStreams => write!(f, "Too many open streams"),
             Error::UnsupportedVersion(version) => write!(f, "Unsupported version: {}", version),
         }
     }
@@ -68,11 +86,14 @@ impl std::error::Error for Error {
     fn description(&self) -> &str {
         match *self {
             Error::BufferOverflow => "Incoming buffer overflow",
+            Error::ConnectionClosed(..) => "Connection closed",
             Error::Decoding(ref message) => message,
+            Error::InvalidConnection => "Invalid connection",
             Error::InvalidData(ref message) => message,
             Error::InvalidHandle => "Invalid handle",
             Error::InvalidStream => "Invalid stream",
             Error::Io(ref io_error) => io_error.description(),
+            Error::TooManyOpenStreams => "Too many open streams",
             Error::UnsupportedVersion(..) => "Unsupported version",
         }
     }
@@ -80,11 +101,14 @@ impl std::error::Error for Error {
     fn cause(&self) -> Option<&std::error::Error> {
         match *self {
             Error::BufferOverflow => None,
+            Error::ConnectionClosed(..) => None,
             Error::Decoding(..) => None,
+            Error::InvalidConnection => None,
             Error::InvalidData(..) => None,
             Error::InvalidHandle => None,
             Error::InvalidStream => None,
             Error::Io(ref io_error) => Some(io_error),
+            Error::TooManyOpenStreams => None,
             Error::UnsupportedVersion(..) => None,
         }
     }
@@ -3,7 +3,7 @@ use std::io;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use cast;
 
-use quic::errors::Result;
+use quic::errors::{Error, Result};
 use quic::packets::utils::{map_unexpected_eof, truncate_u64};
 
 
@@ -112,7 +112,9 @@ impl AckFrame {
     pub fn decode<R: io::Read>(read: &mut R) -> Result<AckFrame> {
         // extract type octet data
         let frame_type = read.read_u8()?;
-        assert!((frame_type & MASK_ACK) == FLAG_ACK);
+        if (frame_type & MASK_ACK) != FLAG_ACK {
+            return Err(Error::Decoding(format!("Invalid ack frame type {:#x}", frame_type)));
+        }
 
         let has_extra_ack_blocks = (frame_type & FLAG_EXTRA_ACK_BLOCKS) != 0;
 
@@ -161,14 +163,14 @@ impl AckFrame {
                 read.read_uint::<BigEndian>(ack_block_size)
                 .map_err(map_unexpected_eof)?;
 
-            extra_ack_blocks.push(ExtraAckBlock { gap: gap, block_length: block_length });
+            extra_ack_blocks.push(ExtraAckBlock { gap, block_length });
         }
 
         // timestamp section
         let first_timestamp = if timestamp_count > 0 {
             let delta_la = read.read_u8().map_err(map_unexpected_eof)?;
             let delta_timestamp = read.read_u32::<BigEndian>().map_err(map_unexpected_eof)?;
-            Some(FirstAckTimestamp { delta_la: delta_la, delta_timestamp: delta_timestamp })
+            Some(FirstAckTimestamp { delta_la, delta_timestamp })
         } else {
             None
         };
@@ -178,19 +180,19 @@ impl AckFrame {
             for _ in 0..(timestamp_count - 1) {
                 let delta_la = read.read_u8().map_err(map_unexpected_eof)?;
                 let delta_timestamp = read.read_u16::<BigEndian>().map_err(map_unexpected_eof)?;
-                extra_timestamps.push(ExtraAckTimestamp { delta_la: delta_la, delta_timestamp: delta_timestamp })
+                extra_timestamps.push(ExtraAckTimestamp { delta_la, delta_timestamp })
             }
         }
 
         Ok(AckFrame {
-            largest_acknowledged: largest_acknowledged,
-            ack_delay: ack_delay,
+            largest_acknowledged,
+            ack_delay,
 
-            first_ack_block_length: first_ack_block_length,
-            extra_ack_blocks: extra_ack_blocks,
+            first_ack_block_length,
+            extra_ack_blocks,
 
-            first_timestamp: first_timestamp,
-            extra_timestamps: extra_timestamps,
+            first_timestamp,
+            extra_timestamps,
         })
     }
 }
@@ -4,6 +4,7 @@ use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 use quic::errors::Result;
 use quic::packets::utils::map_unexpected_eof;
+use super::utils::check_frame_type;
 
 
 pub const FRAME_BLOCKED: u8 = 0x05;
@@ -24,12 +25,12 @@ impl BlockedFrame {
 
     pub fn decode<R: io::Read>(read: &mut R) -> Result<BlockedFrame> {
         let frame_type = read.read_u8()?;
-        assert!(frame_type == FRAME_BLOCKED);
+        check_frame_type(frame_type, FRAME_BLOCKED)?;
 
         let stream_id = 
             read.read_u32::<BigEndian>()
             .map_err(map_unexpected_eof)?;
 
-        Ok(BlockedFrame { stream_id: stream_id })
+        Ok(BlockedFrame { stream_id })
     }
 }
@@ -4,7 +4,7 @@ use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 use quic::errors::Result;
 use quic::packets::utils::map_unexpected_eof;
-use super::utils::{encode_reason_phrase, decode_reason_phrase};
+use super::utils::{encode_reason_phrase, decode_reason_phrase, check_frame_type};
 
 
 pub const FRAME_CONNECTION_CLOSE: u8 = 0x02;
@@ -27,7 +27,7 @@ impl ConnectionCloseFrame {
 
     pub fn decode<R: io::Read>(read: &mut R) -> Result<ConnectionCloseFrame> {
         let frame_type = read.read_u8()?;
-        assert!(frame_type == FRAME_CONNECTION_CLOSE);
+        check_frame_type(frame_type, FRAME_CONNECTION_CLOSE)?;
 
         let error_code = 
             read.read_u32::<BigEndian>()
@@ -35,8 +35,8 @@ impl ConnectionCloseFrame {
         let reason_phrase = decode_reason_phrase(read)?;
 
         Ok(ConnectionCloseFrame {
-            error_code: error_code,
-            reason_phrase: reason_phrase,
+            error_code,
+            reason_phrase,
         })
     }
 }
@@ -4,7 +4,7 @@ use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 use quic::errors::Result;
 use quic::packets::utils::map_unexpected_eof;
-use super::utils::{encode_reason_phrase, decode_reason_phrase};
+use super::utils::{encode_reason_phrase, decode_reason_phrase, check_frame_type};
 
 
 pub const FRAME_GOAWAY: u8 = 0x03;
@@ -29,7 +29,7 @@ impl GoAwayFrame {
 
     pub fn decode<R: io::Read>(read: &mut R) -> Result<GoAwayFrame> {
         let frame_type = read.read_u8()?;
-        assert!(frame_type == FRAME_GOAWAY);
+        check_frame_type(frame_type, FRAME_GOAWAY)?;
 
         let error_code = 
             read.read_u32::<BigEndian>()
@@ -40,9 +40,9 @@ impl GoAwayFrame {
         let reason_phrase = decode_reason_phrase(read)?;
 
         Ok(GoAwayFrame {
-            error_code: error_code,
-            last_good_stream_id: last_good_stream_id,
-            reason_phrase: reason_phrase,
+            error_code,
+            last_good_stream_id,
+            reason_phrase,
         })
     }
 }
@@ -3,6 +3,9 @@ pub mod blocked;
 pub mod connection_close;
 pub mod goaway;
 pub mod padding;
+pub mod parameters;
+pub mod path_challenge;
+pub mod path_response;
 pub mod ping;
 pub mod rst_stream;
 pub mod stop_waiting;
@@ -28,6 +31,9 @@ pub enum Frame {
     ConnectionClose(connection_close::ConnectionCloseFrame),
     GoAway(goaway::GoAwayFrame),
     Padding(padding::PaddingFrame),
+    Parameters(parameters::ParametersFrame),
+    PathChallenge(path_challenge::PathChallengeFrame),
+    PathResponse(path_response::PathResponseFrame),
     Ping(ping::PingFrame),
     RstStream(rst_stream::RstStreamFrame),
     StopWaiting(stop_waiting::StopWaitingFrame),
@@ -37,7 +43,7 @@ pub enum Frame {
 
 impl Frame {
     pub fn encode<W: io::Write>(&self, write: &mut W, packet_number_size: usize, last_frame: bool) -> Result<()> {
-        check_packet_number_size(packet_number_size);
+        check_packet_number_size(packet_number_size)?;
 
         match *self {
             Frame::Ack(ref ack_frame) => ack_frame.encode(write),
@@ -45,6 +51,9 @@ impl Frame {
             Frame::ConnectionClose(ref connection_close_frame) => connection_close_frame.encode(write),
             Frame::GoAway(ref goaway_frame) => goaway_frame.encode(write),
             Frame::Padding(ref padding_frame) => padding_frame.encode(write),
+            Frame::Parameters(ref parameters_frame) => parameters_frame.encode(write),
+            Frame::PathChallenge(ref path_challenge_frame) => path_challenge_frame.encode(write),
+            Frame::PathResponse(ref path_response_frame) => path_response_frame.encode(write),
             Frame::Ping(ref ping_frame) => ping_frame.encode(write),
             Frame::RstStream(ref rst_stream_frame) => rst_stream_frame.encode(write),
             Frame::StopWaiting(ref stop_waiting_frame) => stop_waiting_frame.encode(write, packet_number_size),
@@ -55,10 +64,11 @@ impl Frame {
 
     pub fn decode<R>(read: &mut R, packet_number_size: usize) -> Result<Frame>
             where R: io::Read + io::Seek {
-        check_packet_number_size(packet_number_size);
+        check_packet_number_size(packet_number_size)?;
 
+        // running out of input here means there are no more frames, see `PacketPayload::decode`
         let frame_type = read.read_u8()?;
-        read.seek(io::SeekFrom::Current(-1)).unwrap();
+        read.seek(io::SeekFrom::Current(-1))?;
 
         match frame_type {
             blocked::FRAME_BLOCKED =>
@@ -69,6 +79,12 @@ impl Frame {
                 Ok(Frame::GoAway(goaway::GoAwayFrame::decode(read)?)),
             padding::FRAME_PADDING =>
                 Ok(Frame::Padding(padding::PaddingFrame::decode(read)?)),
+            parameters::FRAME_PARAMETERS =>
+                Ok(Frame::Parameters(parameters::ParametersFrame::decode(read)?)),
+            path_challenge::FRAME_PATH_CHALLENGE =>
+                Ok(Frame::PathChallenge(path_challenge::PathChallengeFrame::decode(read)?)),
+            path_response::FRAME_PATH_RESPONSE =>
+                Ok(Frame::PathResponse(path_response::PathResponseFrame::decode(read)?)),
             ping::FRAME_PING =>
                 Ok(Frame::Ping(ping::PingFrame::decode(read)?)),
             rst_stream::FRAME_RST_STREAM =>
@@ -89,3 +105,90 @@ impl Frame {
         }
     }
 }
+
+
+/// A frame decoded from a slice, stream data borrows the slice instead of being copied
+///
+/// The other frames carry no bulk data and are decoded into their owned types.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FrameRef<'a> {
+    Ack(ack::AckFrame),
+    Blocked(blocked::BlockedFrame),
+    ConnectionClose(connection_close::ConnectionCloseFrame),
+    GoAway(goaway::GoAwayFrame),
+    Padding(padding::PaddingFrame),
+    Parameters(parameters::ParametersFrame),
+    PathChallenge(path_challenge::PathChallengeFrame),
+    PathResponse(path_response::PathResponseFrame),
+    Ping(ping::PingFrame),
+    RstStream(rst_stream::RstStreamFrame),
+    StopWaiting(stop_waiting::StopWaitingFrame),
+    Stream(stream::StreamFrameRef<'a>),
+    WindowUpdate(window_update::WindowUpdateFrame),
+}
+
+impl<'a> FrameRef<'a> {
+    /// Decode the frame at the cursor position, fails if there's none
+    pub fn decode(read: &mut io::Cursor<&'a [u8]>, packet_number_size: usize) -> Result<FrameRef<'a>> {
+        check_packet_number_size(packet_number_size)?;
+
+        // the type octet is peeked at, each frame decoder reads it again
+        let frame_type = match read.get_ref().get(read.position() as usize) {
+            Some(&frame_type) => frame_type,
+            None => return Err(Error::Decoding(String::from("Frame expected"))),
+        };
+
+        match frame_type {
+            blocked::FRAME_BLOCKED =>
+                Ok(FrameRef::Blocked(blocked::BlockedFrame::decode(read)?)),
+            connection_close::FRAME_CONNECTION_CLOSE =>
+                Ok(FrameRef::ConnectionClose(connection_close::ConnectionCloseFrame::decode(read)?)),
+            goaway::FRAME_GOAWAY =>
+                Ok(FrameRef::GoAway(goaway::GoAwayFrame::decode(read)?)),
+            padding::FRAME_PADDING =>
+                Ok(FrameRef::Padding(padding::PaddingFrame::decode(read)?)),
+            parameters::FRAME_PARAMETERS =>
+                Ok(FrameRef::Parameters(parameters::ParametersFrame::decode(read)?)),
+            path_challenge::FRAME_PATH_CHALLENGE =>
+                Ok(FrameRef::PathChallenge(path_challenge::PathChallengeFrame::decode(read)?)),
+            path_response::FRAME_PATH_RESPONSE =>
+                Ok(FrameRef::PathResponse(path_response::PathResponseFrame::decode(read)?)),
+            ping::FRAME_PING =>
+                Ok(FrameRef::Ping(ping::PingFrame::decode(read)?)),
+            rst_stream::FRAME_RST_STREAM =>
+                Ok(FrameRef::RstStream(rst_stream::RstStreamFrame::decode(read)?)),
+            stop_waiting::FRAME_STOP_WAITING =>
+                Ok(FrameRef::StopWaiting(stop_waiting::StopWaitingFrame::decode(read, packet_number_size)?)),
+            window_update::FRAME_WINDOW_UPDATE =>
+                Ok(FrameRef::WindowUpdate(window_update::WindowUpdateFrame::decode(read)?)),
+            other_type => {
+                if (other_type & ack::MASK_ACK) == ack::FLAG_ACK {
+                    Ok(FrameRef::Ack(ack::AckFrame::decode(read)?))
+                } else if (other_type & stream::FLAG_STREAM) != 0 {
+                    Ok(FrameRef::Stream(stream::StreamFrameRef::decode(read)?))
+                } else {
+                    Err(Error::Decoding(String::from("Invalid frame type")))
+                }
+            },
+        }
+    }
+
+    /// Copy borrowed data out of the packet buffer, to keep the frame around
+    pub fn into_owned(self) -> Frame {
+        match self {
+            FrameRef::Ack(ack_frame) => Frame::Ack(ack_frame),
+            FrameRef::Blocked(blocked_frame) => Frame::Blocked(blocked_frame),
+            FrameRef::ConnectionClose(connection_close_frame) => Frame::ConnectionClose(connection_close_frame),
+            FrameRef::GoAway(goaway_frame) => Frame::GoAway(goaway_frame),
+            FrameRef::Padding(padding_frame) => Frame::Padding(padding_frame),
+            FrameRef::Parameters(parameters_frame) => Frame::Parameters(parameters_frame),
+            FrameRef::PathChallenge(path_challenge_frame) => Frame::PathChallenge(path_challenge_frame),
+            FrameRef::PathResponse(path_response_frame) => Frame::PathResponse(path_response_frame),
+            FrameRef::Ping(ping_frame) => Frame::Ping(ping_frame),
+            FrameRef::RstStream(rst_stream_frame) => Frame::RstStream(rst_stream_frame),
+            FrameRef::StopWaiting(stop_waiting_frame) => Frame::StopWaiting(stop_waiting_frame),
+            FrameRef::Stream(stream_frame) => Frame::Stream(stream_frame.into_owned()),
+            FrameRef::WindowUpdate(window_update_frame) => Frame::WindowUpdate(window_update_frame),
+        }
+    }
+}
@@ -3,6 +3,7 @@ use std::io;
 use byteorder::{ReadBytesExt, WriteBytesExt};
 
 use quic::errors::Result;
+use super::utils::check_frame_type;
 
 
 pub const FRAME_PADDING: u8 = 0x00;
@@ -19,7 +20,7 @@ impl PaddingFrame {
 
     pub fn decode<R: io::Read>(read: &mut R) -> Result<PaddingFrame> {
         let frame_type = read.read_u8()?;
-        assert!(frame_type == FRAME_PADDING);
+        check_frame_type(frame_type, FRAME_PADDING)?;
 
         Ok(PaddingFrame {})
     }
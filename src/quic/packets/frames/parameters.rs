@@ -0,0 +1,49 @@
+use std::io;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use quic::errors::Result;
+use quic::packets::utils::map_unexpected_eof;
+use super::utils::check_frame_type;
+
+
+pub const FRAME_PARAMETERS: u8 = 0x09;
+
+pub const FLAG_OMIT_CONNECTION_ID: u8 = 0b00000001;
+
+/// Connection parameters announced by an endpoint when the connection starts
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ParametersFrame {
+    /// The sender routes by address and wants packets without a connection ID
+    pub omit_connection_id: bool,
+    /// How many streams the sender lets the receiver open at once
+    pub max_open_streams: u32,
+}
+
+impl ParametersFrame {
+    pub fn encode<W: io::Write>(&self, write: &mut W) -> Result<()> {
+        write.write_u8(FRAME_PARAMETERS)?;
+
+        let mut flags = 0b00000000;
+        if self.omit_connection_id {
+            flags |= FLAG_OMIT_CONNECTION_ID;
+        }
+        write.write_u8(flags)?;
+        write.write_u32::<BigEndian>(self.max_open_streams)?;
+
+        Ok(())
+    }
+
+    pub fn decode<R: io::Read>(read: &mut R) -> Result<ParametersFrame> {
+        let frame_type = read.read_u8()?;
+        check_frame_type(frame_type, FRAME_PARAMETERS)?;
+
+        let flags = read.read_u8().map_err(map_unexpected_eof)?;
+        let max_open_streams = read.read_u32::<BigEndian>().map_err(map_unexpected_eof)?;
+
+        Ok(ParametersFrame {
+            omit_connection_id: (flags & FLAG_OMIT_CONNECTION_ID) != 0,
+            max_open_streams,
+        })
+    }
+}
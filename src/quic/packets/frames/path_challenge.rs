@@ -0,0 +1,36 @@
+use std::io;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use quic::errors::Result;
+use quic::packets::utils::map_unexpected_eof;
+use super::utils::check_frame_type;
+
+
+pub const FRAME_PATH_CHALLENGE: u8 = 0x0A;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PathChallengeFrame {
+    pub data: u64,
+}
+
+impl PathChallengeFrame {
+    pub fn encode<W: io::Write>(&self, write: &mut W) -> Result<()> {
+        write.write_u8(FRAME_PATH_CHALLENGE)?;
+
+        write.write_u64::<BigEndian>(self.data)?;
+
+        Ok(())
+    }
+
+    pub fn decode<R: io::Read>(read: &mut R) -> Result<PathChallengeFrame> {
+        let frame_type = read.read_u8()?;
+        check_frame_type(frame_type, FRAME_PATH_CHALLENGE)?;
+
+        let data =
+            read.read_u64::<BigEndian>()
+            .map_err(map_unexpected_eof)?;
+
+        Ok(PathChallengeFrame { data })
+    }
+}
@@ -3,6 +3,7 @@ use std::io;
 use byteorder::{ReadBytesExt, WriteBytesExt};
 
 use quic::errors::Result;
+use super::utils::check_frame_type;
 
 
 pub const FRAME_PING: u8 = 0x07;
@@ -19,7 +20,7 @@ impl PingFrame {
 
     pub fn decode<R: io::Read>(read: &mut R) -> Result<PingFrame> {
         let frame_type = read.read_u8()?;
-        assert!(frame_type == FRAME_PING);
+        check_frame_type(frame_type, FRAME_PING)?;
 
         Ok(PingFrame {})
     }
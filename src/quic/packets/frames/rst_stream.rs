@@ -4,6 +4,7 @@ use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 use quic::errors::Result;
 use quic::packets::utils::map_unexpected_eof;
+use super::utils::check_frame_type;
 
 
 pub const FRAME_RST_STREAM: u8 = 0x01;
@@ -28,7 +29,7 @@ impl RstStreamFrame {
 
     pub fn decode<R: io::Read>(read: &mut R) -> Result<RstStreamFrame> {
         let frame_type = read.read_u8()?;
-        assert!(frame_type == FRAME_RST_STREAM);
+        check_frame_type(frame_type, FRAME_RST_STREAM)?;
 
         let error_code = 
             read.read_u32::<BigEndian>()
@@ -41,9 +42,9 @@ impl RstStreamFrame {
             .map_err(map_unexpected_eof)?;
 
         Ok(RstStreamFrame {
-            error_code: error_code,
-            stream_id: stream_id,
-            final_offset: final_offset,
+            error_code,
+            stream_id,
+            final_offset,
         })
     }
 }
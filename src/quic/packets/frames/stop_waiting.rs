@@ -3,8 +3,8 @@ use std::io;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 use quic::errors::Result;
-use super::utils::check_packet_number_size;
 use quic::packets::utils::map_unexpected_eof;
+use super::utils::{check_frame_type, check_packet_number_size};
 
 
 pub const FRAME_STOP_WAITING: u8 = 0x06;
@@ -18,7 +18,7 @@ impl StopWaitingFrame {
     pub fn encode<W: io::Write>(&self, write: &mut W, packet_number_size: usize) -> Result<()> {
         write.write_u8(FRAME_STOP_WAITING)?;
 
-        check_packet_number_size(packet_number_size);
+        check_packet_number_size(packet_number_size)?;
         write.write_uint::<BigEndian>(self.least_acked_delta, packet_number_size)?;
 
         Ok(())
@@ -26,12 +26,13 @@ impl StopWaitingFrame {
 
     pub fn decode<R: io::Read>(read: &mut R, packet_number_size: usize) -> Result<StopWaitingFrame> {
         let frame_type = read.read_u8()?;
-        assert!(frame_type == FRAME_STOP_WAITING);
+        check_frame_type(frame_type, FRAME_STOP_WAITING)?;
+        check_packet_number_size(packet_number_size)?;
 
         let least_acked_delta = 
             read.read_uint::<BigEndian>(packet_number_size)
             .map_err(map_unexpected_eof)?;
 
-        Ok(StopWaitingFrame { least_acked_delta: least_acked_delta })
+        Ok(StopWaitingFrame { least_acked_delta })
     }
 }
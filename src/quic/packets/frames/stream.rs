@@ -4,7 +4,7 @@ use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use cast;
 
 use quic::errors::{Error, Result};
-use quic::packets::utils::map_unexpected_eof;
+use quic::packets::utils::{map_unexpected_eof, read_bytes};
 
 
 pub const FLAG_STREAM: u8 = 0b10000000;
@@ -14,6 +14,10 @@ pub const FLAG_DATA_LENGTH_PRESENT: u8 = 0b00100000;
 pub const MASK_OFFSET_SIZE: u8 = 0b00011100;
 pub const MASK_STREAM_ID_SIZE: u8 = 0b00000011;
 
+// the sizes every frame is encoded with
+const OFFSET_SIZE: usize = 8;
+const STREAM_ID_SIZE: usize = 4;
+
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct StreamFrame {
     pub stream_id: u32,
@@ -23,6 +27,12 @@ pub struct StreamFrame {
 }
 
 impl StreamFrame {
+    /// Bytes an encoded frame takes besides its data
+    pub fn header_len(last_frame: bool) -> usize {
+        let data_length_size = if last_frame { 0 } else { 2 };
+        1 + data_length_size + STREAM_ID_SIZE + OFFSET_SIZE
+    }
+
     pub fn encode<W: io::Write>(&self, write: &mut W, last_frame: bool) -> Result<()> {
         // construct the type octet
         let mut frame_type = FLAG_STREAM;
@@ -36,11 +46,11 @@ impl StreamFrame {
         }
 
         // TODO: calculate this more intelligently
-        let offset_size = 8;
+        let offset_size = OFFSET_SIZE;
         frame_type |= 0b00011100;
 
         // TODO: calculate this more intelligently
-        let stream_id_size = 4;
+        let stream_id_size = STREAM_ID_SIZE;
         frame_type |= 0b00000011;
 
         write.write_u8(frame_type)?;
@@ -62,9 +72,91 @@ impl StreamFrame {
     }
 
     pub fn decode<R: io::Read>(read: &mut R) -> Result<StreamFrame> {
+        let header = StreamFrameHeader::decode(read)?;
+
+        let stream_data = match header.data_length {
+            Some(data_length) => read_bytes(read, data_length)?,
+            None => {
+                let mut buffer = Vec::new();
+                read.read_to_end(&mut buffer).map_err(map_unexpected_eof)?;
+                buffer
+            },
+        };
+        header.check_data(&stream_data)?;
+
+        Ok(StreamFrame {
+            stream_id: header.stream_id,
+            offset: header.offset,
+            stream_data,
+            fin: header.fin,
+        })
+    }
+}
+
+
+/// A stream frame with its data still in the buffer it was decoded from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StreamFrameRef<'a> {
+    pub stream_id: u32,
+    pub offset: u64,
+    pub stream_data: &'a [u8],
+    pub fin: bool,
+}
+
+impl<'a> StreamFrameRef<'a> {
+    pub fn decode(read: &mut io::Cursor<&'a [u8]>) -> Result<StreamFrameRef<'a>> {
+        let header = StreamFrameHeader::decode(read)?;
+
+        let buf: &'a [u8] = read.get_ref();
+        let start = read.position() as usize;
+        let end = match header.data_length {
+            Some(data_length) => start + data_length,
+            None => buf.len(),
+        };
+        if end > buf.len() {
+            return Err(Error::Decoding("Unexpected EOF when decoding a packet".to_string()));
+        }
+        read.set_position(end as u64);
+
+        let stream_data = &buf[start..end];
+        header.check_data(stream_data)?;
+
+        Ok(StreamFrameRef {
+            stream_id: header.stream_id,
+            offset: header.offset,
+            stream_data,
+            fin: header.fin,
+        })
+    }
+
+    /// Copy the data out of the packet buffer, to keep the frame around
+    pub fn into_owned(self) -> StreamFrame {
+        StreamFrame {
+            stream_id: self.stream_id,
+            offset: self.offset,
+            stream_data: self.stream_data.to_vec(),
+            fin: self.fin,
+        }
+    }
+}
+
+
+/// Everything in a stream frame that comes before the data
+struct StreamFrameHeader {
+    stream_id: u32,
+    offset: u64,
+    fin: bool,
+    // the data of the last frame in a packet runs to the end of it
+    data_length: Option<usize>,
+}
+
+impl StreamFrameHeader {
+    fn decode<R: io::Read>(read: &mut R) -> Result<StreamFrameHeader> {
         // extract type octet data
         let frame_type = read.read_u8()?;
-        assert!((frame_type & FLAG_STREAM) != 0);
+        if (frame_type & FLAG_STREAM) == 0 {
+            return Err(Error::Decoding(format!("Invalid stream frame type {:#x}", frame_type)));
+        }
 
         let fin = (frame_type & FLAG_FIN) != 0;
 
@@ -96,20 +188,16 @@ impl StreamFrame {
             0
         };
 
-        let stream_data = match data_length {
-            Some(data_length) => {
-                let mut buffer = vec![0; data_length];
-                read.read_exact(&mut buffer).map_err(map_unexpected_eof)?;
-                buffer
-            },
-            None => {
-                let mut buffer = Vec::new();
-                read.read_to_end(&mut buffer).map_err(map_unexpected_eof)?;
-                buffer
-            },
-        };
+        Ok(StreamFrameHeader {
+            stream_id,
+            offset,
+            fin,
+            data_length,
+        })
+    }
 
-        if stream_data.len() == 0 && !fin {
+    fn check_data(&self, stream_data: &[u8]) -> Result<()> {
+        if stream_data.is_empty() && !self.fin {
             return Err(
                 Error::Decoding(
                     String::from("Must have either non-zero data length or the FIN bit set")
@@ -117,11 +205,6 @@ impl StreamFrame {
             );
         }
 
-        Ok(StreamFrame {
-            stream_id: stream_id,
-            offset: offset,
-            stream_data: stream_data,
-            fin: fin,
-        })
+        Ok(())
     }
 }
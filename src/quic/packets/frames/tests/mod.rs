@@ -3,6 +3,9 @@ mod blocked;
 mod connection_close;
 mod goaway;
 mod padding;
+mod parameters;
+mod path_challenge;
+mod path_response;
 mod ping;
 mod rst_stream;
 mod stop_waiting;
@@ -60,6 +63,11 @@ fn test_encoding() {
     );
     frame.encode(&mut write, 6, false).unwrap();
 
+    let frame = frames::Frame::Parameters(
+        frames::parameters::ParametersFrame { omit_connection_id: true, max_open_streams: 100 }
+    );
+    frame.encode(&mut write, 6, false).unwrap();
+
     let frame = frames::Frame::Ping(
         frames::ping::PingFrame {}
     );
@@ -128,6 +136,11 @@ fn test_encoding() {
             // padding frame
             0x00,
 
+            // parameters frame
+            0x09,
+            0x01,
+            0x00, 0x00, 0x00, 0x64,
+
             // ping frame
             0x07,
 
@@ -186,6 +199,11 @@ fn test_decoding() {
             // padding frame
             0x00,
 
+            // parameters frame
+            0x09,
+            0x01,
+            0x00, 0x00, 0x00, 0x64,
+
             // ping frame
             0x07,
 
@@ -269,6 +287,14 @@ fn test_decoding() {
         )
     );
 
+    let frame = frames::Frame::decode(&mut read, 6).unwrap();
+    assert_eq!(
+        frame,
+        frames::Frame::Parameters(
+            frames::parameters::ParametersFrame { omit_connection_id: true, max_open_streams: 100 }
+        )
+    );
+
     let frame = frames::Frame::decode(&mut read, 6).unwrap();
     assert_eq!(
         frame,
@@ -0,0 +1,70 @@
+use std::io;
+
+use quic::errors::Error;
+use quic::packets::frames::parameters;
+
+
+#[test]
+fn test_encoding() {
+    let frame = parameters::ParametersFrame { omit_connection_id: true, max_open_streams: 100 };
+    let mut write = io::Cursor::new(Vec::new());
+    frame.encode(&mut write).unwrap();
+    assert_eq!(
+        write.get_ref(),
+        &[
+            0x09,
+            0x01,
+            0x00, 0x00, 0x00, 0x64,
+        ]
+    );
+
+    let frame = parameters::ParametersFrame { omit_connection_id: false, max_open_streams: 0x01020304 };
+    let mut write = io::Cursor::new(Vec::new());
+    frame.encode(&mut write).unwrap();
+    assert_eq!(
+        write.get_ref(),
+        &[
+            0x09,
+            0x00,
+            0x01, 0x02, 0x03, 0x04,
+        ]
+    );
+}
+
+#[test]
+fn test_decoding() {
+    let mut read = io::Cursor::new(
+        vec![
+            0x09,
+            0x01,
+            0x00, 0x00, 0x00, 0x64,
+        ]
+    );
+    let frame = parameters::ParametersFrame::decode(&mut read).unwrap();
+    assert_eq!(
+        frame,
+        parameters::ParametersFrame { omit_connection_id: true, max_open_streams: 100 }
+    );
+
+    let mut read = io::Cursor::new(
+        vec![
+            0x09,
+        ]
+    );
+    match parameters::ParametersFrame::decode(&mut read) {
+        Err(Error::Decoding(..)) => {},
+        _ => assert!(false, "Error expected"),
+    };
+
+    let mut read = io::Cursor::new(
+        vec![
+            0x09,
+            0x01,
+            0x00, 0x00,
+        ]
+    );
+    match parameters::ParametersFrame::decode(&mut read) {
+        Err(Error::Decoding(..)) => {},
+        _ => assert!(false, "Error expected"),
+    };
+}
@@ -0,0 +1,45 @@
+use std::io;
+
+use quic::errors::Error;
+use quic::packets::frames::path_challenge;
+
+
+#[test]
+fn test_encoding() {
+    let frame = path_challenge::PathChallengeFrame { data: 0xDEADBEEFCAFEBABE };
+    let mut write = io::Cursor::new(Vec::new());
+    frame.encode(&mut write).unwrap();
+    assert_eq!(
+        write.get_ref(),
+        &[
+            0x0A,
+            0xDE, 0xAD, 0xBE, 0xEF, 0xCA, 0xFE, 0xBA, 0xBE,
+        ]
+    );
+}
+
+#[test]
+fn test_decoding() {
+    let mut read = io::Cursor::new(
+        vec![
+            0x0A,
+            0xDE, 0xAD, 0xBE, 0xEF, 0xCA, 0xFE, 0xBA, 0xBE,
+        ]
+    );
+    let frame = path_challenge::PathChallengeFrame::decode(&mut read).unwrap();
+    assert_eq!(
+        frame,
+        path_challenge::PathChallengeFrame { data: 0xDEADBEEFCAFEBABE }
+    );
+
+    let mut read = io::Cursor::new(
+        vec![
+            0x0A,
+            0xDE, 0xAD, 0xBE, 0xEF, 0xCA, 0xFE, 0xBA,
+        ]
+    );
+    match path_challenge::PathChallengeFrame::decode(&mut read) {
+        Err(Error::Decoding(..)) => {},
+        _ => assert!(false, "Error expected"),
+    };
+}
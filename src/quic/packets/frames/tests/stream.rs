@@ -149,3 +149,19 @@ fn test_decoding() {
         _ => assert!(false, "Error expected"),
     };
 }
+
+#[test]
+fn test_header_len() {
+    let frame = stream::StreamFrame {
+        stream_id: 42,
+        offset: 32,
+        stream_data: vec![0x68, 0x65, 0x6C, 0x6C, 0x6F],
+        fin: false,
+    };
+
+    for &last_frame in &[false, true] {
+        let mut write = io::Cursor::new(Vec::new());
+        frame.encode(&mut write, last_frame).unwrap();
+        assert_eq!(write.get_ref().len(), stream::StreamFrame::header_len(last_frame) + frame.stream_data.len());
+    }
+}
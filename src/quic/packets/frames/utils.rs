@@ -3,8 +3,8 @@ use std::io;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use cast;
 
-use quic::errors::Result;
-use quic::packets::utils::map_unexpected_eof;
+use quic::errors::{Error, Result};
+use quic::packets::utils::{map_unexpected_eof, read_bytes};
 
 
 pub fn encode_reason_phrase(write: &mut io::Write, reason_phrase: &Option<String>) -> Result<()> {
@@ -31,8 +31,7 @@ pub fn decode_reason_phrase(read: &mut io::Read) -> Result<Option<String>> {
         as usize;
     
     if reason_phrase_length != 0 {
-        let mut buf = vec![0; reason_phrase_length];
-        read.read_exact(&mut buf).map_err(map_unexpected_eof)?;
+        let buf = read_bytes(read, reason_phrase_length)?;
         let reason_phrase = String::from_utf8_lossy(&buf).to_string();
         Ok(Some(reason_phrase))
     } else {
@@ -41,9 +40,18 @@ pub fn decode_reason_phrase(read: &mut io::Read) -> Result<Option<String>> {
 }
 
 
-pub fn check_packet_number_size(packet_number_size: usize) {
+pub fn check_packet_number_size(packet_number_size: usize) -> Result<()> {
     match packet_number_size {
-        1 | 2 | 4 | 6 => {},
-        _ => panic!("Invalid packet number size: {}", packet_number_size),
+        1 | 2 | 4 | 6 => Ok(()),
+        _ => Err(Error::Decoding(format!("Invalid packet number size: {}", packet_number_size))),
     }
 }
+
+/// Fail unless the type octet is the one the frame decoder expects
+pub fn check_frame_type(frame_type: u8, expected_type: u8) -> Result<()> {
+    if frame_type != expected_type {
+        return Err(Error::Decoding(format!("Invalid frame type {:#x}, expected {:#x}", frame_type, expected_type)));
+    }
+
+    Ok(())
+}
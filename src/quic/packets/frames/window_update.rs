@@ -4,6 +4,7 @@ use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 use quic::errors::Result;
 use quic::packets::utils::map_unexpected_eof;
+use super::utils::check_frame_type;
 
 
 pub const FRAME_WINDOW_UPDATE: u8 = 0x04;
@@ -26,7 +27,7 @@ impl WindowUpdateFrame {
 
     pub fn decode<R: io::Read>(read: &mut R) -> Result<WindowUpdateFrame> {
         let frame_type = read.read_u8()?;
-        assert!(frame_type == FRAME_WINDOW_UPDATE);
+        check_frame_type(frame_type, FRAME_WINDOW_UPDATE)?;
 
         let stream_id = 
             read.read_u32::<BigEndian>()
@@ -36,8 +37,8 @@ impl WindowUpdateFrame {
             .map_err(map_unexpected_eof)?;
 
         Ok(WindowUpdateFrame {
-            stream_id: stream_id,
-            byte_offset: byte_offset,
+            stream_id,
+            byte_offset,
         })
     }
 }
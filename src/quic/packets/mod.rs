@@ -11,7 +11,7 @@ use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use quic::endpoint_role::EndpointRole;
 use quic::errors::{Error, Result};
 use quic::QUIC_VERSION;
-use self::frames::Frame;
+use self::frames::{Frame, FrameRef};
 use self::utils::{map_unexpected_eof, truncate_u64};
 
 
@@ -62,7 +62,7 @@ impl PacketPayload {
             return Err(Error::Decoding(String::from("At least one frame expected")));
         }
 
-        Ok(PacketPayload { frames: frames })
+        Ok(PacketPayload { frames })
     }
 }
 
@@ -75,6 +75,26 @@ pub struct PacketHeader {
     pub multipath: bool,
 
     pub connection_id: Option<u64>,
+    // only on the wire when the multipath flag is set
+    pub path_id: u8,
+}
+
+impl PacketHeader {
+    /// Encoded size of a regular packet's header, up to and including the packet number
+    pub fn encoded_len(&self, have_version: bool) -> usize {
+        let mut len = 1 + self.packet_number_size;
+        if self.connection_id.is_some() {
+            len += 8;
+        }
+        if self.multipath {
+            len += 1;
+        }
+        if have_version {
+            len += 4;
+        }
+
+        len
+    }
 }
 
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
@@ -115,17 +135,29 @@ impl Packet {
         }
     }
 
-    pub fn connection_id(&self) -> Option<u64> {
-        let header = match *self {
+    pub fn header(&self) -> PacketHeader {
+        match *self {
             Packet::PublicReset(ref public_reset_packet) =>
                 public_reset_packet.header,
             Packet::Regular(ref regular_packet) =>
                 regular_packet.header,
             Packet::VersionNegotiation(ref version_negotiation_packet) =>
                 version_negotiation_packet.header,
-        };
+        }
+    }
 
-        header.connection_id
+    pub fn connection_id(&self) -> Option<u64> {
+        self.header().connection_id
+    }
+
+    /// Path the packet belongs to, packets without the multipath flag use path 0
+    pub fn path_id(&self) -> u8 {
+        let header = self.header();
+        if header.multipath {
+            header.path_id
+        } else {
+            0
+        }
     }
 
     fn encode_header<W>(write: &mut W, header: &PacketHeader, have_version: bool, public_reset: bool) -> Result<()>
@@ -161,6 +193,11 @@ impl Packet {
             write.write_u64::<BigEndian>(connection_id)?;
         }
 
+        // path id
+        if header.multipath {
+            write.write_u8(header.path_id)?;
+        }
+
         Ok(())
     }
 
@@ -211,96 +248,242 @@ impl Packet {
         Ok(())
     }
 
-    pub fn decode<R: io::Read + io::Seek>(read: &mut R, endpoint_type: EndpointRole) -> Result<Packet> {
-        let flags = read.read_u8().map_err(map_unexpected_eof)?;
-        let has_version = (flags & FLAG_VERSION) != 0;
-        let public_reset = (flags & FLAG_PUBLIC_RESET) != 0;
-        let key_phase = (flags & FLAG_KEY_PHASE) != 0;
-        let has_connection_id = (flags & FLAG_CONNECTION_ID) != 0;
-        let packet_number_size = match (flags & MASK_PACKET_NUMBER_SIZE) >> 4 {
-            0b00 => 1,
-            0b01 => 2,
-            0b10 => 4,
-            0b11 => 6,
-            _ => unreachable!(),
-        };
-        let multipath = (flags & FLAG_MULTIPATH) != 0;
-
-        let connection_id = if has_connection_id {
-            Some(read.read_u64::<BigEndian>().map_err(map_unexpected_eof)?)
-        } else {
-            None
-        };
-
-        let header = PacketHeader {
-            key_phase: key_phase,
-            packet_number_size: packet_number_size,
-            multipath: multipath,
+    /// Encode into the start of `buf`, returns the encoded size
+    ///
+    /// Fails with `Error::BufferOverflow` if the packet doesn't fit, leaving `buf` partly written.
+    pub fn encode_to_slice(&self, buf: &mut [u8]) -> Result<usize> {
+        let mut write = io::Cursor::new(buf);
+        match self.encode(&mut write) {
+            Ok(()) => Ok(write.position() as usize),
+            Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::WriteZero => Err(Error::BufferOverflow),
+            Err(e) => Err(e),
+        }
+    }
 
-            connection_id: connection_id,
-        };
+    pub fn decode<R: io::Read + io::Seek>(read: &mut R, endpoint_type: EndpointRole) -> Result<Packet> {
+        let (header, kind) = decode_prelude(read, endpoint_type)?;
 
-        match (public_reset, has_version, endpoint_type) {
-            (true, _, _) => {
-                // public reset packet
-                Ok(Packet::PublicReset(PublicResetPacket { header: header }))
+        match kind {
+            PacketKind::PublicReset => {
+                Ok(Packet::PublicReset(PublicResetPacket { header }))
             },
-            (false, false, _) | (false, true, EndpointRole::Server) => {
-                // regular packet
-                let version = if has_version {
-                    let version = read.read_u32::<BigEndian>().map_err(map_unexpected_eof)?;
-                    if version != QUIC_VERSION {
-                        return Err(Error::UnsupportedVersion(version));
-                    }
-
-                    Some(version)
-                } else {
-                    None
-                };
-
-                let packet_number =
-                    read.read_uint::<BigEndian>(packet_number_size)
-                    .map_err(map_unexpected_eof)?
-                    as u64;
-
-                let payload = PacketPayload::decode(read, packet_number_size)?;
+            PacketKind::Regular(version, packet_number) => {
+                let payload = PacketPayload::decode(read, header.packet_number_size)?;
 
                 Ok(
                     Packet::Regular(
                         RegularPacket {
-                            header: header,
+                            header,
 
-                            version: version,
-                            packet_number: packet_number,
-                            payload: payload,
+                            version,
+                            packet_number,
+                            payload,
                         }
                     )
                 )
             },
-            (false, true, EndpointRole::Client) => {
-                // version negotiation packet
-                let mut versions = Vec::new();
-
-                loop {
-                    match read.read_u32::<BigEndian>() {
-                        Ok(version) => {
-                            versions.push(version);
-                        },
-                        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
-                            break;
-                        },
-                        Err(e) => {
-                            return Err(Error::from(e));
-                        }
-                    };
-                }
-
+            PacketKind::VersionNegotiation => {
                 Ok(
                     Packet::VersionNegotiation(
-                        VersionNegotiationPacket { header: header, versions: versions }
+                        VersionNegotiationPacket { header, versions: decode_versions(read)? }
                     )
                 )
             },
         }
     }
 }
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PacketPayloadRef<'a> {
+    pub frames: Vec<FrameRef<'a>>,
+}
+
+impl<'a> PacketPayloadRef<'a> {
+    /// Decode the frames from the cursor position to the end of the slice
+    pub fn decode(read: &mut io::Cursor<&'a [u8]>, packet_number_size: usize) -> Result<PacketPayloadRef<'a>> {
+        let mut frames = Vec::new();
+        while (read.position() as usize) < read.get_ref().len() {
+            frames.push(FrameRef::decode(read, packet_number_size)?);
+        }
+
+        if frames.is_empty() {
+            return Err(Error::Decoding(String::from("At least one frame expected")));
+        }
+
+        Ok(PacketPayloadRef { frames })
+    }
+
+    pub fn into_owned(self) -> PacketPayload {
+        PacketPayload {
+            frames: self.frames.into_iter().map(FrameRef::into_owned).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegularPacketRef<'a> {
+    pub header: PacketHeader,
+
+    pub version: Option<u32>,
+    pub packet_number: u64,
+    pub payload: PacketPayloadRef<'a>,
+}
+
+/// A packet decoded from a datagram without copying its stream data, see `FrameRef`
+#[derive(Clone, Debug, PartialEq)]
+pub enum PacketRef<'a> {
+    Regular(RegularPacketRef<'a>),
+    VersionNegotiation(VersionNegotiationPacket),
+    PublicReset(PublicResetPacket),
+}
+
+impl<'a> PacketRef<'a> {
+    pub fn decode(buf: &'a [u8], endpoint_type: EndpointRole) -> Result<PacketRef<'a>> {
+        let mut read = io::Cursor::new(buf);
+        let (header, kind) = decode_prelude(&mut read, endpoint_type)?;
+
+        match kind {
+            PacketKind::PublicReset => {
+                Ok(PacketRef::PublicReset(PublicResetPacket { header }))
+            },
+            PacketKind::Regular(version, packet_number) => {
+                let payload = PacketPayloadRef::decode(&mut read, header.packet_number_size)?;
+
+                Ok(PacketRef::Regular(RegularPacketRef {
+                    header,
+
+                    version,
+                    packet_number,
+                    payload,
+                }))
+            },
+            PacketKind::VersionNegotiation => {
+                Ok(PacketRef::VersionNegotiation(VersionNegotiationPacket {
+                    header,
+                    versions: decode_versions(&mut read)?,
+                }))
+            },
+        }
+    }
+
+    /// Copy borrowed data out of the datagram, to keep the packet around
+    pub fn into_owned(self) -> Packet {
+        match self {
+            PacketRef::Regular(regular_packet) => Packet::Regular(RegularPacket {
+                header: regular_packet.header,
+
+                version: regular_packet.version,
+                packet_number: regular_packet.packet_number,
+                payload: regular_packet.payload.into_owned(),
+            }),
+            PacketRef::VersionNegotiation(version_negotiation_packet) =>
+                Packet::VersionNegotiation(version_negotiation_packet),
+            PacketRef::PublicReset(public_reset_packet) =>
+                Packet::PublicReset(public_reset_packet),
+        }
+    }
+}
+
+
+/// What follows the common header
+enum PacketKind {
+    PublicReset,
+    /// With the version, if present, and the packet number
+    Regular(Option<u32>, u64),
+    VersionNegotiation,
+}
+
+/// The connection id in a packet's header, without decoding the rest
+pub fn peek_connection_id(buf: &[u8]) -> Option<u64> {
+    match buf.first() {
+        Some(&flags) if (flags & FLAG_CONNECTION_ID) != 0 && buf.len() >= 9 => {
+            Some((&buf[1..9]).read_u64::<BigEndian>().unwrap())
+        },
+        _ => None,
+    }
+}
+
+/// Decode everything up to the payload or version list
+fn decode_prelude<R: io::Read>(read: &mut R, endpoint_type: EndpointRole) -> Result<(PacketHeader, PacketKind)> {
+    let flags = read.read_u8().map_err(map_unexpected_eof)?;
+    let has_version = (flags & FLAG_VERSION) != 0;
+    let public_reset = (flags & FLAG_PUBLIC_RESET) != 0;
+    let key_phase = (flags & FLAG_KEY_PHASE) != 0;
+    let has_connection_id = (flags & FLAG_CONNECTION_ID) != 0;
+    let packet_number_size = match (flags & MASK_PACKET_NUMBER_SIZE) >> 4 {
+        0b00 => 1,
+        0b01 => 2,
+        0b10 => 4,
+        0b11 => 6,
+        _ => unreachable!(),
+    };
+    let multipath = (flags & FLAG_MULTIPATH) != 0;
+
+    let connection_id = if has_connection_id {
+        Some(read.read_u64::<BigEndian>().map_err(map_unexpected_eof)?)
+    } else {
+        None
+    };
+
+    let path_id = if multipath {
+        read.read_u8().map_err(map_unexpected_eof)?
+    } else {
+        0
+    };
+
+    let header = PacketHeader {
+        key_phase,
+        packet_number_size,
+        multipath,
+
+        connection_id,
+        path_id,
+    };
+
+    let kind = match (public_reset, has_version, endpoint_type) {
+        (true, _, _) => PacketKind::PublicReset,
+        (false, false, _) | (false, true, EndpointRole::Server) => {
+            let version = if has_version {
+                let version = read.read_u32::<BigEndian>().map_err(map_unexpected_eof)?;
+                if version != QUIC_VERSION {
+                    return Err(Error::UnsupportedVersion(version));
+                }
+
+                Some(version)
+            } else {
+                None
+            };
+
+            let packet_number =
+                read.read_uint::<BigEndian>(packet_number_size)
+                .map_err(map_unexpected_eof)?
+                as u64;
+
+            PacketKind::Regular(version, packet_number)
+        },
+        (false, true, EndpointRole::Client) => PacketKind::VersionNegotiation,
+    };
+
+    Ok((header, kind))
+}
+
+fn decode_versions<R: io::Read>(read: &mut R) -> Result<Vec<u32>> {
+    let mut versions = Vec::new();
+
+    loop {
+        match read.read_u32::<BigEndian>() {
+            Ok(version) => {
+                versions.push(version);
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                break;
+            },
+            Err(e) => {
+                return Err(Error::from(e));
+            }
+        };
+    }
+
+    Ok(versions)
+}
@@ -1,9 +1,13 @@
 use std::io;
 
+use rand::{Rng, SeedableRng, XorShiftRng};
+
 use quic::endpoint_role::EndpointRole;
 use quic::errors::Error;
 use quic::packets;
-use quic::packets::frames::{Frame, padding, ping};
+use quic::packets::frames;
+use quic::packets::frames::{ack, blocked, connection_close, Frame, goaway, padding, parameters, path_challenge};
+use quic::packets::frames::{path_response, ping, rst_stream, stop_waiting, stream, window_update};
 use quic::QUIC_VERSION;
 
 
@@ -86,6 +90,7 @@ fn test_packet_encoding() {
                 multipath: true,
 
                 connection_id: Some(0xABCDEF1234567890),
+                path_id: 5,
             },
 
             version: Some(0x12345678),
@@ -106,6 +111,7 @@ fn test_packet_encoding() {
             // header
             0x6D,
             0xAB, 0xCD, 0xEF, 0x12, 0x34, 0x56, 0x78, 0x90,
+            0x05,
 
             // regular packet fields
             0x12, 0x34, 0x56, 0x78,
@@ -125,6 +131,7 @@ fn test_packet_encoding() {
                 multipath: false,
 
                 connection_id: None,
+                path_id: 0,
             },
 
             version: None,
@@ -162,6 +169,7 @@ fn test_packet_encoding() {
                 multipath: false,
 
                 connection_id: Some(0xABCDEF1234567890),
+                path_id: 0,
             },
 
             versions: vec![0x12345678, 0xABCDEF12],
@@ -190,6 +198,7 @@ fn test_packet_encoding() {
                 multipath: false,
 
                 connection_id: Some(0xABCDEF1234567890),
+                path_id: 0,
             },
         }
     );
@@ -212,6 +221,7 @@ fn test_packet_decoding() {
             // header
             0x6D,
             0xAB, 0xCD, 0xEF, 0x12, 0x34, 0x56, 0x78, 0x90,
+            0x05,
 
             // regular packet fields
             0xFA, 0xB0, 0x00, 0x01,
@@ -232,6 +242,7 @@ fn test_packet_decoding() {
                     multipath: true,
 
                     connection_id: Some(0xABCDEF1234567890),
+                    path_id: 5,
                 },
 
                 version: Some(QUIC_VERSION),
@@ -269,6 +280,7 @@ fn test_packet_decoding() {
                     multipath: false,
 
                     connection_id: None,
+                    path_id: 0,
                 },
 
                 version: None,
@@ -304,6 +316,7 @@ fn test_packet_decoding() {
                     multipath: false,
 
                     connection_id: Some(0xABCDEF1234567890),
+                    path_id: 0,
                 },
 
                 versions: vec![0x12345678, 0xABCDEF12],
@@ -328,6 +341,7 @@ fn test_packet_decoding() {
                     multipath: false,
 
                     connection_id: Some(0xABCDEF1234567890),
+                    path_id: 0,
                 },
             }
         )
@@ -338,6 +352,7 @@ fn test_packet_decoding() {
             // header
             0x6D,
             0xAB, 0xCD, 0xEF, 0x12, 0x34, 0x56, 0x78, 0x90,
+            0x05,
 
             // regular packet fields
             0xFA, 0xC0, 0x00, 0x01,
@@ -374,6 +389,7 @@ fn test_packet_decoding() {
             // header
             0x6D,
             0xAB, 0xCD, 0xEF, 0x12, 0x34, 0x56, 0x78, 0x90,
+            0x05,
 
             // regular packet fields
             0x12, 0x34, 0x56, 0x78,
@@ -392,3 +408,227 @@ fn test_packet_decoding() {
         _ => assert!(false, "Decoding error expected"),
     };
 }
+
+
+/// Encoded packets of every type, with every frame type, for mutating into malformed input
+fn valid_packets() -> Vec<Vec<u8>> {
+    let header = packets::PacketHeader {
+        key_phase: false,
+        packet_number_size: 6,
+        multipath: true,
+
+        connection_id: Some(0xABCDEF1234567890),
+        path_id: 1,
+    };
+    let frames = vec![
+        Frame::Ack(ack::AckFrame {
+            largest_acknowledged: 42,
+            ack_delay: 32,
+
+            first_ack_block_length: 64,
+            extra_ack_blocks: vec![ack::ExtraAckBlock { gap: 1, block_length: 2 }],
+
+            first_timestamp: Some(ack::FirstAckTimestamp { delta_la: 1, delta_timestamp: 2 }),
+            extra_timestamps: vec![ack::ExtraAckTimestamp { delta_la: 3, delta_timestamp: 4 }],
+        }),
+        Frame::Blocked(blocked::BlockedFrame { stream_id: 42 }),
+        Frame::ConnectionClose(connection_close::ConnectionCloseFrame {
+            error_code: 42,
+            reason_phrase: Some(String::from("reason")),
+        }),
+        Frame::GoAway(goaway::GoAwayFrame {
+            error_code: 42,
+            last_good_stream_id: 32,
+            reason_phrase: Some(String::from("reason")),
+        }),
+        Frame::Padding(padding::PaddingFrame {}),
+        Frame::Parameters(parameters::ParametersFrame { omit_connection_id: true, max_open_streams: 100 }),
+        Frame::PathChallenge(path_challenge::PathChallengeFrame { data: 42 }),
+        Frame::PathResponse(path_response::PathResponseFrame { data: 42 }),
+        Frame::Ping(ping::PingFrame {}),
+        Frame::RstStream(rst_stream::RstStreamFrame { error_code: 42, stream_id: 32, final_offset: 64 }),
+        Frame::StopWaiting(stop_waiting::StopWaitingFrame { least_acked_delta: 42 }),
+        Frame::Stream(stream::StreamFrame { stream_id: 1, offset: 32, stream_data: vec![1, 2, 3], fin: false }),
+        Frame::WindowUpdate(window_update::WindowUpdateFrame { stream_id: 42, byte_offset: 32 }),
+        Frame::Stream(stream::StreamFrame { stream_id: 3, offset: 0, stream_data: vec![4, 5, 6], fin: true }),
+    ];
+
+    let packets = [
+        packets::Packet::Regular(packets::RegularPacket {
+            header,
+
+            version: Some(QUIC_VERSION),
+            packet_number: 42,
+            payload: packets::PacketPayload { frames },
+        }),
+        packets::Packet::VersionNegotiation(packets::VersionNegotiationPacket {
+            header,
+            versions: vec![QUIC_VERSION, 0x12345678],
+        }),
+        packets::Packet::PublicReset(packets::PublicResetPacket { header }),
+    ];
+
+    packets.iter()
+        .map(|packet| {
+            let mut payload = vec![];
+            packet.encode(&mut payload).unwrap();
+            payload
+        })
+        .collect()
+}
+
+#[test]
+fn test_decoding_arbitrary_input() {
+    let mut rng = XorShiftRng::from_seed([0x2545_f491, 0x4f6c_dd1d, 0x9e37_79b9, 0x7f4a_7c15]);
+    let valid_packets = valid_packets();
+
+    for round in 0..30000 {
+        let mut input = rng.choose(&valid_packets).unwrap().clone();
+        match round % 3 {
+            // random bytes
+            0 => {
+                let size = rng.gen_range(0, 64);
+                input = (0..size).map(|_| rng.gen()).collect();
+            },
+            // a few bytes changed
+            1 => {
+                for _ in 0..rng.gen_range(1, 4) {
+                    let index = rng.gen_range(0, input.len());
+                    input[index] = rng.gen();
+                }
+            },
+            // cut short
+            _ => {
+                let size = rng.gen_range(0, input.len());
+                input.truncate(size);
+            },
+        }
+
+        // whatever decodes has to survive a round trip
+        for &endpoint_role in &[EndpointRole::Client, EndpointRole::Server] {
+            let packet = packets::Packet::decode(&mut io::Cursor::new(&input[..]), endpoint_role).ok();
+            let packet_ref = packets::PacketRef::decode(&input, endpoint_role).ok();
+            assert_eq!(packet_ref.map(packets::PacketRef::into_owned), packet);
+
+            let packet = match packet {
+                Some(packet) => packet,
+                None => continue,
+            };
+
+            let mut encoded = vec![];
+            packet.encode(&mut encoded).unwrap();
+            let decoded = packets::Packet::decode(&mut io::Cursor::new(&encoded[..]), endpoint_role).unwrap();
+            assert_eq!(decoded, packet, "Round trip failed for input {:?}", input);
+        }
+
+        let packet_number_size = rng.gen_range(0, 8);
+        let _ = Frame::decode(&mut io::Cursor::new(&input[..]), packet_number_size);
+    }
+}
+
+#[test]
+fn test_decoding_malformed_fields() {
+    let mut read = io::Cursor::new(
+        vec![
+            // a stream frame claiming 65535 bytes of data, holding 2
+            0xBF,
+            0xFF, 0xFF,
+            0x00, 0x00, 0x00, 0x2A,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20,
+            0x68, 0x65,
+        ]
+    );
+    match Frame::decode(&mut read, 6) {
+        Err(Error::Decoding(..)) => {},
+        _ => assert!(false, "Decoding error expected"),
+    };
+
+    // frame decoders called directly check their type octet and sizes
+    match blocked::BlockedFrame::decode(&mut io::Cursor::new(vec![0x07, 0x00, 0x00, 0x00, 0x2A])) {
+        Err(Error::Decoding(..)) => {},
+        _ => assert!(false, "Decoding error expected"),
+    };
+    match Frame::decode(&mut io::Cursor::new(vec![0x07]), 3) {
+        Err(Error::Decoding(..)) => {},
+        _ => assert!(false, "Decoding error expected"),
+    };
+}
+
+#[test]
+fn test_slice_decoding() {
+    for buf in valid_packets() {
+        for &endpoint_role in &[EndpointRole::Client, EndpointRole::Server] {
+            // a version negotiation packet doesn't decode on servers
+            let packet_ref = match packets::PacketRef::decode(&buf, endpoint_role) {
+                Ok(packet_ref) => packet_ref,
+                Err(_) => continue,
+            };
+            let packet = packets::Packet::decode(&mut io::Cursor::new(&buf[..]), endpoint_role).unwrap();
+            assert_eq!(packet_ref.clone().into_owned(), packet);
+
+            // stream data points into the datagram
+            if let packets::PacketRef::Regular(ref regular_packet) = packet_ref {
+                for frame in &regular_packet.payload.frames {
+                    if let frames::FrameRef::Stream(ref stream_frame) = *frame {
+                        let data_start = stream_frame.stream_data.as_ptr() as usize;
+                        let buf_start = buf.as_ptr() as usize;
+                        assert!(data_start >= buf_start && data_start + stream_frame.stream_data.len() <= buf_start + buf.len());
+                    }
+                }
+            }
+        }
+    }
+
+    match packets::PacketRef::decode(&[], EndpointRole::Server) {
+        Err(Error::Decoding(..)) => {},
+        _ => assert!(false, "Decoding error expected"),
+    };
+}
+
+#[test]
+fn test_encoding_to_slice() {
+    for buf in valid_packets() {
+        let packet = packets::Packet::decode(&mut io::Cursor::new(&buf[..]), EndpointRole::Client).unwrap();
+
+        let mut slice = [0; 1500];
+        assert_eq!(packet.encode_to_slice(&mut slice).unwrap(), buf.len());
+        assert_eq!(&slice[..buf.len()], &buf[..]);
+
+        let mut slice = vec![0; buf.len() - 1];
+        match packet.encode_to_slice(&mut slice) {
+            Err(Error::BufferOverflow) => {},
+            other => assert!(false, "Buffer overflow expected, got {:?}", other),
+        };
+    }
+}
+
+#[test]
+fn test_peeking_connection_id() {
+    for buf in valid_packets() {
+        let packet = packets::Packet::decode(&mut io::Cursor::new(&buf[..]), EndpointRole::Client).unwrap();
+        let connection_id = match packet {
+            packets::Packet::Regular(ref regular_packet) => regular_packet.header.connection_id,
+            packets::Packet::VersionNegotiation(ref version_packet) => version_packet.header.connection_id,
+            packets::Packet::PublicReset(ref public_reset_packet) => public_reset_packet.header.connection_id,
+        };
+        assert_eq!(packets::peek_connection_id(&buf), connection_id);
+    }
+
+    assert_eq!(packets::peek_connection_id(&[]), None);
+    assert_eq!(packets::peek_connection_id(&[packets::FLAG_CONNECTION_ID, 0, 0, 0]), None);
+    assert_eq!(packets::peek_connection_id(&[packets::FLAG_CONNECTION_ID, 0, 0, 0, 0, 0, 0, 1, 2]), Some(0x0102));
+}
+
+#[test]
+fn test_header_len() {
+    for buf in valid_packets() {
+        let packet = packets::Packet::decode(&mut io::Cursor::new(&buf[..]), EndpointRole::Client).unwrap();
+        if let packets::Packet::Regular(ref regular_packet) = packet {
+            let mut payload = vec![];
+            regular_packet.payload.encode(&mut payload, regular_packet.header.packet_number_size).unwrap();
+
+            let header_len = regular_packet.header.encoded_len(regular_packet.version.is_some());
+            assert_eq!(header_len + payload.len(), buf.len());
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use std::io;
+use std::io::Read;
 
-use quic::errors::Error;
+use quic::errors::{Error, Result};
 
 
 /// Return the number with all except <byte_count> least-significant bytes set to zero
@@ -12,6 +13,21 @@ pub fn truncate_u64(number: u64, byte_count: usize) -> u64 {
 }
 
 
+/// Read exactly `length` bytes
+///
+/// The buffer grows with the data actually read, so a bogus length field
+/// can't make us allocate more than the input holds.
+pub fn read_bytes<R: io::Read + ?Sized>(read: &mut R, length: usize) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    Read::take(read, length as u64).read_to_end(&mut buf)?;
+    if buf.len() < length {
+        return Err(Error::Decoding("Unexpected EOF when decoding a packet".to_string()));
+    }
+
+    Ok(buf)
+}
+
+
 /// Wrap an UnexpectedEof io error into our own Decoding error
 pub fn map_unexpected_eof(io_error: io::Error) -> Error {
     if io_error.kind() == io::ErrorKind::UnexpectedEof {
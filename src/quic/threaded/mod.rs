@@ -1,106 +1,253 @@
 //! # Threaded QUIC connections
 //! A QUIC API based on a threaded connection handler
 mod handle;
-mod timer;
+mod poller;
+mod socket;
+#[cfg(test)]
+mod tests;
 mod utils;
 mod worker;
 
+use std::cmp::max;
+use std::collections::HashMap;
 use std::io;
-use std::net::ToSocketAddrs;
-use std::sync::Arc;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
 
+use quic::engine::config::Config;
+use quic::engine::path::Migration;
 use quic::errors::Result;
 use self::utils::get_socket_addr;
 
 
+/// A connection and its streams share this, the last one dropped finalizes the connection
 #[derive(Debug)]
-pub struct QuicConnection {
+struct ConnectionRef {
     worker_ref: Arc<worker::Worker>,
     handle: handle::Handle,
+    // writers by stream id, the last one dropped finalizes the stream
+    stream_writers: Mutex<HashMap<u32, usize>>,
+}
+
+impl Drop for ConnectionRef {
+    fn drop(&mut self) {
+        if let Err(e) = self.worker_ref.finalize_connection(self.handle) {
+            error!("Could not finalize connection: {}", e);
+        }
+    }
+}
+
+
+/// A connection, which stays open while any of its streams are in use
+#[derive(Debug)]
+pub struct QuicConnection {
+    connection: Arc<ConnectionRef>,
 }
 
 impl QuicConnection {
     pub fn new<A: ToSocketAddrs>(addr: A) -> Result<QuicConnection> {
+        QuicConnection::with_config(addr, Config::default())
+    }
+
+    /// Connect with engine settings, such as `omit_connection_id` for a client that won't add paths
+    pub fn with_config<A: ToSocketAddrs>(addr: A, config: Config) -> Result<QuicConnection> {
         let addr = get_socket_addr(addr)?;
-        let worker_ref = worker::Worker::new("0.0.0.0:0", false)?;
+        let worker_ref = worker::Worker::new("0.0.0.0:0", false, config)?;
         let handle = worker_ref.new_connection(addr)?;
-        Ok(QuicConnection { worker_ref: worker_ref, handle: handle })
+        Ok(QuicConnection::from_handle(worker_ref, handle))
     }
 
-    pub fn get_stream(&self, stream_id: u32) -> QuicStream {
-        QuicStream { connection: self, stream_id: stream_id }
+    fn from_handle(worker_ref: Arc<worker::Worker>, handle: handle::Handle) -> QuicConnection {
+        QuicConnection {
+            connection: Arc::new(ConnectionRef {
+                worker_ref,
+                handle,
+                stream_writers: Mutex::new(HashMap::new()),
+            }),
+        }
     }
-}
 
-impl Drop for QuicConnection {
-    fn drop(&mut self) {
-        self.worker_ref.finalize_connection(self.handle).unwrap();
+    /// The current address of the peer, which changes when it migrates
+    pub fn peer_address(&self) -> Result<SocketAddr> {
+        self.connection.worker_ref.peer_address(self.connection.handle)
     }
-}
 
+    /// Take the next move of the peer to a new address, the connection follows it by itself
+    pub fn poll_migration(&self) -> Result<Option<Migration>> {
+        self.connection.worker_ref.pop_migration(self.connection.handle)
+    }
 
+    /// Also send and receive through a socket bound to `local_addr`, returns the path id
+    pub fn add_path<A: ToSocketAddrs>(&self, local_addr: A) -> Result<u8> {
+        worker::Worker::add_path(&self.connection.worker_ref, self.connection.handle, local_addr)
+    }
+
+    /// Open a new stream, the peer learns about it once something is written
+    pub fn open_stream(&self) -> Result<QuicStream> {
+        let stream_id = self.connection.worker_ref.open_stream(self.connection.handle)?;
+        Ok(self.get_stream(stream_id))
+    }
+
+    /// Block until the peer opens a stream
+    pub fn accept_stream(&self) -> Result<QuicStream> {
+        let stream_id = self.connection.worker_ref.accept_stream(self.connection.handle)?;
+        Ok(self.get_stream(stream_id))
+    }
+
+    /// A stream of this connection, which can be moved to other threads
+    ///
+    /// Getting a stream more than once is fine, it is finalized once all of them are dropped.
+    pub fn get_stream(&self, stream_id: u32) -> QuicStream {
+        *self.connection.stream_writers.lock().unwrap().entry(stream_id).or_insert(0) += 1;
+
+        QuicStream {
+            reader: QuicStreamReader {
+                connection: self.connection.clone(),
+                stream_id,
+            },
+            writer: QuicStreamWriter {
+                connection: self.connection.clone(),
+                stream_id,
+            },
+        }
+    }
+}
+
+/// A bidirectional stream, finalized in the outgoing direction when dropped
+///
+/// It keeps its connection open, even after the `QuicConnection` is dropped.
 #[derive(Debug)]
-pub struct QuicStream<'a> {
-    connection: &'a QuicConnection,
-    stream_id: u32,
+pub struct QuicStream {
+    reader: QuicStreamReader,
+    writer: QuicStreamWriter,
 }
 
-impl<'a> QuicStream<'a> {
-    pub fn finalize(&self) {
-        let handle = self.connection.handle;
-        let stream_id = self.stream_id;
+impl QuicStream {
+    pub fn id(&self) -> u32 {
+        self.writer.stream_id
+    }
 
-        self.connection.worker_ref.finalize_outgoing_stream(handle, stream_id).unwrap();
+    pub fn finalize(&self) -> Result<()> {
+        self.writer.finalize()
     }
-}
 
-impl<'a> Drop for QuicStream<'a> {
-    fn drop(&mut self) {
-        self.finalize();
+    /// Split into halves that can be used from different threads
+    pub fn split(self) -> (QuicStreamReader, QuicStreamWriter) {
+        (self.reader, self.writer)
     }
 }
 
-impl<'a> io::Read for QuicStream<'a> {
+impl io::Read for QuicStream {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let handle = self.connection.handle;
-        let stream_id = self.stream_id;
+        self.reader.read(buf)
+    }
+}
 
-        self.connection.worker_ref.read(handle, stream_id, buf)
+impl io::Write for QuicStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    /// Block until the peer acknowledged everything written so far
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+
+/// The receiving half of a stream
+#[derive(Debug)]
+pub struct QuicStreamReader {
+    connection: Arc<ConnectionRef>,
+    stream_id: u32,
+}
+
+impl io::Read for QuicStreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.connection.worker_ref.read(self.connection.handle, self.stream_id, buf)
             .map_err(|e| e.into())
     }
 }
 
-impl<'a> io::Write for QuicStream<'a> {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let handle = self.connection.handle;
-        let stream_id = self.stream_id;
 
-        self.connection.worker_ref.write(handle, stream_id, buf)
-            .map_err::<io::Error, _>(|e| e.into())?;
+/// The sending half of a stream, the last one of a stream finalizes it when dropped
+#[derive(Debug)]
+pub struct QuicStreamWriter {
+    connection: Arc<ConnectionRef>,
+    stream_id: u32,
+}
+
+impl QuicStreamWriter {
+    pub fn finalize(&self) -> Result<()> {
+        self.connection.worker_ref.finalize_outgoing_stream(self.connection.handle, self.stream_id)
+    }
+}
+
+impl Drop for QuicStreamWriter {
+    fn drop(&mut self) {
+        {
+            let mut stream_writers = self.connection.stream_writers.lock().unwrap();
+            let count = stream_writers.get_mut(&self.stream_id).unwrap();
+            *count -= 1;
+            if *count > 0 {
+                return;
+            }
+            stream_writers.remove(&self.stream_id);
+        }
+
+        // the connection may be gone already
+        if let Err(e) = self.finalize() {
+            debug!("Could not finalize stream {}: {}", self.stream_id, e);
+        }
+    }
+}
 
-        Ok(buf.len())
+impl io::Write for QuicStreamWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.connection.worker_ref.write(self.connection.handle, self.stream_id, buf)
+            .map_err(|e| e.into())
     }
 
+    /// Block until the peer acknowledged everything written so far
     fn flush(&mut self) -> io::Result<()> {
-        Ok(())
+        self.connection.worker_ref.flush(self.connection.handle, self.stream_id)
+            .map_err(|e| e.into())
     }
 }
 
 
 #[derive(Debug)]
 pub struct QuicListener {
-    worker_ref: Arc<worker::Worker>,
+    workers: Vec<Arc<worker::Worker>>,
 }
 
 impl QuicListener {
     pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<QuicListener> {
-        let worker_ref = worker::Worker::new(addr, true)?;
-        Ok(QuicListener { worker_ref: worker_ref })
+        QuicListener::bind_with_config(addr, Config::default())
+    }
+
+    pub fn bind_with_config<A: ToSocketAddrs>(addr: A, config: Config) -> Result<QuicListener> {
+        let worker_ref = worker::Worker::new(addr, true, config)?;
+        Ok(QuicListener { workers: vec![worker_ref] })
+    }
+
+    /// Spread connections over `worker_count` threads, each connection stays on one of them
+    pub fn bind_sharded<A: ToSocketAddrs>(addr: A, worker_count: usize) -> Result<QuicListener> {
+        QuicListener::bind_sharded_with_config(addr, Config::default(), worker_count)
+    }
+
+    pub fn bind_sharded_with_config<A: ToSocketAddrs>(addr: A, config: Config, worker_count: usize) -> Result<QuicListener> {
+        let workers = worker::Worker::new_sharded(addr, config, max(worker_count, 1))?;
+        Ok(QuicListener { workers })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.workers[0].local_address()
     }
 
     pub fn accept(&self) -> Result<QuicConnection> {
-        let handle = self.worker_ref.accept()?;
+        let (worker_ref, handle) = worker::Worker::accept(&self.workers)?;
 
-        Ok(QuicConnection { worker_ref: self.worker_ref.clone(), handle: handle })
+        Ok(QuicConnection::from_handle(worker_ref, handle))
     }
 }
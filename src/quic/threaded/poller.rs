@@ -0,0 +1,157 @@
+use std::io;
+use std::net;
+use std::time;
+
+
+/// How long the fallback waits before checking the sockets again
+#[cfg(not(target_os = "linux"))]
+const FALLBACK_INTERVAL_MS: u64 = 5;
+
+
+/// Waits for datagrams on the worker's sockets, or for application threads calling `wake`
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct Poller {
+    event_fd: libc::c_int,
+    // set between `wake` and the worker catching up, so that wakeups don't pile up
+    wake_pending: ::std::sync::atomic::AtomicBool,
+}
+
+#[cfg(target_os = "linux")]
+impl Poller {
+    pub fn new() -> io::Result<Poller> {
+        use std::sync::atomic::AtomicBool;
+
+        use libc;
+
+        let event_fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if event_fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Poller {
+            event_fd,
+            wake_pending: AtomicBool::new(false),
+        })
+    }
+
+    /// Make the worker look at its state again, call after changing it
+    pub fn wake(&self) {
+        use std::sync::atomic::Ordering;
+
+        use libc;
+
+        if self.wake_pending.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let value: u64 = 1;
+        let result = unsafe {
+            libc::write(self.event_fd, &value as *const u64 as *const libc::c_void, 8)
+        };
+        if result < 0 {
+            error!("Could not wake the worker: {:?}", io::Error::last_os_error());
+        }
+    }
+
+    /// Wait until a socket is readable, `wake` is called or the timeout passes
+    ///
+    /// `readable` is filled in for `sockets`, without a timeout this waits indefinitely.
+    pub fn wait(&self, sockets: &[&net::UdpSocket], readable: &mut Vec<bool>, timeout: Option<time::Duration>) -> io::Result<()> {
+        use std::cmp::min;
+        use std::os::unix::io::AsRawFd;
+        use std::sync::atomic::Ordering;
+
+        use libc;
+
+        let mut poll_fds = Vec::with_capacity(sockets.len() + 1);
+        poll_fds.push(libc::pollfd { fd: self.event_fd, events: libc::POLLIN, revents: 0 });
+        for udp_socket in sockets {
+            poll_fds.push(libc::pollfd { fd: udp_socket.as_raw_fd(), events: libc::POLLIN, revents: 0 });
+        }
+
+        let timeout_ms = match timeout {
+            Some(timeout) => {
+                let timeout_ms = timeout.as_secs().saturating_mul(1000) + (u64::from(timeout.subsec_nanos()) + 999_999) / 1_000_000;
+                min(timeout_ms, libc::c_int::MAX as u64) as libc::c_int
+            },
+            None => -1,
+        };
+
+        readable.clear();
+        let result = unsafe { libc::poll(poll_fds.as_mut_ptr(), poll_fds.len() as libc::nfds_t, timeout_ms) };
+        if result < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                readable.resize(sockets.len(), false);
+                return Ok(());
+            }
+            return Err(error);
+        }
+
+        // cleared before the worker looks at its state, so no change goes unnoticed
+        if poll_fds[0].revents != 0 {
+            self.wake_pending.store(false, Ordering::SeqCst);
+            let mut value: u64 = 0;
+            unsafe {
+                libc::read(self.event_fd, &mut value as *mut u64 as *mut libc::c_void, 8);
+            }
+        }
+
+        readable.extend(poll_fds[1..].iter().map(|poll_fd| poll_fd.revents != 0));
+
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Poller {
+    fn drop(&mut self) {
+        use libc;
+
+        unsafe {
+            libc::close(self.event_fd);
+        }
+    }
+}
+
+
+/// Waits for application threads calling `wake`, and checks the sockets every few milliseconds
+#[cfg(not(target_os = "linux"))]
+#[derive(Debug, Default)]
+pub struct Poller {
+    wake_pending: ::std::sync::Mutex<bool>,
+    woken: ::std::sync::Condvar,
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Poller {
+    pub fn new() -> io::Result<Poller> {
+        Ok(Poller::default())
+    }
+
+    /// Make the worker look at its state again, call after changing it
+    pub fn wake(&self) {
+        *self.wake_pending.lock().unwrap() = true;
+        self.woken.notify_one();
+    }
+
+    /// Wait until `wake` is called or the timeout passes, all sockets may be readable then
+    pub fn wait(&self, sockets: &[&net::UdpSocket], readable: &mut Vec<bool>, timeout: Option<time::Duration>) -> io::Result<()> {
+        use std::cmp::min;
+
+        let interval = time::Duration::from_millis(FALLBACK_INTERVAL_MS);
+        let timeout = timeout.map_or(interval, |timeout| min(timeout, interval));
+
+        let mut wake_pending = self.wake_pending.lock().unwrap();
+        if !*wake_pending {
+            wake_pending = self.woken.wait_timeout(wake_pending, timeout).unwrap().0;
+        }
+        *wake_pending = false;
+
+        readable.clear();
+        readable.resize(sockets.len(), true);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+use std::io;
+use std::net;
+
+use quic::engine::udp_packet::OutgoingUdpPacket;
+
+
+/// How many datagrams are received or sent with one system call at most
+pub const BATCH_SIZE: usize = 16;
+
+
+/// Set the don't-fragment bit on outgoing packets, so oversized PMTU probes get dropped
+/// instead of fragmented
+#[cfg(target_os = "linux")]
+pub fn set_dont_fragment(udp_socket: &net::UdpSocket) -> io::Result<()> {
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+
+    use libc;
+
+    // PROBE sets DF but ignores the kernel's cached path MTU, we discover it ourselves
+    let (level, option, value) = match udp_socket.local_addr()? {
+        net::SocketAddr::V4(..) => (libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, libc::IP_PMTUDISC_PROBE),
+        net::SocketAddr::V6(..) => (libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER, libc::IPV6_PMTUDISC_PROBE),
+    };
+
+    let result = unsafe {
+        libc::setsockopt(
+            udp_socket.as_raw_fd(),
+            level,
+            option,
+            &value as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_dont_fragment(_udp_socket: &net::UdpSocket) -> io::Result<()> {
+    Ok(())
+}
+
+/// Whether a send failed because the packet exceeds the local MTU, which is expected for probes
+pub fn is_message_too_long(error: &io::Error) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        use libc;
+
+        error.raw_os_error() == Some(libc::EMSGSIZE)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = error;
+        false
+    }
+}
+
+
+/// Buffers for receiving a batch of datagrams
+#[derive(Debug)]
+pub struct RecvBatch {
+    buffers: Vec<Vec<u8>>,
+    // source address and size of what each buffer received
+    received: Vec<(net::SocketAddr, usize)>,
+}
+
+impl RecvBatch {
+    pub fn new(buffer_size: usize) -> RecvBatch {
+        RecvBatch {
+            buffers: vec![vec![0; buffer_size]; BATCH_SIZE],
+            received: Vec::with_capacity(BATCH_SIZE),
+        }
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffers[0].len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.received.len()
+    }
+
+    /// The source address and data of a received datagram, oversized ones fill the whole buffer
+    pub fn get(&self, index: usize) -> (net::SocketAddr, &[u8]) {
+        let (source_address, size) = self.received[index];
+        (source_address, &self.buffers[index][..size])
+    }
+}
+
+
+#[cfg(target_os = "linux")]
+fn to_raw_address(address: &net::SocketAddr, storage: &mut libc::sockaddr_storage) -> libc::socklen_t {
+    use std::mem;
+
+    use libc;
+
+    match *address {
+        net::SocketAddr::V4(ref address) => {
+            let raw_address = unsafe { &mut *(storage as *mut _ as *mut libc::sockaddr_in) };
+            raw_address.sin_family = libc::AF_INET as libc::sa_family_t;
+            raw_address.sin_port = address.port().to_be();
+            raw_address.sin_addr = libc::in_addr { s_addr: u32::from(*address.ip()).to_be() };
+
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t
+        },
+        net::SocketAddr::V6(ref address) => {
+            let raw_address = unsafe { &mut *(storage as *mut _ as *mut libc::sockaddr_in6) };
+            raw_address.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            raw_address.sin6_port = address.port().to_be();
+            raw_address.sin6_flowinfo = address.flowinfo();
+            raw_address.sin6_addr = libc::in6_addr { s6_addr: address.ip().octets() };
+            raw_address.sin6_scope_id = address.scope_id();
+
+            mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t
+        },
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn from_raw_address(storage: &libc::sockaddr_storage) -> Option<net::SocketAddr> {
+    use libc;
+
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let raw_address = unsafe { &*(storage as *const _ as *const libc::sockaddr_in) };
+            let ip = net::Ipv4Addr::from(u32::from_be(raw_address.sin_addr.s_addr));
+
+            Some(net::SocketAddr::V4(net::SocketAddrV4::new(ip, u16::from_be(raw_address.sin_port))))
+        },
+        libc::AF_INET6 => {
+            let raw_address = unsafe { &*(storage as *const _ as *const libc::sockaddr_in6) };
+            let ip = net::Ipv6Addr::from(raw_address.sin6_addr.s6_addr);
+
+            Some(net::SocketAddr::V6(net::SocketAddrV6::new(
+                ip,
+                u16::from_be(raw_address.sin6_port),
+                raw_address.sin6_flowinfo,
+                raw_address.sin6_scope_id,
+            )))
+        },
+        _ => None,
+    }
+}
+
+/// Receive up to `BATCH_SIZE` datagrams that are waiting already, returns how many
+#[cfg(target_os = "linux")]
+pub fn recv_batch(udp_socket: &net::UdpSocket, batch: &mut RecvBatch) -> io::Result<usize> {
+    use std::os::unix::io::AsRawFd;
+
+    use libc;
+
+    batch.received.clear();
+    match recv_mmsg(udp_socket.as_raw_fd(), batch, libc::MSG_DONTWAIT) {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::Interrupted => Ok(0),
+        result => result,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn recv_mmsg(fd: libc::c_int, batch: &mut RecvBatch, flags: libc::c_int) -> io::Result<usize> {
+    use std::mem;
+    use std::ptr;
+
+    use libc;
+
+    let mut addresses: [libc::sockaddr_storage; BATCH_SIZE] = unsafe { mem::zeroed() };
+    let mut iovecs: [libc::iovec; BATCH_SIZE] = unsafe { mem::zeroed() };
+    let mut messages: [libc::mmsghdr; BATCH_SIZE] = unsafe { mem::zeroed() };
+    for index in 0..BATCH_SIZE {
+        iovecs[index].iov_base = batch.buffers[index].as_mut_ptr() as *mut libc::c_void;
+        iovecs[index].iov_len = batch.buffers[index].len();
+
+        messages[index].msg_hdr.msg_name = &mut addresses[index] as *mut _ as *mut libc::c_void;
+        messages[index].msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        messages[index].msg_hdr.msg_iov = &mut iovecs[index];
+        messages[index].msg_hdr.msg_iovlen = 1;
+    }
+
+    let count = unsafe { libc::recvmmsg(fd, messages.as_mut_ptr(), BATCH_SIZE as libc::c_uint, flags, ptr::null_mut()) };
+    if count < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    for index in 0..count as usize {
+        // only IP sockets are used
+        let source_address = from_raw_address(&addresses[index])
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Unknown address family"))?;
+        batch.received.push((source_address, messages[index].msg_len as usize));
+    }
+
+    Ok(count as usize)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn recv_batch(udp_socket: &net::UdpSocket, batch: &mut RecvBatch) -> io::Result<usize> {
+    batch.received.clear();
+
+    // sends stay blocking
+    udp_socket.set_nonblocking(true)?;
+    let result = udp_socket.recv_from(&mut batch.buffers[0]);
+    udp_socket.set_nonblocking(false)?;
+
+    match result {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+        Err(e) => Err(e),
+        Ok((size, source_address)) => {
+            batch.received.push((source_address, size));
+            Ok(1)
+        },
+    }
+}
+
+/// Send from the start of `packets`, returns how many were sent
+///
+/// An error is about the first packet, the caller skips it and sends the rest.
+#[cfg(target_os = "linux")]
+pub fn send_batch(udp_socket: &net::UdpSocket, packets: &[OutgoingUdpPacket]) -> io::Result<usize> {
+    use std::cmp::min;
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+
+    use libc;
+
+    let count = min(packets.len(), BATCH_SIZE);
+    let mut addresses: [libc::sockaddr_storage; BATCH_SIZE] = unsafe { mem::zeroed() };
+    let mut iovecs: [libc::iovec; BATCH_SIZE] = unsafe { mem::zeroed() };
+    let mut messages: [libc::mmsghdr; BATCH_SIZE] = unsafe { mem::zeroed() };
+    for (index, packet) in packets[..count].iter().enumerate() {
+        iovecs[index].iov_base = packet.payload.as_ptr() as *mut libc::c_void;
+        iovecs[index].iov_len = packet.payload.len();
+
+        messages[index].msg_hdr.msg_namelen = to_raw_address(&packet.destination_address, &mut addresses[index]);
+        messages[index].msg_hdr.msg_name = &mut addresses[index] as *mut _ as *mut libc::c_void;
+        messages[index].msg_hdr.msg_iov = &mut iovecs[index];
+        messages[index].msg_hdr.msg_iovlen = 1;
+    }
+
+    let sent = unsafe { libc::sendmmsg(udp_socket.as_raw_fd(), messages.as_mut_ptr(), count as libc::c_uint, 0) };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(sent as usize)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn send_batch(udp_socket: &net::UdpSocket, packets: &[OutgoingUdpPacket]) -> io::Result<usize> {
+    udp_socket.send_to(&packets[0].payload, packets[0].destination_address)?;
+    Ok(1)
+}
@@ -0,0 +1,238 @@
+use std::io::{Read, Write};
+use std::net;
+use std::sync::Arc;
+use std::thread;
+use std::time;
+
+use quic::engine::udp_packet::OutgoingUdpPacket;
+use quic::threaded::{QuicConnection, QuicListener};
+use quic::threaded::poller::Poller;
+use quic::threaded::socket;
+
+
+fn bind() -> net::UdpSocket {
+    net::UdpSocket::bind("127.0.0.1:0").unwrap()
+}
+
+
+#[test]
+fn test_batches() {
+    let sender = bind();
+    let receiver = bind();
+    let packets: Vec<OutgoingUdpPacket> =
+        (0..socket::BATCH_SIZE + 4)
+        .map(|index| OutgoingUdpPacket {
+            source_address: None,
+            destination_address: receiver.local_addr().unwrap(),
+            payload: vec![index as u8; index + 1],
+        })
+        .collect();
+
+    let mut sent = 0;
+    while sent < packets.len() {
+        sent += socket::send_batch(&sender, &packets[sent..]).unwrap();
+    }
+
+    let poller = Poller::new().unwrap();
+    let mut readable = vec![];
+    let mut batch = socket::RecvBatch::new(2048);
+    let mut received = vec![];
+    while received.len() < packets.len() {
+        poller.wait(&[&receiver], &mut readable, Some(time::Duration::from_secs(5))).unwrap();
+        assert_eq!(readable, vec![true]);
+
+        let count = socket::recv_batch(&receiver, &mut batch).unwrap();
+        assert!(count > 0);
+        for index in 0..batch.len() {
+            let (source_address, data) = batch.get(index);
+            assert_eq!(source_address, sender.local_addr().unwrap());
+            received.push(data.to_vec());
+        }
+    }
+
+    let payloads: Vec<Vec<u8>> = packets.into_iter().map(|packet| packet.payload).collect();
+    assert_eq!(received, payloads);
+}
+
+#[test]
+fn test_nothing_received() {
+    let receiver = bind();
+    let mut batch = socket::RecvBatch::new(2048);
+
+    assert_eq!(socket::recv_batch(&receiver, &mut batch).unwrap(), 0);
+    assert_eq!(batch.len(), 0);
+}
+
+#[test]
+fn test_poll_timeout() {
+    let receiver = bind();
+    let poller = Poller::new().unwrap();
+    let mut readable = vec![];
+
+    let start = time::Instant::now();
+    poller.wait(&[&receiver], &mut readable, Some(time::Duration::from_millis(20))).unwrap();
+    assert!(start.elapsed() >= time::Duration::from_millis(20));
+    assert_eq!(readable, vec![false]);
+}
+
+#[test]
+fn test_wake() {
+    let receiver = bind();
+    let poller = Arc::new(Poller::new().unwrap());
+    let mut readable = vec![];
+
+    // a wakeup before waiting isn't lost
+    poller.wake();
+    poller.wake();
+    poller.wait(&[&receiver], &mut readable, None).unwrap();
+
+    let thread_poller = poller.clone();
+    let waker = thread::spawn(move || {
+        thread::sleep(time::Duration::from_millis(20));
+        thread_poller.wake();
+    });
+    let start = time::Instant::now();
+    poller.wait(&[&receiver], &mut readable, Some(time::Duration::from_secs(10))).unwrap();
+    assert!(start.elapsed() < time::Duration::from_secs(5));
+    assert_eq!(readable, vec![false]);
+    waker.join().unwrap();
+}
+
+#[test]
+fn test_sharded_listener() {
+    const CLIENTS: usize = 6;
+
+    let listener = QuicListener::bind_sharded("127.0.0.1:0", 3).unwrap();
+    let server_address = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let echoers: Vec<thread::JoinHandle<()>> =
+            (0..CLIENTS)
+            .map(|_| {
+                let connection = listener.accept().unwrap();
+                thread::spawn(move || {
+                    let mut stream = connection.accept_stream().unwrap();
+                    let mut buf = vec![];
+                    stream.read_to_end(&mut buf).unwrap();
+                    stream.write_all(&buf).unwrap();
+                    // dropping the connection waits until the client has everything
+                    stream.finalize().unwrap();
+                })
+            })
+            .collect();
+        for echoer in echoers {
+            echoer.join().unwrap();
+        }
+    });
+
+    let clients: Vec<thread::JoinHandle<()>> =
+        (0..CLIENTS)
+        .map(|index| {
+            thread::spawn(move || {
+                let connection = QuicConnection::new(server_address).unwrap();
+                let mut stream = connection.open_stream().unwrap();
+                let message = vec![index as u8; 10000 + index];
+                stream.write_all(&message).unwrap();
+                stream.finalize().unwrap();
+
+                let mut echoed = vec![];
+                stream.read_to_end(&mut echoed).unwrap();
+                assert_eq!(echoed, message);
+            })
+        })
+        .collect();
+
+    for client in clients {
+        client.join().unwrap();
+    }
+    server.join().unwrap();
+}
+
+#[test]
+fn test_streams_outlive_connection() {
+    let listener = QuicListener::bind("127.0.0.1:0").unwrap();
+    let server_address = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let mut stream = listener.accept().unwrap().accept_stream().unwrap();
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).unwrap();
+        stream.write_all(&buf).unwrap();
+    });
+
+    let (mut reader, mut writer) = QuicConnection::new(server_address).unwrap().open_stream().unwrap().split();
+    let message = vec![7; 10000];
+    writer.write_all(&message).unwrap();
+    drop(writer);
+
+    let mut echoed = vec![];
+    reader.read_to_end(&mut echoed).unwrap();
+    assert_eq!(echoed, message);
+
+    server.join().unwrap();
+}
+
+#[test]
+fn test_stream_taken_twice() {
+    let listener = QuicListener::bind("127.0.0.1:0").unwrap();
+    let server_address = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let mut stream = listener.accept().unwrap().accept_stream().unwrap();
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"hello world");
+    });
+
+    let connection = QuicConnection::new(server_address).unwrap();
+    let mut stream = connection.open_stream().unwrap();
+    stream.write_all(b"hello").unwrap();
+
+    // dropping another handle to the stream doesn't finalize it
+    drop(connection.get_stream(stream.id()));
+    stream.write_all(b" world").unwrap();
+    drop(stream);
+
+    server.join().unwrap();
+}
+
+#[test]
+fn test_stalled_connection_does_not_block_others() {
+    let listener = QuicListener::bind("127.0.0.1:0").unwrap();
+    let server_address = listener.local_addr().unwrap();
+
+    // nobody reads the first connection until the second one is done
+    let stalled_client = thread::spawn(move || {
+        let connection = QuicConnection::new(server_address).unwrap();
+        let mut stream = connection.open_stream().unwrap();
+        stream.write_all(&vec![1; 4 * 1024 * 1024]).unwrap();
+        stream.finalize().unwrap();
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"done");
+    });
+    let mut stalled_stream = listener.accept().unwrap().accept_stream().unwrap();
+
+    let client = thread::spawn(move || {
+        let connection = QuicConnection::new(server_address).unwrap();
+        let mut stream = connection.open_stream().unwrap();
+        stream.write_all(b"hello").unwrap();
+        stream.finalize().unwrap();
+        let mut echoed = vec![];
+        stream.read_to_end(&mut echoed).unwrap();
+        assert_eq!(echoed, b"hello");
+    });
+    let mut stream = listener.accept().unwrap().accept_stream().unwrap();
+    let mut buf = vec![];
+    stream.read_to_end(&mut buf).unwrap();
+    stream.write_all(&buf).unwrap();
+    stream.finalize().unwrap();
+    client.join().unwrap();
+
+    let mut stalled = vec![];
+    stalled_stream.read_to_end(&mut stalled).unwrap();
+    assert_eq!(stalled.len(), 4 * 1024 * 1024);
+    stalled_stream.write_all(b"done").unwrap();
+    drop(stalled_stream);
+    stalled_client.join().unwrap();
+}
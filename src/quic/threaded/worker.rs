@@ -1,240 +1,770 @@
-use std::collections::HashMap;
-use std::io;
+use std::cmp::min;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::mem;
 use std::net;
 use std::net::{SocketAddr, ToSocketAddrs};
-use std::sync::{Arc, Condvar, Mutex};
+use std::ptr;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
 use std::thread;
 use std::time;
 
 use quic::engine::QuicEngine;
+use quic::engine::config::Config;
+use quic::engine::event::Event;
+use quic::engine::path::Migration;
+use quic::engine::timer::HeapTimer;
 use quic::engine::udp_packet::{IncomingUdpPacket, OutgoingUdpPacket};
-use quic::errors::{Error, Result};
+use quic::errors::{codes, Error, Result};
+use quic::packets;
 use super::handle::{Handle, HandleGenerator};
-use super::timer::ThreadedTimer;
+use super::poller::Poller;
+use super::socket;
 
 
+const UDP_BUF_SIZE: usize = 65535;
+
+/// Bytes a stream stages in each direction between application threads and the engine
+const STREAM_STAGING_SIZE: usize = 64 * 1024;
+
+/// Stream data on its way between application threads and the engine
+#[derive(Debug, Default)]
+struct StreamIo {
+    // read from the engine by the worker, for readers
+    incoming: VecDeque<u8>,
+    finished: bool,
+    // reported to the next reader
+    read_error: Option<Error>,
+    // written by application threads, for the worker to pass to the engine
+    outgoing: VecDeque<u8>,
+    // the worker finalizes the stream once `outgoing` is passed on
+    finalizing: bool,
+    finalized: bool,
+    // set by the worker once everything passed on was acknowledged
+    flush_requested: bool,
+    flushed: bool,
+    // reported to the next writer
+    write_error: Option<Error>,
+}
+
+impl StreamIo {
+    /// Copy staged data to `buf`
+    fn take_incoming(&mut self, buf: &mut [u8]) -> usize {
+        let size = min(buf.len(), self.incoming.len());
+        {
+            let (front, back) = self.incoming.as_slices();
+            let front_size = min(size, front.len());
+            buf[..front_size].copy_from_slice(&front[..front_size]);
+            buf[front_size..size].copy_from_slice(&back[..size - front_size]);
+        }
+        self.incoming.drain(..size);
+        size
+    }
+
+    /// Read what the engine has for the stream, returns whether there's news for readers
+    fn stage_incoming(
+            &mut self,
+            engine: &mut QuicEngine<HeapTimer>,
+            connection_id: u64,
+            stream_id: u32,
+            read_buffer: &mut [u8]) -> bool {
+        let mut staged = false;
+        while !self.finished && self.read_error.is_none() && self.incoming.len() < STREAM_STAGING_SIZE &&
+                engine.data_available(connection_id, stream_id) {
+            staged = true;
+            let size = min(read_buffer.len(), STREAM_STAGING_SIZE - self.incoming.len());
+            match engine.read(connection_id, stream_id, &mut read_buffer[..size]) {
+                Ok(0) => self.finished = true,
+                Ok(read_size) => self.incoming.extend(&read_buffer[..read_size]),
+                Err(e) => self.read_error = Some(e),
+            }
+        }
+        staged
+    }
+
+    /// Write staged data to the engine, then finalize or check for a flush, returns whether there's news for writers
+    fn pass_outgoing(&mut self, engine: &mut QuicEngine<HeapTimer>, connection_id: u64, stream_id: u32) -> bool {
+        let staged_size = self.outgoing.len();
+        while !self.outgoing.is_empty() {
+            let written_size = match engine.write(connection_id, stream_id, self.outgoing.as_slices().0) {
+                Ok(written_size) => written_size,
+                Err(e) => return self.fail_writes(e),
+            };
+            if written_size == 0 {
+                // the engine reports the stream as writable once it has room
+                return self.outgoing.len() < staged_size;
+            }
+            self.outgoing.drain(..written_size);
+        }
+
+        if self.finalizing && !self.finalized {
+            self.finalized = true;
+            if let Err(e) = engine.finalize_outgoing_stream(connection_id, stream_id) {
+                return self.fail_writes(e);
+            }
+        }
+        if self.flush_requested {
+            match engine.is_flushed(connection_id, stream_id) {
+                Ok(true) => {
+                    self.flush_requested = false;
+                    self.flushed = true;
+                    return true;
+                },
+                // the engine reports the stream as writable once acknowledgements arrive
+                Ok(false) => {},
+                Err(e) => return self.fail_writes(e),
+            }
+        }
+
+        self.outgoing.len() < staged_size
+    }
+
+    fn fail_writes(&mut self, error: Error) -> bool {
+        debug!("Dropping {} bytes staged for writing: {}", self.outgoing.len(), error);
+        self.outgoing.clear();
+        self.flush_requested = false;
+        self.write_error = Some(error);
+        true
+    }
+
+    fn has_staged_writes(&self) -> bool {
+        !self.outgoing.is_empty() || (self.finalizing && !self.finalized)
+    }
+
+    fn is_done(&self) -> bool {
+        self.finished && self.incoming.is_empty() && self.read_error.is_none() &&
+            self.finalized && !self.has_staged_writes() && !self.flush_requested
+    }
+}
+
+
+/// Stream data and what the engine reported, shared by application threads and the worker
+#[derive(Debug, Default)]
+struct ConnectionIo {
+    streams: HashMap<u32, StreamIo>,
+    // changes of the peer's address the application hasn't taken yet
+    migrations: VecDeque<Migration>,
+    streams_available: bool,
+    finalized: bool,
+    closed: bool,
+}
+
+
+/// A connection as seen by application threads
+///
+/// Reads and writes only take this connection's lock, the worker thread moves stream
+/// data between it and the engine. Other waits clear their signal with the engine
+/// locked, so that nothing the worker reports in between is lost.
 #[derive(Debug, Default)]
 struct WorkerConnection {
     connection_id: u64,
-    data_available: Arc<Condvar>,
-    finalized: Arc<Condvar>,
+    io: Mutex<ConnectionIo>,
+    data_available: Condvar,
+    writable: Condvar,
+    streams_available: Condvar,
+    finalized: Condvar,
 }
 
+impl WorkerConnection {
+    fn new(connection_id: u64) -> WorkerConnection {
+        WorkerConnection {
+            connection_id,
+            ..WorkerConnection::default()
+        }
+    }
+
+    fn signal<F: FnOnce(&mut ConnectionIo)>(&self, condvar: &Condvar, update: F) {
+        update(&mut self.io.lock().unwrap());
+        condvar.notify_all();
+    }
+
+    fn clear<F: FnOnce(&mut ConnectionIo)>(&self, update: F) {
+        update(&mut self.io.lock().unwrap());
+    }
+
+    /// Block on `condvar` until `ready`
+    fn wait<F: Fn(&ConnectionIo) -> bool>(&self, condvar: &Condvar, ready: F) {
+        let mut io = self.io.lock().unwrap();
+        while !ready(&io) {
+            io = condvar.wait(io).unwrap();
+        }
+    }
 
+    fn has_staged_writes(&self) -> bool {
+        self.io.lock().unwrap().streams.values().any(StreamIo::has_staged_writes)
+    }
+}
+
+
+/// Datagrams a dispatcher passed on to a sharded worker
+#[derive(Debug, Default)]
+struct Inbox {
+    packets: Vec<IncomingUdpPacket>,
+    // payload buffers the worker returns for the next datagrams
+    spare_buffers: Vec<Vec<u8>>,
+}
+
+
+/// Connections established by peers, waiting for `accept`, shared by the workers of a listener
 #[derive(Debug, Default)]
+struct AcceptQueue {
+    // the shard of the worker running the connection, and its id
+    connection_ids: Mutex<VecDeque<(usize, u64)>>,
+    available: Condvar,
+}
+
+
+/// Connections by the handles given out to application threads
+#[derive(Debug)]
+struct HandleTable {
+    handle_generator: HandleGenerator,
+    connections: HashMap<Handle, Arc<WorkerConnection>>,
+}
+
+
+#[derive(Debug)]
 struct WorkerState {
     // checks
     started: bool,
 
     // engine
-    engine: QuicEngine<ThreadedTimer>,
+    engine: QuicEngine<HeapTimer>,
+    // what the worker reads from the engine passes through this
+    read_buffer: Vec<u8>,
 
-    // connections
-    handle_generator: HandleGenerator,
-    connection_map: HashMap<Handle, WorkerConnection>,
+    // connections with a handle, by connection id
+    connections: HashMap<u64, Arc<WorkerConnection>>,
+    // connections someone waits for in `finalize_connection`
+    finalizing: HashSet<u64>,
 
-    connections_available: Arc<Condvar>,
+    shard: usize,
+    accept_queue: Arc<AcceptQueue>,
 }
 
 impl WorkerState {
     fn handle_incoming_packet(&mut self, packet: IncomingUdpPacket) {
-        self.engine.handle_incoming_packet(packet);
+        self.engine.handle_incoming_packet(time::Instant::now(), packet);
+    }
+
+    /// Handle due events, and bring the engine's clock up to date for what follows
+    fn handle_timeout(&mut self) {
+        self.engine.handle_timeout(time::Instant::now());
+        self.dispatch_events();
     }
 
-    fn get_event_timeout(&self) -> time::Duration {
-        self.engine.timer_ref().time_until_next_event()
-            .unwrap_or_else(|| {
-                trace!("No events pending, using default timeout");
-                time::Duration::from_millis(50)
+    /// Time until the next scheduled event, application calls wake the worker otherwise
+    fn get_event_timeout(&self) -> Option<time::Duration> {
+        self.engine.next_timeout()
+            .map(|deadline| {
+                let now = time::Instant::now();
+                if deadline >= now {
+                    deadline - now
+                } else {
+                    time::Duration::from_millis(0)
+                }
             })
     }
 
-    fn signal_data_available(&self) {
-        for connection in self.connection_map.values() {
-            if self.engine.any_data_available(connection.connection_id) {
-                connection.data_available.notify_all();
+    /// Wake up the threads waiting for what the engine reported
+    fn dispatch_events(&mut self) {
+        while let Some(event) = self.engine.poll_event() {
+            trace!("Dispatching event: {:?}", event);
+
+            let connection = match self.connections.get(&event.connection_id()) {
+                Some(connection) => connection.clone(),
+                None => {
+                    // connections we didn't initiate get their handle in `accept`
+                    if let Event::ConnectionEstablished(connection_id) = event {
+                        self.accept_queue.connection_ids.lock().unwrap().push_back((self.shard, connection_id));
+                        self.accept_queue.available.notify_all();
+                    }
+                    continue;
+                },
+            };
+
+            match event {
+                Event::ConnectionEstablished(..) => {},
+                Event::StreamOpened(..) => {
+                    connection.signal(&connection.streams_available, |io| io.streams_available = true);
+                },
+                Event::StreamReadable(_, stream_id) |
+                Event::StreamFinished(_, stream_id) |
+                Event::StreamWritable(_, stream_id) |
+                Event::StreamReset(_, stream_id, _) => {
+                    self.transfer(&connection, Some(stream_id));
+                },
+                Event::PeerMigrated(_, migration) => {
+                    connection.io.lock().unwrap().migrations.push_back(migration);
+                },
+                Event::ConnectionClosed(..) => {
+                    connection.signal(&connection.streams_available, |io| io.closed = true);
+                    connection.finalized.notify_all();
+                    // readers and writers get the error from the engine
+                    self.transfer(&connection, None);
+                },
+            }
+        }
+    }
+
+    /// Move data between the engine and what a connection's streams, or one of them, staged
+    fn transfer(&mut self, connection: &WorkerConnection, stream_id: Option<u32>) {
+        let connection_id = connection.connection_id;
+        let mut readable = false;
+        let mut writable = false;
+        {
+            let mut io = connection.io.lock().unwrap();
+            let engine = &mut self.engine;
+            let read_buffer = &mut self.read_buffer;
+            let mut transfer_stream = |stream_id: u32, stream: &mut StreamIo| {
+                writable |= stream.pass_outgoing(engine, connection_id, stream_id);
+                readable |= stream.stage_incoming(engine, connection_id, stream_id, read_buffer);
+            };
+
+            match stream_id {
+                Some(stream_id) => transfer_stream(stream_id, io.streams.entry(stream_id).or_default()),
+                None => for (&stream_id, stream) in &mut io.streams {
+                    transfer_stream(stream_id, stream);
+                },
             }
+            io.streams.retain(|_, stream| !stream.is_done());
         }
+
+        if readable {
+            connection.data_available.notify_all();
+        }
+        if writable {
+            connection.writable.notify_all();
+        }
+    }
+
+    /// Report what received packets changed
+    fn finish_receiving(&mut self) {
+        self.dispatch_events();
+        self.signal_finalized();
     }
 
     fn signal_finalized(&self) {
-        for connection in self.connection_map.values() {
-            if self.engine.is_finalized(connection.connection_id) {
-                connection.finalized.notify_all();
+        for connection_id in &self.finalizing {
+            if let Some(connection) = self.connections.get(connection_id) {
+                if self.engine.is_finalized(*connection_id) && !connection.has_staged_writes() {
+                    connection.signal(&connection.finalized, |io| io.finalized = true);
+                }
             }
         }
     }
 }
 
 
+/// Runs an engine on a thread of its own for application threads
+///
+/// Application threads read and write stream data under their connection's lock, so the
+/// engine lock is only held for the copies of the worker thread and for short calls.
+///
+/// Locks are taken in this order: the engine state, the handle table, a connection's I/O state.
+/// The accept queue, I/O requests, inbox and path sockets are never held while taking another lock.
 #[derive(Debug)]
 pub struct Worker {
     state: Mutex<WorkerState>,
-    udp_socket: net::UdpSocket,
+    handles: RwLock<HandleTable>,
+    // connections whose streams application threads staged data for or took data from
+    io_requests: Mutex<HashSet<u64>>,
+    accept_queue: Arc<AcceptQueue>,
+    udp_socket: Arc<net::UdpSocket>,
+    // set when a dispatcher receives from `udp_socket` and fills the inbox instead
+    dispatched: bool,
+    // datagrams of this worker's connections, from the dispatcher
+    inbox: Mutex<Inbox>,
+    // sockets bound for additional paths, by local address
+    path_sockets: Mutex<HashMap<SocketAddr, Arc<net::UdpSocket>>>,
+    // the worker thread sends everything, application calls wake it up to do so
+    poller: Poller,
 }
 
 impl Worker {
-    pub fn new<A: ToSocketAddrs>(addr: A, accept_connections: bool) -> Result<Arc<Worker>> {
+    pub fn new<A: ToSocketAddrs>(addr: A, accept_connections: bool, config: Config) -> Result<Arc<Worker>> {
         let udp_socket = net::UdpSocket::bind(addr)?;
+        socket::set_dont_fragment(&udp_socket)?;
         let worker_ref = Arc::new(
-            Worker {
-                state: Mutex::new(WorkerState {
-                    started: false,
-                    engine: QuicEngine::new(ThreadedTimer::new(), accept_connections),
-                    handle_generator: HandleGenerator::new(),
-                    connection_map: HashMap::new(),
-                    connections_available: Arc::new(Condvar::new()),
-                }),
-                udp_socket: udp_socket,
-            }
+            Worker::with_socket(Arc::new(udp_socket), false, 0, Arc::new(AcceptQueue::default()), accept_connections, config)?
         );
         Self::spawn_thread(worker_ref.clone());
         Ok(worker_ref)
     }
 
-    pub fn new_connection(&self, addr: SocketAddr) -> Result<Handle> {
-        let mut state = self.state.lock().unwrap();
+    /// Server workers sharing a socket bound to `addr`, each running the connections of one shard
+    ///
+    /// A dispatcher thread receives from the socket and routes datagrams by connection id,
+    /// so a connection stays on its worker when the peer migrates.
+    pub fn new_sharded<A: ToSocketAddrs>(addr: A, config: Config, worker_count: usize) -> Result<Vec<Arc<Worker>>> {
+        let udp_socket = Arc::new(net::UdpSocket::bind(addr)?);
+        socket::set_dont_fragment(&udp_socket)?;
+        let accept_queue = Arc::new(AcceptQueue::default());
+
+        let mut workers = Vec::with_capacity(worker_count);
+        for shard in 0..worker_count {
+            let worker = Worker::with_socket(udp_socket.clone(), true, shard, accept_queue.clone(), true, config.clone())?;
+            workers.push(Arc::new(worker));
+        }
+        let poller = Poller::new()?;
 
-        let id = state.engine.initiate_connection(addr);
+        for worker_ref in &workers {
+            Self::spawn_thread(worker_ref.clone());
+        }
+        let dispatcher_workers = workers.clone();
+        thread::spawn(move || {
+            Self::run_dispatcher(&udp_socket, &poller, &dispatcher_workers);
+        });
+
+        Ok(workers)
+    }
+
+    fn with_socket(
+        udp_socket: Arc<net::UdpSocket>,
+        dispatched: bool,
+        shard: usize,
+        accept_queue: Arc<AcceptQueue>,
+        accept_connections: bool,
+        config: Config,
+    ) -> Result<Worker> {
+        Ok(Worker {
+            state: Mutex::new(WorkerState {
+                started: false,
+                engine: QuicEngine::with_config(HeapTimer::new(), accept_connections, config),
+                read_buffer: vec![0; UDP_BUF_SIZE],
+                connections: HashMap::new(),
+                finalizing: HashSet::new(),
+                shard,
+                accept_queue: accept_queue.clone(),
+            }),
+            handles: RwLock::new(HandleTable {
+                handle_generator: HandleGenerator::new(),
+                connections: HashMap::new(),
+            }),
+            io_requests: Mutex::new(HashSet::new()),
+            accept_queue,
+            udp_socket,
+            dispatched,
+            inbox: Mutex::new(Inbox::default()),
+            path_sockets: Mutex::new(HashMap::new()),
+            poller: Poller::new()?,
+        })
+    }
+
+    /// The address of the socket shared by all connections
+    pub fn local_address(&self) -> Result<SocketAddr> {
+        Ok(self.udp_socket.local_addr()?)
+    }
+
+    pub fn new_connection(&self, addr: SocketAddr) -> Result<Handle> {
+        let handle = {
+            let mut state = self.state.lock().unwrap();
 
-        let connection = WorkerConnection {
-            connection_id: id,
-            data_available: Arc::new(Condvar::new()),
-            finalized: Arc::new(Condvar::new()),
+            let id = state.engine.initiate_connection(time::Instant::now(), addr);
+            self.add_connection(&mut state, id)
         };
-        let handle = state.handle_generator.generate();
-        state.connection_map.insert(handle, connection);
+
+        self.poller.wake();
 
         Ok(handle)
     }
 
     pub fn read(&self, handle: Handle, stream_id: u32, buf: &mut [u8]) -> Result<usize> {
-        let (connection_id, data_available) = {
-            let state = self.state.lock().unwrap();
+        let connection = self.connection(handle)?;
+
+        let mut io = connection.io.lock().unwrap();
+        loop {
+            let read_size = {
+                let stream = io.streams.entry(stream_id).or_default();
+                if let Some(e) = stream.read_error.take() {
+                    return Err(e);
+                }
+                if stream.incoming.is_empty() && stream.finished {
+                    return Ok(0);
+                }
 
-            let connection = {
-                state.connection_map.get(&handle)
-                .ok_or(Error::InvalidHandle)?
+                if stream.incoming.is_empty() {
+                    None
+                } else {
+                    let was_full = stream.incoming.len() >= STREAM_STAGING_SIZE;
+                    Some((stream.take_incoming(buf), was_full))
+                }
             };
 
-            (connection.connection_id, connection.data_available.clone())
-        };
+            match read_size {
+                Some((read_size, was_full)) => {
+                    // the engine has more once the staged data stopped it from reading
+                    if was_full {
+                        self.request_io(connection.connection_id);
+                    }
+                    return Ok(read_size);
+                },
+                None => {
+                    // the worker reads what the engine has, or finds out why there's nothing
+                    self.request_io(connection.connection_id);
+                    io = connection.data_available.wait(io).unwrap();
+                },
+            }
+        }
+    }
 
-        let (read_size, outgoing_packets) = {
-            let mut state = self.state.lock().unwrap();
+    /// Stage as much as fits for the worker to write, blocking until there's room
+    ///
+    /// Errors of the engine are reported by the next write or flush.
+    pub fn write(&self, handle: Handle, stream_id: u32, buf: &[u8]) -> Result<usize> {
+        let connection = self.connection(handle)?;
 
-            while !state.engine.data_available(connection_id, stream_id) {
-                state = data_available.wait(state).unwrap();
+        let mut io = connection.io.lock().unwrap();
+        let written_size = loop {
+            {
+                let stream = io.streams.entry(stream_id).or_default();
+                if let Some(e) = stream.write_error.take() {
+                    return Err(e);
+                }
+                if stream.finalizing {
+                    return Err(Error::InvalidStream);
+                }
+
+                let room = STREAM_STAGING_SIZE.saturating_sub(stream.outgoing.len());
+                if room > 0 || buf.is_empty() {
+                    let written_size = min(room, buf.len());
+                    stream.outgoing.extend(&buf[..written_size]);
+                    break written_size;
+                }
             }
 
-            let read_size = state.engine.read(connection_id, stream_id, buf);
+            // the worker reports the stream once it passed staged data on
+            io = connection.writable.wait(io).unwrap();
+        };
+
+        if written_size > 0 {
+            self.request_io(connection.connection_id);
+        }
+
+        Ok(written_size)
+    }
 
-            let outgoing_packets = state.engine.pop_pending_packets();
+    /// Block until the peer acknowledged everything written to the stream
+    pub fn flush(&self, handle: Handle, stream_id: u32) -> Result<()> {
+        let connection = self.connection(handle)?;
 
-            state.signal_finalized();
+        let mut io = connection.io.lock().unwrap();
+        {
+            let stream = io.streams.entry(stream_id).or_default();
+            stream.flush_requested = true;
+            stream.flushed = false;
+        }
+        self.request_io(connection.connection_id);
 
-            (read_size, outgoing_packets)
-        };
+        loop {
+            {
+                let stream = io.streams.entry(stream_id).or_default();
+                if let Some(e) = stream.write_error.take() {
+                    return Err(e);
+                }
+                if stream.flushed {
+                    return Ok(());
+                }
+            }
+
+            io = connection.writable.wait(io).unwrap();
+        }
+    }
 
-        self.send_packets(outgoing_packets);
+    pub fn peer_address(&self, handle: Handle) -> Result<SocketAddr> {
+        let connection = self.connection(handle)?;
 
-        read_size
+        let state = self.state.lock().unwrap();
+        state.engine.peer_address(connection.connection_id)
     }
 
-    pub fn write(&self, handle: Handle, stream_id: u32, buf: &[u8]) -> Result<()> {
-        let outgoing_packets = {
-            let mut state = self.state.lock().unwrap();
+    /// Take the next change of the peer's address, if there was one
+    pub fn pop_migration(&self, handle: Handle) -> Result<Option<Migration>> {
+        let connection = self.connection(handle)?;
 
-            let connection_id = {
-                state.connection_map.get(&handle)
-                .ok_or(Error::InvalidHandle)?
-                .connection_id
-            };
+        let migration = connection.io.lock().unwrap().migrations.pop_front();
+        Ok(migration)
+    }
 
-            state.engine.write(connection_id, stream_id, buf)?;
-            state.signal_finalized();
+    /// Open another path to the peer from a socket bound to `local_addr`
+    pub fn add_path<A: ToSocketAddrs>(worker_ref: &Arc<Worker>, handle: Handle, local_addr: A) -> Result<u8> {
+        let connection = worker_ref.connection(handle)?;
+        let udp_socket = Arc::new(net::UdpSocket::bind(local_addr)?);
+        socket::set_dont_fragment(&udp_socket)?;
+        let local_address = udp_socket.local_addr()?;
 
-            state.engine.pop_pending_packets()
+        let path_id = {
+            let mut state = worker_ref.state.lock().unwrap();
+
+            let peer_address = state.engine.peer_address(connection.connection_id)?;
+            worker_ref.path_sockets.lock().unwrap().insert(local_address, udp_socket.clone());
+            state.engine.open_path(connection.connection_id, Some(local_address), peer_address)?
         };
 
-        self.send_packets(outgoing_packets);
+        // the worker polls the new socket from now on
+        worker_ref.poller.wake();
 
-        Ok(())
+        Ok(path_id)
     }
 
-    pub fn accept(&self) -> Result<Handle> {
-        let connections_available = {
-            let state = self.state.lock().unwrap();
+    /// Block until a peer establishes a connection with any of `workers`, which share an accept queue
+    pub fn accept(workers: &[Arc<Worker>]) -> Result<(Arc<Worker>, Handle)> {
+        let (shard, connection_id) = {
+            let accept_queue = &workers[0].accept_queue;
+            let mut connection_ids = accept_queue.connection_ids.lock().unwrap();
+
+            trace!("Checking for new connections: {}", connection_ids.len());
+            loop {
+                match connection_ids.pop_front() {
+                    Some(entry) => break entry,
+                    None => connection_ids = accept_queue.available.wait(connection_ids).unwrap(),
+                }
+            }
+        };
+        trace!("Got a connection on shard {}", shard);
 
-            state.connections_available.clone()
+        let worker_ref = &workers[shard];
+        let handle = {
+            let mut state = worker_ref.state.lock().unwrap();
+            worker_ref.add_connection(&mut state, connection_id)
         };
+        Ok((worker_ref.clone(), handle))
+    }
 
-        {
-            let mut state = self.state.lock().unwrap();
+    pub fn open_stream(&self, handle: Handle) -> Result<u32> {
+        let connection = self.connection(handle)?;
 
-            trace!("Checking for new connections: {}", state.engine.have_connections());
-            while !state.engine.have_connections() {
-                state = connections_available.wait(state).unwrap();
-            }
-            trace!("Got a connection");
-            let connection_id = state.engine.pop_new_connection();
+        let mut state = self.state.lock().unwrap();
+        state.engine.open_stream(connection.connection_id)
+    }
 
-            let connection = WorkerConnection {
-                connection_id: connection_id,
-                data_available: Arc::new(Condvar::new()),
-                finalized: Arc::new(Condvar::new()),
-            };
-            let handle = state.handle_generator.generate();
-            state.connection_map.insert(handle, connection);
+    /// Block until the peer opens a stream
+    pub fn accept_stream(&self, handle: Handle) -> Result<u32> {
+        let connection = self.connection(handle)?;
 
-            Ok(handle)
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+
+                if let Some(stream_id) = state.engine.accept_stream(connection.connection_id)? {
+                    return Ok(stream_id);
+                }
+                connection.clear(|io| io.streams_available = false);
+            }
+
+            connection.wait(&connection.streams_available, |io| io.streams_available || io.closed);
         }
     }
 
     pub fn finalize_connection(&self, handle: Handle) -> Result<()> {
-        let mut state = self.state.lock().unwrap();
+        let connection = self.connection(handle)?;
 
-        let (connection_id, finalized) = {
-            let connection =
-                state.connection_map.get(&handle)
-                .ok_or(Error::InvalidHandle)?;
+        debug!("Waiting to finalize connection...");
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
 
-            (connection.connection_id, connection.finalized.clone())
-        };
+                state.finalizing.insert(connection.connection_id);
+                if state.engine.is_finalized(connection.connection_id) && !connection.has_staged_writes() {
+                    break;
+                }
+                connection.clear(|io| io.finalized = false);
+            }
 
-        debug!("Waiting to finalize connection...");
-        while !state.engine.is_finalized(connection_id) {
-            state = finalized.wait(state).unwrap();
+            connection.wait(&connection.finalized, |io| io.finalized || io.closed);
         }
 
+        {
+            let mut state = self.state.lock().unwrap();
+
+            // the engine reaps the connection after draining, it may be gone already
+            if let Err(e) = state.engine.close_connection(connection.connection_id, codes::QUIC_NO_ERROR) {
+                debug!("Not closing connection {}: {}", connection.connection_id, e);
+            }
+            self.remove_connection(&mut state, handle);
+        }
+
+        self.poller.wake();
+
         Ok(())
     }
 
+    /// Have the worker finalize the stream once it passed on everything written to it
     pub fn finalize_outgoing_stream(&self, handle: Handle, stream_id: u32) -> Result<()> {
-        let outgoing_packets = {
-            let mut state = self.state.lock().unwrap();
+        let connection = self.connection(handle)?;
 
-            let connection_id = {
-                state.connection_map.get(&handle)
-                .ok_or(Error::InvalidHandle)?
-                .connection_id
-            };
+        connection.io.lock().unwrap()
+            .streams.entry(stream_id).or_default()
+            .finalizing = true;
+        self.request_io(connection.connection_id);
 
-            state.engine.finalize_outgoing_stream(connection_id, stream_id)?;
+        Ok(())
+    }
 
-            state.engine.pop_pending_packets()
-        };
+    /// Have the worker move data between the engine and a connection's streams
+    fn request_io(&self, connection_id: u64) {
+        self.io_requests.lock().unwrap().insert(connection_id);
+        self.poller.wake();
+    }
+
+    fn connection(&self, handle: Handle) -> Result<Arc<WorkerConnection>> {
+        self.handles.read().unwrap()
+            .connections.get(&handle)
+            .cloned()
+            .ok_or(Error::InvalidHandle)
+    }
 
-        self.send_packets(outgoing_packets);
+    fn add_connection(&self, state: &mut WorkerState, connection_id: u64) -> Handle {
+        let connection = Arc::new(WorkerConnection::new(connection_id));
+        state.connections.insert(connection_id, connection.clone());
 
-        Ok(())
+        let mut handles = self.handles.write().unwrap();
+        let handle = handles.handle_generator.generate();
+        handles.connections.insert(handle, connection);
+
+        handle
+    }
+
+    fn remove_connection(&self, state: &mut WorkerState, handle: Handle) {
+        if let Some(connection) = self.handles.write().unwrap().connections.remove(&handle) {
+            state.finalizing.remove(&connection.connection_id);
+            state.connections.remove(&connection.connection_id);
+        }
     }
 
-    fn send_packets(&self, outgoing_packets: Vec<OutgoingUdpPacket>) {
-        for packet in outgoing_packets {
-            debug!("Sending UDP packet (size: {})", packet.payload.len());
-            if let Err(ref e) = self.udp_socket.send_to(&packet.payload[..], packet.destination_address) {
-                error!("UDP send error: {:?}", e);
+    /// Send packets popped from the engine, only the worker thread does this
+    fn send_packets(&self, outgoing_packets: &[OutgoingUdpPacket]) {
+        let path_sockets = self.path_sockets.lock().unwrap();
+        let packet_socket = |packet: &OutgoingUdpPacket| {
+            match packet.source_address {
+                Some(ref source_address) => match path_sockets.get(source_address) {
+                    Some(udp_socket) => &**udp_socket,
+                    None => &*self.udp_socket,
+                },
+                None => &*self.udp_socket,
+            }
+        };
+
+        // consecutive packets from the same socket go out together
+        let mut start = 0;
+        while start < outgoing_packets.len() {
+            let udp_socket = packet_socket(&outgoing_packets[start]);
+            let end =
+                outgoing_packets[start..].iter()
+                .position(|packet| !ptr::eq(packet_socket(packet), udp_socket))
+                .map_or(outgoing_packets.len(), |count| start + count);
+
+            debug!("Sending {} UDP packets", end - start);
+            while start < end {
+                match socket::send_batch(udp_socket, &outgoing_packets[start..end]) {
+                    Err(ref e) if socket::is_message_too_long(e) => {
+                        debug!("UDP packet too large for the local MTU (size: {})", outgoing_packets[start].payload.len());
+                        start += 1;
+                    },
+                    Err(ref e) => {
+                        error!("UDP send error: {:?}", e);
+                        start += 1;
+                    },
+                    Ok(count) => start += count,
+                }
             }
         }
     }
@@ -256,69 +786,202 @@ impl Worker {
     fn run(worker_ref: Arc<Worker>) {
         debug!("Running QUIC worker");
 
-        let udp_socket = &worker_ref.udp_socket;
-        const UDP_BUF_SIZE: usize = 65535;
-        let mut incoming_udp_buf = [0; UDP_BUF_SIZE];
+        let mut recv_batch = socket::RecvBatch::new(UDP_BUF_SIZE);
+        let mut sent_packets: Vec<OutgoingUdpPacket> = Vec::new();
+        let mut inbox = Vec::new();
+        let mut io_requests = HashSet::new();
+        let mut spare_buffers = Vec::new();
+        let mut readable = Vec::new();
 
         loop {
+            // take back the buffers of what was sent
             // process scheduled events
             // get time until the next one
             // and get packets to send
             let (timeout, outgoing_packets) = {
                 let mut state = worker_ref.state.lock().unwrap();
 
+                for packet in sent_packets.drain(..) {
+                    state.engine.recycle_buffer(packet.payload);
+                }
+
+                state.handle_timeout();
                 let mut timeout = state.get_event_timeout();
-                while timeout == time::Duration::from_secs(0) {
+                while timeout == Some(time::Duration::from_secs(0)) {
                     debug!("Processing due QUIC events");
-                    state.engine.handle_due_events();
+                    state.handle_timeout();
                     timeout = state.get_event_timeout();
                 }
 
+                // serve what application threads staged or took
+                mem::swap(&mut io_requests, &mut worker_ref.io_requests.lock().unwrap());
+                for connection_id in io_requests.drain() {
+                    if let Some(connection) = state.connections.get(&connection_id).cloned() {
+                        state.transfer(&connection, None);
+                    }
+                }
+
+                state.dispatch_events();
                 state.signal_finalized();
 
                 (timeout, state.engine.pop_pending_packets())
             };
 
             // send pending packets
-            worker_ref.send_packets(outgoing_packets);
-
-            // receive a packet with a timeout
-            trace!("Waiting to receive a UDP packet with timeout: {:?}", timeout);
-            udp_socket.set_read_timeout(Some(timeout)).unwrap();
-            let (packet_size, source_address) = match udp_socket.recv_from(&mut incoming_udp_buf) {
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    trace!("UDP receive timed out");
+            worker_ref.send_packets(&outgoing_packets);
+            sent_packets = outgoing_packets;
+
+            // wait for packets or a wakeup
+            let path_sockets: Vec<(SocketAddr, Arc<net::UdpSocket>)> =
+                worker_ref.path_sockets.lock().unwrap().iter()
+                .map(|(local_address, udp_socket)| (*local_address, udp_socket.clone()))
+                .collect();
+            let mut sockets = Vec::with_capacity(path_sockets.len() + 1);
+            let mut local_addresses = Vec::with_capacity(path_sockets.len() + 1);
+            if !worker_ref.dispatched {
+                sockets.push(worker_ref.udp_socket.as_ref());
+                local_addresses.push(None);
+            }
+            for (local_address, udp_socket) in &path_sockets {
+                sockets.push(udp_socket.as_ref());
+                local_addresses.push(Some(*local_address));
+            }
+
+            trace!("Waiting for UDP packets with timeout: {:?}", timeout);
+            if let Err(e) = worker_ref.poller.wait(&sockets, &mut readable, timeout) {
+                error!("Poll error: {:?}", e);
+                continue;
+            }
+
+            // take what the dispatcher passed on
+            if worker_ref.dispatched {
+                mem::swap(&mut inbox, &mut worker_ref.inbox.lock().unwrap().packets);
+                if !inbox.is_empty() {
+                    debug!("Received {} UDP packets from the dispatcher", inbox.len());
+                    worker_ref.handle_packets(&mut inbox, &mut spare_buffers);
+                    worker_ref.inbox.lock().unwrap().spare_buffers.append(&mut spare_buffers);
+                }
+            }
+
+            // receive what arrived
+            for (index, udp_socket) in sockets.iter().enumerate() {
+                if !readable[index] {
                     continue;
-                },
+                }
+
+                let local_address = local_addresses[index];
+                match socket::recv_batch(udp_socket, &mut recv_batch) {
+                    Ok(0) => continue,
+                    Err(ref e) => {
+                        error!("UDP recv error: {:?}, kind: {:?}", e, e.kind());
+                        continue;
+                    },
+                    Ok(count) => debug!("Received {} UDP packets", count),
+                }
+
+                worker_ref.handle_datagrams(local_address, &recv_batch);
+            }
+        }
+    }
+
+    fn handle_datagrams(&self, destination_address: Option<SocketAddr>, recv_batch: &socket::RecvBatch) {
+        let mut state = self.state.lock().unwrap();
+
+        for index in 0..recv_batch.len() {
+            let (source_address, data) = recv_batch.get(index);
+            if data.len() >= recv_batch.buffer_size() {
+                error!("Dropping a jumbogram packet: not supported");
+                continue;
+            }
+
+            // the engine takes the buffer back once it's done with the packet
+            let mut payload = state.engine.take_buffer();
+            payload.clear();
+            payload.extend_from_slice(data);
+            state.handle_incoming_packet(IncomingUdpPacket {
+                source_address,
+                destination_address,
+                payload,
+            });
+        }
+
+        state.finish_receiving();
+    }
+
+    /// Handle datagrams from the dispatcher, and take as many buffers for it from the engine
+    fn handle_packets(&self, packets: &mut Vec<IncomingUdpPacket>, spare_buffers: &mut Vec<Vec<u8>>) {
+        let mut state = self.state.lock().unwrap();
+
+        for packet in packets.drain(..) {
+            // the engine takes the payload back once it's done with the packet
+            state.handle_incoming_packet(packet);
+            spare_buffers.push(state.engine.take_buffer());
+        }
+
+        state.finish_receiving();
+    }
+
+    /// Receive from the shared socket of sharded workers, and pass datagrams on by connection id
+    fn run_dispatcher(udp_socket: &net::UdpSocket, poller: &Poller, workers: &[Arc<Worker>]) {
+        debug!("Running QUIC dispatcher for {} workers", workers.len());
+
+        let mut recv_batch = socket::RecvBatch::new(UDP_BUF_SIZE);
+        let mut readable = Vec::new();
+        let mut woken = vec![false; workers.len()];
+
+        loop {
+            if let Err(e) = poller.wait(&[udp_socket], &mut readable, None) {
+                error!("Poll error: {:?}", e);
+                continue;
+            }
+            if !readable[0] {
+                continue;
+            }
+
+            match socket::recv_batch(udp_socket, &mut recv_batch) {
+                Ok(0) => continue,
                 Err(ref e) => {
                     error!("UDP recv error: {:?}, kind: {:?}", e, e.kind());
                     continue;
                 },
-                Ok(result) => result,
-            };
-            if packet_size >= UDP_BUF_SIZE {
-                error!("Dropping a jumbogram packet: not supported");
-                continue;
+                Ok(count) => debug!("Dispatching {} UDP packets", count),
             }
 
-            debug!("Received UDP packet (size: {})", packet_size);
-            let packet_data = &incoming_udp_buf[..packet_size];
-            let packet = IncomingUdpPacket {
-                source_address: source_address,
-                payload: Vec::from(packet_data),
-            };
+            for index in 0..recv_batch.len() {
+                let (source_address, data) = recv_batch.get(index);
+                if data.len() >= recv_batch.buffer_size() {
+                    error!("Dropping a jumbogram packet: not supported");
+                    continue;
+                }
 
-            {
-                let mut state = worker_ref.state.lock().unwrap();
-                state.handle_incoming_packet(packet);
+                // clients always send the connection id to servers
+                let connection_id = match packets::peek_connection_id(data) {
+                    Some(connection_id) => connection_id,
+                    None => {
+                        debug!("Dropping a UDP packet from {} without a connection id", source_address);
+                        continue;
+                    },
+                };
+
+                let shard = (connection_id % workers.len() as u64) as usize;
+                let mut inbox = workers[shard].inbox.lock().unwrap();
+                let mut payload = inbox.spare_buffers.pop().unwrap_or_default();
+                payload.clear();
+                payload.extend_from_slice(data);
+                inbox.packets.push(IncomingUdpPacket {
+                    source_address,
+                    destination_address: None,
+                    payload,
+                });
+                woken[shard] = true;
+            }
 
-                trace!("Signaling connections_available: {}", state.engine.have_connections());
-                if state.engine.have_connections() {
-                    state.connections_available.notify_all();
+            // one wakeup per worker and batch
+            for (shard, woken) in woken.iter_mut().enumerate() {
+                if *woken {
+                    workers[shard].poller.wake();
+                    *woken = false;
                 }
-
-                state.signal_data_available();
-                state.signal_finalized();
             }
         }
     }